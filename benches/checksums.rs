@@ -0,0 +1,40 @@
+// Throughput of the checksums over buffers of various sizes. Run with
+// `cargo bench --bench checksums`.
+
+use diy_zlib::adler32::adler32;
+use diy_zlib::crc32::crc32;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+// Each measurement processes about this much data in total.
+const BYTES_PER_MEASUREMENT: usize = 1 << 30;
+
+fn throughput(data: &[u8], checksum: fn(&[u8]) -> u32) -> f64 {
+    let iterations = (BYTES_PER_MEASUREMENT / data.len()).max(1);
+    let start = Instant::now();
+    for _ in 0..iterations {
+        black_box(checksum(black_box(data)));
+    }
+    let elapsed = start.elapsed().max(Duration::from_nanos(1));
+    (iterations * data.len()) as f64 / elapsed.as_secs_f64() / 1e9
+}
+
+fn main() {
+    let mut state: u32 = 1;
+    let data: Vec<u8> = (0..16 << 20)
+        .map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect();
+    println!("{:>10} {:>12} {:>12}", "size", "crc32", "adler32");
+    for size in [16, 64, 256, 4 << 10, 64 << 10, 1 << 20, 16 << 20] {
+        let data = &data[..size];
+        println!(
+            "{:>10} {:>9.2} GB/s {:>7.2} GB/s",
+            size,
+            throughput(data, crc32),
+            throughput(data, adler32)
+        );
+    }
+}
@@ -0,0 +1,332 @@
+// Adler-32 checksum, as used in the zlib stream trailer.
+// https://datatracker.ietf.org/doc/html/rfc1950#section-8
+//
+// The sums are only reduced modulo 65521 every NMAX bytes, the most that can
+// be added before `b` could overflow 32 bits. On x86_64, blocks of 16 or 32
+// bytes are summed with SSE2 or AVX2 when the CPU supports them.
+
+const MODULUS: u32 = 65521;
+
+// Largest n such that 255 n (n + 1) / 2 + (n + 1) (MODULUS - 1) < 2^32.
+const NMAX: usize = 5552;
+
+// Inputs shorter than this are summed with the scalar code, as setting up
+// the vector sums wouldn't pay off.
+#[cfg(target_arch = "x86_64")]
+const SIMD_MIN_LENGTH: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Adler32 {
+    pub fn new() -> Self {
+        Self { a: 1, b: 0 }
+    }
+
+    // Resume from the checksum of some earlier data.
+    pub fn from_checksum(checksum: u32) -> Self {
+        Self {
+            a: checksum & 0xffff,
+            b: checksum >> 16,
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        #[cfg(target_arch = "x86_64")]
+        if bytes.len() >= SIMD_MIN_LENGTH {
+            if is_x86_feature_detected!("avx2") {
+                // SAFETY: the CPU supports AVX2.
+                return unsafe { x86::update_avx2(self, bytes) };
+            }
+            if is_x86_feature_detected!("sse2") {
+                // SAFETY: the CPU supports SSE2.
+                return unsafe { x86::update_sse2(self, bytes) };
+            }
+        }
+        self.update_scalar(bytes);
+    }
+
+    fn update_scalar(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(NMAX) {
+            for &byte in chunk {
+                self.a += byte as u32;
+                self.b += self.a;
+            }
+            self.a %= MODULUS;
+            self.b %= MODULUS;
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+
+    // Checksum of the concatenation of the data checksummed by `self` and by
+    // `other`, where `other` covers `other_length` bytes.
+    pub fn combine(&self, other: &Adler32, other_length: usize) -> Adler32 {
+        let modulus = MODULUS as u64;
+        let length = other_length as u64 % modulus;
+        let (a1, b1) = (self.a as u64, self.b as u64);
+        let (a2, b2) = (other.a as u64, other.b as u64);
+        // The second part's running sums start from a1 instead of 1.
+        Adler32 {
+            a: ((a1 + a2 + modulus - 1) % modulus) as u32,
+            b: ((b1 + b2 + length * ((a1 + modulus - 1) % modulus)) % modulus) as u32,
+        }
+    }
+}
+
+impl Default for Adler32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Adler-32 of `bytes`.
+pub fn adler32(bytes: &[u8]) -> u32 {
+    let mut checksum = Adler32::new();
+    checksum.update(bytes);
+    checksum.finish()
+}
+
+// Adler-32 of the concatenation of two pieces of data, given the checksum of
+// each and the length of the second, like zlib's adler32_combine().
+pub fn adler32_combine(first: u32, second: u32, second_length: usize) -> u32 {
+    Adler32::from_checksum(first)
+        .combine(&Adler32::from_checksum(second), second_length)
+        .finish()
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use super::{Adler32, MODULUS, NMAX};
+    use std::arch::x86_64::*;
+
+    // Add the sums for `blocks` to the checksum, given the sum of the bytes
+    // (`byte_sum`), of the byte sums before each block (`prefix_sum`) and of
+    // each byte weighted by its distance from the end of its block
+    // (`weighted_sum`).
+    fn add_block_sums(
+        checksum: &mut Adler32,
+        block_size: usize,
+        blocks: usize,
+        byte_sum: u64,
+        prefix_sum: u64,
+        weighted_sum: u64,
+    ) {
+        let modulus = MODULUS as u64;
+        let a = checksum.a as u64;
+        let b = checksum.b as u64
+            + (blocks * block_size) as u64 * a
+            + block_size as u64 * prefix_sum
+            + weighted_sum;
+        checksum.a = ((a + byte_sum) % modulus) as u32;
+        checksum.b = (b % modulus) as u32;
+    }
+
+    #[target_feature(enable = "avx2")]
+    fn sum_u32x8(v: __m256i) -> u64 {
+        let mut lanes = [0u32; 8];
+        // SAFETY: `lanes` has room for 32 bytes.
+        unsafe { _mm256_storeu_si256(lanes.as_mut_ptr().cast(), v) };
+        lanes.iter().map(|&lane| lane as u64).sum()
+    }
+
+    #[target_feature(enable = "sse2")]
+    fn sum_u32x4(v: __m128i) -> u64 {
+        let mut lanes = [0u32; 4];
+        // SAFETY: `lanes` has room for 16 bytes.
+        unsafe { _mm_storeu_si128(lanes.as_mut_ptr().cast(), v) };
+        lanes.iter().map(|&lane| lane as u64).sum()
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub fn update_avx2(checksum: &mut Adler32, bytes: &[u8]) {
+        const BLOCK: usize = 32;
+        let weights = _mm256_setr_epi8(
+            32, 31, 30, 29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 19, 18, 17, 16, 15, 14, 13, 12, 11,
+            10, 9, 8, 7, 6, 5, 4, 3, 2, 1,
+        );
+        let ones = _mm256_set1_epi16(1);
+        let zero = _mm256_setzero_si256();
+        let mut chunks = bytes.chunks_exact(NMAX / BLOCK * BLOCK);
+        let mut process = |chunk: &[u8]| {
+            let blocks = chunk.chunks_exact(BLOCK);
+            let tail = blocks.remainder();
+            let count = blocks.len();
+            let mut byte_sums = zero;
+            let mut prefix_sums = zero;
+            let mut weighted_sums = zero;
+            for block in blocks {
+                // SAFETY: `block` is 32 bytes long.
+                let v = unsafe { _mm256_loadu_si256(block.as_ptr().cast()) };
+                prefix_sums = _mm256_add_epi32(prefix_sums, byte_sums);
+                byte_sums = _mm256_add_epi32(byte_sums, _mm256_sad_epu8(v, zero));
+                let products = _mm256_maddubs_epi16(v, weights);
+                weighted_sums = _mm256_add_epi32(weighted_sums, _mm256_madd_epi16(products, ones));
+            }
+            add_block_sums(
+                checksum,
+                BLOCK,
+                count,
+                sum_u32x8(byte_sums),
+                sum_u32x8(prefix_sums),
+                sum_u32x8(weighted_sums),
+            );
+            checksum.update_scalar(tail);
+        };
+        for chunk in chunks.by_ref() {
+            process(chunk);
+        }
+        process(chunks.remainder());
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub fn update_sse2(checksum: &mut Adler32, bytes: &[u8]) {
+        const BLOCK: usize = 16;
+        let high_weights = _mm_setr_epi16(16, 15, 14, 13, 12, 11, 10, 9);
+        let low_weights = _mm_setr_epi16(8, 7, 6, 5, 4, 3, 2, 1);
+        let zero = _mm_setzero_si128();
+        let mut chunks = bytes.chunks_exact(NMAX / BLOCK * BLOCK);
+        let mut process = |chunk: &[u8]| {
+            let blocks = chunk.chunks_exact(BLOCK);
+            let tail = blocks.remainder();
+            let count = blocks.len();
+            let mut byte_sums = zero;
+            let mut prefix_sums = zero;
+            let mut weighted_sums = zero;
+            for block in blocks {
+                // SAFETY: `block` is 16 bytes long.
+                let v = unsafe { _mm_loadu_si128(block.as_ptr().cast()) };
+                prefix_sums = _mm_add_epi32(prefix_sums, byte_sums);
+                byte_sums = _mm_add_epi32(byte_sums, _mm_sad_epu8(v, zero));
+                let first = _mm_madd_epi16(_mm_unpacklo_epi8(v, zero), high_weights);
+                let second = _mm_madd_epi16(_mm_unpackhi_epi8(v, zero), low_weights);
+                weighted_sums = _mm_add_epi32(weighted_sums, _mm_add_epi32(first, second));
+            }
+            add_block_sums(
+                checksum,
+                BLOCK,
+                count,
+                sum_u32x4(byte_sums),
+                sum_u32x4(prefix_sums),
+                sum_u32x4(weighted_sums),
+            );
+            checksum.update_scalar(tail);
+        };
+        for chunk in chunks.by_ref() {
+            process(chunk);
+        }
+        process(chunks.remainder());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Bytes that exercise every value, with runs of the maximum to provoke
+    // overflows.
+    fn test_data(length: usize) -> Vec<u8> {
+        let mut state: u32 = 1;
+        (0..length)
+            .map(|i| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                if (i / 1000).is_multiple_of(3) {
+                    0xff
+                } else {
+                    (state >> 16) as u8
+                }
+            })
+            .collect()
+    }
+
+    // The definition, one modulo per byte.
+    fn reference(bytes: &[u8]) -> u32 {
+        let (mut a, mut b) = (1, 0);
+        for &byte in bytes {
+            a = (a + byte as u32) % MODULUS;
+            b = (b + a) % MODULUS;
+        }
+        (b << 16) | a
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(adler32(b""), 1);
+    }
+
+    #[test]
+    fn test_known_value() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+    }
+
+    #[test]
+    fn test_incremental() {
+        let mut checksum = Adler32::new();
+        checksum.update(b"Wiki");
+        checksum.update(b"pedia");
+        assert_eq!(checksum.finish(), adler32(b"Wikipedia"));
+    }
+
+    #[test]
+    fn test_matches_reference() {
+        let data = test_data(3 * NMAX + 100);
+        for length in (0..200).chain([NMAX - 1, NMAX, NMAX + 1, 2 * NMAX + 37, data.len()]) {
+            for offset in [0, 1, 7] {
+                let bytes = &data[offset..offset + length.min(data.len() - offset)];
+                assert_eq!(adler32(bytes), reference(bytes), "length {}", length);
+            }
+        }
+    }
+
+    #[test]
+    fn test_all_ones() {
+        // Maximal sums between reductions.
+        let data = vec![0xff; 100_000];
+        assert_eq!(adler32(&data), reference(&data));
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_simd_paths() {
+        let data = test_data(50_000);
+        for length in [64, 65, 100, 1000, NMAX + 33, data.len()] {
+            let bytes = &data[..length];
+            let mut scalar = Adler32::from_checksum(0x1234_5678 % MODULUS);
+            let mut simd = scalar;
+            scalar.update_scalar(bytes);
+            if is_x86_feature_detected!("sse2") {
+                // SAFETY: the CPU supports SSE2.
+                unsafe { x86::update_sse2(&mut simd, bytes) };
+                assert_eq!(simd, scalar);
+            }
+            if is_x86_feature_detected!("avx2") {
+                let mut simd = Adler32::from_checksum(0x1234_5678 % MODULUS);
+                // SAFETY: the CPU supports AVX2.
+                unsafe { x86::update_avx2(&mut simd, bytes) };
+                assert_eq!(simd, scalar);
+            }
+        }
+    }
+
+    #[test]
+    fn test_combine() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i * 31 % 256) as u8).collect();
+        for split in [0, 1, 5552, 65521, 100_000, 200_000] {
+            let (first, second) = data.split_at(split);
+            let mut a = Adler32::new();
+            a.update(first);
+            let mut b = Adler32::new();
+            b.update(second);
+            assert_eq!(a.combine(&b, second.len()).finish(), adler32(&data));
+            assert_eq!(
+                adler32_combine(adler32(first), adler32(second), second.len()),
+                adler32(&data)
+            );
+        }
+    }
+}
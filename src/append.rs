@@ -0,0 +1,341 @@
+// Appending data to an existing zlib stream or gzip file in place, like
+// zlib's gzappend example. The file is decoded in one pass to find the last
+// stream's final block and rebuild its window. The final block's BFINAL bit is cleared, and the new
+// data is compressed with the window as dictionary, starting in the final
+// block's last byte. The trailer is rewritten with the updated checksum and
+// length, and a zlib header is made to declare a 32 KiB window.
+
+use crate::adler32::Adler32;
+use crate::bit_reader::BitReader;
+use crate::block_decoder::Variant;
+use crate::crc32::Crc32;
+use crate::deflate::{Compression, Deflater, Flush};
+use crate::error::{InflateError, InflateResult};
+use crate::format::Format;
+use crate::gzip::GzHeader;
+use crate::header::StreamHeader;
+use crate::inflate::RawInflator;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+
+// Checksum of the data in a stream, continued from its trailer.
+enum Checksum {
+    Raw,
+    Zlib(Adler32),
+    Gzip(Crc32),
+}
+
+impl Checksum {
+    fn new(format: Format) -> Self {
+        match format {
+            Format::Raw => Checksum::Raw,
+            Format::Zlib => Checksum::Zlib(Adler32::new()),
+            Format::Gzip => Checksum::Gzip(Crc32::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Checksum::Raw => {}
+            Checksum::Zlib(adler32) => adler32.update(data),
+            Checksum::Gzip(crc32) => crc32.update(data),
+        }
+    }
+
+    fn finish(&self) -> u32 {
+        match self {
+            Checksum::Raw => 0,
+            Checksum::Zlib(adler32) => adler32.finish(),
+            Checksum::Gzip(crc32) => crc32.finish(),
+        }
+    }
+}
+
+// Append `data` to the compressed stream in `file`, compressing it at
+// `compression`. For gzip files with several members, the last member is
+// extended. The stream must end at the end of the file, and is verified
+// before anything is written. The file is read once, a buffer at a time, and
+// only grows, so it never needs to be truncated.
+//
+// Streams with a preset dictionary fail with DictionaryRequired. If writing
+// fails partway, the stream is left corrupted.
+pub fn append_to_stream<F: Read + Write + Seek>(
+    file: &mut F,
+    format: Format,
+    data: &[u8],
+    compression: Compression,
+) -> InflateResult<()> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut input = BitReader::new(BufReader::new(&mut *file));
+    let mut zlib_header = None;
+    // Decode each stream to verify its trailer, keeping the last one's
+    // checksum, final block position and window. Bit positions are relative
+    // to the start of the file.
+    let (mut checksum, mut length, final_block, end, window) = loop {
+        match format {
+            Format::Raw => {}
+            Format::Zlib => {
+                let mut bytes = [0u8; 2];
+                input.read_exact(&mut bytes)?;
+                let header = StreamHeader::try_from(&bytes)?;
+                if header.flags.preset_dictionary {
+                    let mut id = [0u8; 4];
+                    input.read_exact(&mut id)?;
+                    return Err(InflateError::DictionaryRequired(u32::from_be_bytes(id)));
+                }
+                zlib_header = Some((bytes, header));
+            }
+            Format::Gzip => {
+                GzHeader::read_from(&mut input)?;
+            }
+        }
+        let mut inflator = RawInflator::from_bit_reader(input, Variant::Deflate);
+        let mut checksum = Checksum::new(format);
+        let mut length = 0u64;
+        while !inflator.is_finished() {
+            let block = inflator.next_block()?;
+            checksum.update(&block);
+            length += block.len() as u64;
+        }
+        let end = inflator.bit_position();
+        verify_trailer(inflator.input_mut(), format, &checksum, length)?;
+        if inflator.input_mut().get_mut().fill_buf()?.is_empty() {
+            break (
+                checksum,
+                length,
+                inflator.final_block_position().unwrap(),
+                end,
+                inflator.window(),
+            );
+        }
+        if format != Format::Gzip {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Data after the end of the stream",
+            )
+            .into());
+        }
+        input = inflator.into_input();
+    };
+
+    // The new data may reference the full 32 KiB window, so a zlib header
+    // declaring a smaller one is widened.
+    if let Some((bytes, header)) = zlib_header {
+        let widened = <[u8; 2]>::from(&StreamHeader::new(header.flags));
+        if widened != bytes {
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&widened)?;
+        }
+    }
+
+    // Clear BFINAL.
+    let mut byte = [0u8];
+    file.seek(SeekFrom::Start(final_block / 8))?;
+    file.read_exact(&mut byte)?;
+    byte[0] &= !(1 << (final_block % 8));
+    file.seek(SeekFrom::Start(final_block / 8))?;
+    file.write_all(&byte)?;
+
+    // Continue the stream from its last partial byte, which may be the one
+    // just rewritten.
+    let mut deflater = Deflater::new(compression);
+    deflater.set_dictionary(&window);
+    let trailing_bits = (end % 8) as u8;
+    if trailing_bits != 0 {
+        file.seek(SeekFrom::Start(end / 8))?;
+        file.read_exact(&mut byte)?;
+        deflater.prime((byte[0] & ((1 << trailing_bits) - 1)) as u32, trailing_bits)?;
+    }
+    deflater.write(data)?;
+    deflater.flush(Flush::Finish)?;
+    checksum.update(data);
+    length += data.len() as u64;
+
+    file.seek(SeekFrom::Start(end / 8))?;
+    file.write_all(&deflater.take_output())?;
+    match format {
+        Format::Raw => {}
+        Format::Zlib => file.write_all(&checksum.finish().to_be_bytes())?,
+        Format::Gzip => {
+            file.write_all(&checksum.finish().to_le_bytes())?;
+            file.write_all(&(length as u32).to_le_bytes())?;
+        }
+    }
+    file.flush()?;
+    Ok(())
+}
+
+// Read the trailer after a stream's final block and check it against the
+// decoded data.
+fn verify_trailer<R: Read>(
+    input: &mut BitReader<R>,
+    format: Format,
+    checksum: &Checksum,
+    length: u64,
+) -> InflateResult<()> {
+    let mut trailer = vec![0u8; format.trailer_size()];
+    input.read_exact(&mut trailer)?;
+    let expected = match format {
+        Format::Raw => return Ok(()),
+        Format::Zlib => u32::from_be_bytes(trailer[..4].try_into().unwrap()),
+        Format::Gzip => {
+            let size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+            if size != length as u32 {
+                return Err(InflateError::SizeMismatch(size, length as u32));
+            }
+            u32::from_le_bytes(trailer[..4].try_into().unwrap())
+        }
+    };
+    if expected != checksum.finish() {
+        return Err(InflateError::ChecksumMismatch(expected, checksum.finish()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::CompressionInfo;
+    use crate::{
+        Inflator, ZlibEncoder, compress, decompress, gzip_compress, gzip_decompress, raw_compress,
+        raw_decompress,
+    };
+    use std::assert_matches;
+    use std::io::Cursor;
+
+    fn record(index: usize) -> Vec<u8> {
+        format!("{{\"event\": \"login\", \"user\": {}}}\n", index % 17).into_bytes()
+    }
+
+    #[test]
+    fn test_append_gzip() -> InflateResult<()> {
+        let mut expected = record(0);
+        let mut file = Cursor::new(gzip_compress(&expected, Compression::default()));
+        for index in 1..50 {
+            let data = record(index);
+            append_to_stream(&mut file, Format::Gzip, &data, Compression::default())?;
+            expected.extend(data);
+            assert_eq!(gzip_decompress(file.get_ref())?, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_zlib_and_raw() -> InflateResult<()> {
+        let start = b"first part of the log\n".repeat(3000);
+        let mut zlib = Cursor::new(compress(&start, Compression::best()));
+        let mut raw = Cursor::new(raw_compress(&start, Compression::none()));
+        append_to_stream(&mut zlib, Format::Zlib, b"more", Compression::fast())?;
+        append_to_stream(&mut raw, Format::Raw, b"more", Compression::fast())?;
+        append_to_stream(&mut raw, Format::Raw, b"", Compression::fast())?;
+        let expected = [start.as_slice(), b"more"].concat();
+        assert_eq!(decompress(zlib.get_ref())?, expected);
+        assert_eq!(raw_decompress(raw.get_ref())?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_to_small_window() -> InflateResult<()> {
+        // A stream declaring a 512-byte window, extended with data that
+        // repeats at a distance of 1000.
+        let mut compressed = compress(b"small window", Compression::default());
+        let header = StreamHeader::try_from(&[compressed[0], compressed[1]])?;
+        let small_window = StreamHeader {
+            info: CompressionInfo::try_from(1)?,
+            ..header
+        };
+        compressed[..2].copy_from_slice(&<[u8; 2]>::from(&small_window));
+        let length = compressed.len();
+        let mut file = Cursor::new(compressed);
+        let noise: Vec<u8> = (0..1000u32).map(|i| (i * i % 251) as u8).collect();
+        append_to_stream(
+            &mut file,
+            Format::Zlib,
+            &noise.repeat(2),
+            Compression::default(),
+        )?;
+
+        let appended = file.into_inner();
+        assert!(appended.len() > length);
+        let header = StreamHeader::try_from(&[appended[0], appended[1]])?;
+        assert_eq!(header.info.window_size(), 32768);
+        assert_eq!(header.flags, small_window.flags);
+        let mut inflator = Inflator::try_new_strict(appended.as_slice())?;
+        let mut out = Vec::new();
+        inflator.read_to_end(&mut out)?;
+        assert_eq!(out, [&b"small window"[..], &noise, &noise].concat());
+        Ok(())
+    }
+
+    #[test]
+    fn test_window_is_reused() -> InflateResult<()> {
+        // The appended data repeats the existing data, so it costs little.
+        let data = b"a line that is repeated in the appended data\n".repeat(20);
+        let mut file = Cursor::new(compress(&data, Compression::default()));
+        let before = file.get_ref().len();
+        append_to_stream(&mut file, Format::Zlib, &data, Compression::default())?;
+        assert!(
+            file.get_ref().len() < before + 16,
+            "{}",
+            file.get_ref().len()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_to_last_member() -> InflateResult<()> {
+        let file = [
+            gzip_compress(b"member one\n", Compression::default()),
+            gzip_compress(b"member two\n", Compression::default()),
+        ]
+        .concat();
+        let mut file = Cursor::new(file);
+        append_to_stream(
+            &mut file,
+            Format::Gzip,
+            b"appended\n",
+            Compression::default(),
+        )?;
+        assert_eq!(
+            gzip_decompress(file.get_ref())?,
+            b"member one\nmember two\nappended\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_streams() -> io::Result<()> {
+        let mut corrupted = compress(b"some data", Compression::default());
+        let length = corrupted.len();
+        corrupted[length - 1] ^= 1;
+        let original = corrupted.clone();
+        let mut file = Cursor::new(corrupted);
+        assert_matches!(
+            append_to_stream(&mut file, Format::Zlib, b"x", Compression::default()),
+            Err(InflateError::ChecksumMismatch(..))
+        );
+        // Nothing was written.
+        assert_eq!(file.get_ref(), &original);
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.set_dictionary(b"dictionary")?;
+        encoder.write_all(b"data")?;
+        let mut file = Cursor::new(encoder.finish()?);
+        assert_matches!(
+            append_to_stream(&mut file, Format::Zlib, b"x", Compression::default()),
+            Err(InflateError::DictionaryRequired(_))
+        );
+
+        let mut trailing = compress(b"data", Compression::default());
+        trailing.push(0);
+        assert_matches!(
+            append_to_stream(
+                &mut Cursor::new(trailing),
+                Format::Zlib,
+                b"x",
+                Compression::default()
+            ),
+            Err(InflateError::IoError(_))
+        );
+        Ok(())
+    }
+}
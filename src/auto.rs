@@ -0,0 +1,240 @@
+// Decoding streams whose container format isn't known in advance, such as
+// HTTP's "Content-Encoding: deflate", which servers send as either zlib or
+// raw DEFLATE. The format is detected from the first two bytes:
+//
+// - Gzip members start with the magic bytes 1f 8b. A raw stream can't start
+//   with 0x1f, as that would be a block of the reserved type 3.
+// - Zlib headers have a valid CM and CINFO, and FCHECK makes them a multiple
+//   of 31. A raw stream starting with such a byte would begin with a stored
+//   block with non-zero padding, which encoders don't write.
+// - Anything else is taken to be raw DEFLATE.
+
+use crate::Inflator;
+use crate::error::{InflateError, InflateResult};
+use crate::format::Format;
+use crate::gzip::{GzDecoder, GzHeader};
+use crate::header::StreamHeader;
+use crate::inflate::RawInflator;
+use std::io::{Chain, Cursor, Read};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+// Guess the format of a stream from its first bytes. At least two are needed
+// to tell the formats apart; fewer are taken to be raw DEFLATE.
+pub fn detect_format(prefix: &[u8]) -> Format {
+    let Some(&[first, second]) = prefix.first_chunk::<2>() else {
+        return Format::Raw;
+    };
+    if [first, second] == GZIP_MAGIC {
+        Format::Gzip
+    } else if StreamHeader::try_from(&[first, second]).is_ok() {
+        Format::Zlib
+    } else {
+        Format::Raw
+    }
+}
+
+// The input, with the bytes read for detection put back in front.
+type SniffedInput<R> = Chain<Cursor<Vec<u8>>, R>;
+
+#[derive(Debug)]
+enum Decoder<R: Read> {
+    Raw(RawInflator<SniffedInput<R>>),
+    Zlib(Inflator<SniffedInput<R>>),
+    Gzip(GzDecoder<SniffedInput<R>>),
+}
+
+// Decoder for a raw DEFLATE, zlib or gzip stream, detecting which from its
+// first bytes. Gzip input is decoded one member at a time, like GzDecoder.
+#[derive(Debug)]
+pub struct AutoDecoder<R: Read> {
+    decoder: Decoder<R>,
+}
+
+impl<R: Read> AutoDecoder<R> {
+    // Read the first bytes of `input` to detect its format, then its header.
+    pub fn try_new(mut input: R) -> InflateResult<Self> {
+        let mut prefix = Vec::with_capacity(2);
+        input.by_ref().take(2).read_to_end(&mut prefix)?;
+        let format = detect_format(&prefix);
+        let input = Cursor::new(prefix).chain(input);
+        let decoder = match format {
+            Format::Raw => Decoder::Raw(RawInflator::new(input)),
+            Format::Zlib => Decoder::Zlib(Inflator::try_new(input)?),
+            Format::Gzip => Decoder::Gzip(GzDecoder::try_new(input)?),
+        };
+        Ok(Self { decoder })
+    }
+
+    // The detected format.
+    pub fn format(&self) -> Format {
+        match self.decoder {
+            Decoder::Raw(_) => Format::Raw,
+            Decoder::Zlib(_) => Format::Zlib,
+            Decoder::Gzip(_) => Format::Gzip,
+        }
+    }
+
+    // The gzip member header, for gzip input.
+    pub fn gzip_header(&self) -> Option<&GzHeader> {
+        match &self.decoder {
+            Decoder::Gzip(decoder) => Some(decoder.header()),
+            _ => None,
+        }
+    }
+
+    // The Adler-32 checksum of the preset dictionary a zlib stream was
+    // compressed with, if any.
+    pub fn dictionary_id(&self) -> Option<u32> {
+        match &self.decoder {
+            Decoder::Zlib(inflator) => inflator.dictionary_id(),
+            _ => None,
+        }
+    }
+
+    // Supply the preset dictionary named by `dictionary_id()`. Raw streams
+    // accept any dictionary before the first block, as there is nothing to
+    // check it against, and gzip streams can't use one.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) -> InflateResult<()> {
+        match &mut self.decoder {
+            Decoder::Raw(inflator) => inflator.set_dictionary(dictionary),
+            Decoder::Zlib(inflator) => return inflator.set_dictionary(dictionary),
+            Decoder::Gzip(_) => return Err(InflateError::UnexpectedDictionary),
+        }
+        Ok(())
+    }
+
+    // Whether the final block, and the trailer if any, have been read.
+    pub fn is_finished(&self) -> bool {
+        match &self.decoder {
+            Decoder::Raw(inflator) => inflator.is_finished(),
+            Decoder::Zlib(inflator) => inflator.is_finished(),
+            Decoder::Gzip(decoder) => decoder.is_finished(),
+        }
+    }
+
+    // Decode the next block. After the final block, the trailer, if any, is
+    // read and verified.
+    pub fn next_block(&mut self) -> InflateResult<Vec<u8>> {
+        match &mut self.decoder {
+            Decoder::Raw(inflator) => inflator.next_block(),
+            Decoder::Zlib(inflator) => inflator.next_block(),
+            Decoder::Gzip(decoder) => decoder.next_block(),
+        }
+    }
+
+    // Decode all remaining blocks and verify the trailer, if any.
+    pub fn read_to_end(&mut self, out: &mut Vec<u8>) -> InflateResult<()> {
+        while !self.is_finished() {
+            out.extend(self.next_block()?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Compression, GzEncoder, ZlibEncoder, compress, gzip_compress, raw_compress};
+    use std::assert_matches;
+    use std::io::Write;
+
+    fn decode(compressed: &[u8]) -> InflateResult<(Format, Vec<u8>)> {
+        let mut decoder = AutoDecoder::try_new(compressed)?;
+        let mut out = Vec::new();
+        decoder.read_to_end(&mut out)?;
+        Ok((decoder.format(), out))
+    }
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(detect_format(&[0x1f, 0x8b, 8]), Format::Gzip);
+        assert_eq!(detect_format(&[0x78, 0x9c]), Format::Zlib);
+        assert_eq!(detect_format(&[0x78, 0x01]), Format::Zlib);
+        assert_eq!(detect_format(&[0x78, 0xda]), Format::Zlib);
+        // Bad FCHECK.
+        assert_eq!(detect_format(&[0x78, 0x9d]), Format::Raw);
+        assert_eq!(detect_format(&[0x1f, 0x8c]), Format::Raw);
+        assert_eq!(detect_format(&[0x1f]), Format::Raw);
+        assert_eq!(detect_format(&[]), Format::Raw);
+    }
+
+    #[test]
+    fn test_every_format() -> InflateResult<()> {
+        let data = b"sniff the first bytes, then decode. ".repeat(100);
+        for level in [0, 1, 6, 9] {
+            let compression = Compression::new(level);
+            assert_eq!(
+                decode(&compress(&data, compression))?,
+                (Format::Zlib, data.clone())
+            );
+            assert_eq!(
+                decode(&gzip_compress(&data, compression))?,
+                (Format::Gzip, data.clone())
+            );
+            assert_eq!(
+                decode(&raw_compress(&data, compression))?,
+                (Format::Raw, data.clone())
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw_never_looks_like_a_header() {
+        // Every first byte an encoder could write for a raw stream: the
+        // block header, then zero padding for a stored block.
+        for byte in 0..=255u8 {
+            let block_type = (byte >> 1) & 3;
+            if block_type == 3 || (block_type == 0 && byte >> 3 != 0) {
+                continue;
+            }
+            for second in 0..=255u8 {
+                assert_eq!(detect_format(&[byte, second]), Format::Raw);
+            }
+        }
+    }
+
+    #[test]
+    fn test_short_input() -> InflateResult<()> {
+        // An empty final fixed block is two bytes.
+        let compressed = raw_compress(&[], Compression::default());
+        assert_eq!(compressed.len(), 2);
+        assert_eq!(decode(&compressed)?, (Format::Raw, vec![]));
+        assert_matches!(decode(&[]), Err(InflateError::IoError(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzip_header() -> InflateResult<()> {
+        let header = GzHeader::builder().filename("data.txt").build()?;
+        let mut encoder = GzEncoder::with_header(Vec::new(), header, Compression::default())?;
+        encoder.write_all(b"data")?;
+        let compressed = encoder.finish()?;
+        let decoder = AutoDecoder::try_new(compressed.as_slice())?;
+        assert_eq!(
+            decoder
+                .gzip_header()
+                .and_then(|header| header.filename.as_deref()),
+            Some(b"data.txt".as_slice())
+        );
+        assert_eq!(decoder.dictionary_id(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_zlib_dictionary() -> InflateResult<()> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.set_dictionary(b"dictionary")?;
+        encoder.write_all(b"dictionary data")?;
+        let compressed = encoder.finish()?;
+        let mut decoder = AutoDecoder::try_new(compressed.as_slice())?;
+        assert_eq!(decoder.format(), Format::Zlib);
+        assert!(decoder.dictionary_id().is_some());
+        decoder.set_dictionary(b"dictionary")?;
+        let mut out = Vec::new();
+        decoder.read_to_end(&mut out)?;
+        assert_eq!(out, b"dictionary data");
+        Ok(())
+    }
+}
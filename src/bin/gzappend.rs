@@ -0,0 +1,66 @@
+// Appends files to a compressed file in place, without recompressing what is
+// already there.
+//
+// Usage: gzappend [--zlib | --raw] [-LEVEL] FILE [INPUT...]
+//
+// FILE is a gzip file by default. Without inputs, standard input is appended.
+
+use diy_zlib::append::append_to_stream;
+use diy_zlib::{Compression, Format};
+use std::fs::OpenOptions;
+use std::io::Read;
+use std::process::ExitCode;
+
+fn usage() -> ExitCode {
+    eprintln!("Usage: gzappend [--zlib | --raw] [-LEVEL] FILE [INPUT...]");
+    ExitCode::FAILURE
+}
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let mut format = Format::Gzip;
+    let mut compression = Compression::default();
+    while let Some(arg) = args.first().filter(|arg| arg.starts_with('-')) {
+        match arg.as_str() {
+            "--zlib" => format = Format::Zlib,
+            "--raw" => format = Format::Raw,
+            level => match level[1..].parse() {
+                Ok(level @ 0..=9) => compression = Compression::new(level),
+                _ => return usage(),
+            },
+        }
+        args.remove(0);
+    }
+    let Some((path, input_paths)) = args.split_first() else {
+        return usage();
+    };
+
+    let mut data = Vec::new();
+    if input_paths.is_empty()
+        && let Err(e) = std::io::stdin().read_to_end(&mut data)
+    {
+        eprintln!("stdin: {}", e);
+        return ExitCode::FAILURE;
+    }
+    for input_path in input_paths {
+        match std::fs::read(input_path) {
+            Ok(input) => data.extend(input),
+            Err(e) => {
+                eprintln!("{}: {}", input_path, e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let result = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .map_err(Into::into)
+        .and_then(|mut file| append_to_stream(&mut file, format, &data, compression));
+    if let Err(e) = result {
+        eprintln!("{}: {}", path, e);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,55 @@
+// Joins compressed files into a single stream without recompressing them.
+//
+// Usage: gzjoin [--zlib | --raw] OUTPUT INPUT...
+//
+// Inputs are gzip files by default.
+
+use diy_zlib::Format;
+use diy_zlib::join::join_streams;
+use std::process::ExitCode;
+
+fn usage() -> ExitCode {
+    eprintln!("Usage: gzjoin [--zlib | --raw] OUTPUT INPUT...");
+    ExitCode::FAILURE
+}
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let format = match args.first().map(String::as_str) {
+        Some("--zlib") => Format::Zlib,
+        Some("--raw") => Format::Raw,
+        _ => Format::Gzip,
+    };
+    if format != Format::Gzip {
+        args.remove(0);
+    }
+    let Some((output, input_paths)) = args.split_first() else {
+        return usage();
+    };
+    if input_paths.is_empty() {
+        return usage();
+    }
+
+    let mut inputs = Vec::new();
+    for path in input_paths {
+        match std::fs::read(path) {
+            Ok(input) => inputs.push(input),
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let joined = match join_streams(&inputs, format) {
+        Ok(joined) => joined,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(e) = std::fs::write(output, &joined) {
+        eprintln!("{}: {}", output, e);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
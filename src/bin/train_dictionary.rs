@@ -0,0 +1,48 @@
+// Builds a preset dictionary from sample files and prints its DICTID.
+//
+// Usage: train_dictionary [--size BYTES] OUTPUT SAMPLE...
+
+use diy_zlib::dictionary::{MAX_DICTIONARY_SIZE, dictionary_id, train_dictionary};
+use std::process::ExitCode;
+
+fn usage() -> ExitCode {
+    eprintln!("Usage: train_dictionary [--size BYTES] OUTPUT SAMPLE...");
+    ExitCode::FAILURE
+}
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let mut size = MAX_DICTIONARY_SIZE;
+    if args.first().is_some_and(|arg| arg == "--size") {
+        let Some(Ok(value)) = args.get(1).map(|value| value.parse()) else {
+            return usage();
+        };
+        size = value;
+        args.drain(..2);
+    }
+    let Some((output, sample_paths)) = args.split_first() else {
+        return usage();
+    };
+    if sample_paths.is_empty() {
+        return usage();
+    }
+
+    let mut samples = Vec::new();
+    for path in sample_paths {
+        match std::fs::read(path) {
+            Ok(sample) => samples.push(sample),
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let dictionary = train_dictionary(&samples, size);
+    if let Err(e) = std::fs::write(output, &dictionary) {
+        eprintln!("{}: {}", output, e);
+        return ExitCode::FAILURE;
+    }
+    println!("Size: {} bytes", dictionary.len());
+    println!("DICTID: {:#010x}", dictionary_id(&dictionary));
+    ExitCode::SUCCESS
+}
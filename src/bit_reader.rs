@@ -67,6 +67,8 @@ pub trait BitRead: Read {
 pub struct BitReader<R: Read> {
     input: R,
     bit_buffer: Option<BitBuffer>,
+    // Bytes taken from the input so far.
+    bytes_read: u64,
 }
 
 impl<R: Read> BitReader<R> {
@@ -74,8 +76,25 @@ impl<R: Read> BitReader<R> {
         BitReader {
             input,
             bit_buffer: None,
+            bytes_read: 0,
         }
     }
+
+    // Number of bits consumed from the input so far.
+    pub fn bit_position(&self) -> u64 {
+        self.bytes_read * 8 - self.pending_bits() as u64
+    }
+
+    // Number of unread bits in the partially consumed byte, if any.
+    pub fn pending_bits(&self) -> u8 {
+        self.bit_buffer.map_or(0, |buffer| 8 - buffer.bit_offset)
+    }
+
+    // Underlying input, e.g. for checking whether more follows. Reading from
+    // it directly throws off bit_position().
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.input
+    }
 }
 
 impl<R: Read> BitRead for BitReader<R> {
@@ -95,7 +114,9 @@ impl<R: Read> BitRead for BitReader<R> {
 impl<R: Read> Read for BitReader<R> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         self.bit_buffer = None;
-        self.input.read(buf)
+        let count = self.input.read(buf)?;
+        self.bytes_read += count as u64;
+        Ok(count)
     }
 }
 
@@ -115,6 +136,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_read_bit() -> io::Result<()> {
         // A pattern of 1x1, 0, 2x1, 0, ...
         let raw: &[u8] = &[0b11101101, 0b1101_1110];
@@ -172,4 +194,19 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_pending_bits() -> io::Result<()> {
+        let raw: &[u8] = &[0xff, 0xff];
+        let mut reader = BitReader::new(raw);
+        assert_eq!(reader.pending_bits(), 0);
+        reader.read_bits::<u8>(3)?;
+        assert_eq!(reader.pending_bits(), 5);
+        reader.read_bits::<u8>(5)?;
+        assert_eq!(reader.pending_bits(), 0);
+        assert_eq!(reader.bit_position(), 8);
+        reader.read_bit()?;
+        assert_eq!(reader.bit_position(), 9);
+        Ok(())
+    }
 }
@@ -0,0 +1,142 @@
+use crate::code::Code;
+use std::io::{self, Write};
+
+// Counterpart to BitReader: writes values to a byte-based output at a sub-byte
+// granularity. Bits are packed starting from the LSB of each byte.
+#[derive(Debug)]
+pub struct BitWriter<W: Write> {
+    output: W,
+    // Pending bits that don't yet form a complete byte, stored LSB-first.
+    buffer: u64,
+    bit_count: u8,
+}
+
+impl<W: Write> BitWriter<W> {
+    pub fn new(output: W) -> Self {
+        BitWriter {
+            output,
+            buffer: 0,
+            bit_count: 0,
+        }
+    }
+
+    // Write the lowest `count` bits of `value`, starting from the LSB.
+    pub fn write_bits(&mut self, value: u32, count: u8) -> io::Result<()> {
+        debug_assert!(count <= 32);
+        let value = (value as u64) & ((1u64 << count) - 1);
+        self.buffer |= value << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.output.write_all(&[self.buffer as u8])?;
+            self.buffer >>= 8;
+            self.bit_count -= 8;
+        }
+        Ok(())
+    }
+
+    pub fn write_bit(&mut self, bit: bool) -> io::Result<()> {
+        self.write_bits(bit as u32, 1)
+    }
+
+    // Huffman codes are packed starting from their MSB.
+    pub fn write_code(&mut self, code: Code) -> io::Result<()> {
+        if code.length == 0 {
+            return Ok(());
+        }
+        let reversed = code.bits.reverse_bits() >> (32 - code.length);
+        self.write_bits(reversed, code.length)
+    }
+
+    // Pad the current byte with zeroes so that the next write starts on a byte
+    // boundary.
+    pub fn align(&mut self) -> io::Result<()> {
+        if self.bit_count > 0 {
+            self.write_bits(0, 8 - self.bit_count)?;
+        }
+        Ok(())
+    }
+
+    pub fn is_aligned(&self) -> bool {
+        self.bit_count == 0
+    }
+
+    // Number of bits written to the current partial byte.
+    pub fn pending_bits(&self) -> u8 {
+        self.bit_count
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.output
+    }
+
+    // Any partially written byte is discarded.
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+
+    pub fn write_u16(&mut self, value: u16) -> io::Result<()> {
+        self.write_bits(value as u32, 16)
+    }
+
+    // Write whole bytes. The output must be byte-aligned.
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        assert!(self.is_aligned(), "Unaligned byte write");
+        self.output.write_all(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_reader::{BitRead, BitReader};
+
+    #[test]
+    fn test_write_bits() -> io::Result<()> {
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_bits(0b1, 1)?;
+        writer.write_bits(0b10, 2)?;
+        writer.write_bits(0b101, 3)?;
+        // Cross byte boundary.
+        writer.write_bits(0b1011, 4)?;
+        writer.write_bits(0b10111, 5)?;
+        writer.align()?;
+        assert_eq!(
+            std::mem::take(writer.get_mut()),
+            vec![0b11101101, 0b0101_1110]
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::unusual_byte_groupings)]
+    fn test_write_code() -> io::Result<()> {
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_code(Code::from("0"))?;
+        writer.write_code(Code::from("10"))?;
+        writer.write_code(Code::from("11"))?;
+        writer.write_code(Code::from("010"))?;
+        writer.align()?;
+        assert_eq!(std::mem::take(writer.get_mut()), vec![0b010_11_01_0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip() -> io::Result<()> {
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_bits(5, 3)?;
+        writer.align()?;
+        writer.write_u16(0x1234)?;
+        writer.write_bytes(&[7, 8])?;
+        writer.write_bits(0x1ffff, 17)?;
+        writer.align()?;
+
+        let raw = std::mem::take(writer.get_mut());
+        let mut reader = BitReader::new(raw.as_slice());
+        assert_eq!(reader.read_bits::<u8>(3)?, 5);
+        assert_eq!(reader.read_u16()?, 0x1234);
+        assert_eq!(reader.read_u8()?, 7);
+        assert_eq!(reader.read_u8()?, 8);
+        assert_eq!(reader.read_bits::<u32>(17)?, 0x1ffff);
+        Ok(())
+    }
+}
@@ -1,12 +1,53 @@
 use crate::bit_reader::BitRead;
-use crate::code_table::{CodeLength, CodeToSymbolTable};
+use crate::block_encoder::LL_COUNT;
+use crate::code_table::{CodeLength, CodeToSymbolTable, is_oversubscribed};
 use crate::error::{InflateError, InflateResult};
 use crate::lz77::Instruction;
+use crate::match_finder::WINDOW_SIZE;
+use crate::options::InflateOptions;
+use std::borrow::Cow;
+use std::sync::LazyLock;
+
+// The fixed codes are built once, as scanning for blocks may start many.
+static FIXED_LL_TABLE: LazyLock<CodeToSymbolTable> = LazyLock::new(CodeToSymbolTable::fixed_ll);
+static FIXED_DISTANCE_TABLE: LazyLock<CodeToSymbolTable> =
+    LazyLock::new(CodeToSymbolTable::fixed_distance);
+
+// Which flavour of DEFLATE to decode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Variant {
+    #[default]
+    Deflate,
+    // Deflate64, or Enhanced Deflate, as used by ZIP's compression method 9:
+    // a 64 KiB window, length symbol 285 with 16 extra bits for lengths of
+    // up to 65538, and distance symbols 30 and 31.
+    Deflate64,
+}
+
+impl Variant {
+    // Largest back-reference distance.
+    pub fn window_size(&self) -> usize {
+        match self {
+            Variant::Deflate => WINDOW_SIZE,
+            Variant::Deflate64 => 64 * 1024,
+        }
+    }
+}
+
+// A decoded instruction, with lengths and distances wide enough for
+// Deflate64. Instruction holds those of standard DEFLATE.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WideInstruction {
+    Literal(u8),
+    EndOfBlock,
+    BackReference { length: u32, distance: u32 },
+}
 
 pub struct BlockDecoder<'a, R: BitRead> {
     reader: &'a mut R,
-    ll_table: CodeToSymbolTable,
-    distance_table: CodeToSymbolTable,
+    ll_table: Cow<'static, CodeToSymbolTable>,
+    distance_table: Cow<'static, CodeToSymbolTable>,
+    variant: Variant,
 }
 
 fn push_repeated<T: Copy>(v: &mut Vec<T>, value: T, count: usize) {
@@ -20,16 +61,37 @@ impl<'a, R: BitRead> BlockDecoder<'a, R> {
     pub fn new_fixed(reader: &'a mut R) -> Self {
         Self {
             reader,
-            ll_table: CodeToSymbolTable::fixed_ll(),
-            distance_table: CodeToSymbolTable::fixed_distance(),
+            ll_table: Cow::Borrowed(&FIXED_LL_TABLE),
+            distance_table: Cow::Borrowed(&FIXED_DISTANCE_TABLE),
+            variant: Variant::Deflate,
         }
     }
 
+    // Decode `variant` instead of standard DEFLATE. The block header is the
+    // same for both. Deflate64 must be read with next_wide().
+    pub(crate) fn with_variant(self, variant: Variant) -> Self {
+        Self { variant, ..self }
+    }
+
     // Decoder for block type 2 (dynamic codes).
     pub fn new_dynamic(reader: &'a mut R) -> InflateResult<Self> {
+        Self::new_dynamic_checked(reader, &InflateOptions::lenient(), &mut Vec::new())
+    }
+
+    // Like new_dynamic(), applying the block header checks of `options`.
+    pub(crate) fn new_dynamic_checked(
+        reader: &'a mut R,
+        options: &InflateOptions,
+        warnings: &mut Vec<InflateError>,
+    ) -> InflateResult<Self> {
         let ll_count = reader.read_bits::<usize>(5)? + 257;
         let distance_count = reader.read_bits::<usize>(5)? + 1;
         let cl_count = reader.read_bits::<usize>(4)? + 4;
+        if ll_count > LL_COUNT {
+            options
+                .excess_length_codes
+                .apply(InflateError::TooManyLengthCodes(ll_count), warnings)?;
+        }
 
         // Construct CL table.
         let cl_table: CodeToSymbolTable;
@@ -38,10 +100,11 @@ impl<'a, R: BitRead> BlockDecoder<'a, R> {
             let cl_indexes = [
                 16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
             ];
-            for i in 0..cl_count {
-                let cl_code_length = reader.read_bits::<u8>(3)?;
-                let index = cl_indexes[i];
-                cl_code_lengths[index] = cl_code_length;
+            for &index in cl_indexes.iter().take(cl_count) {
+                cl_code_lengths[index] = reader.read_bits::<u8>(3)?;
+            }
+            if is_oversubscribed(&cl_code_lengths) {
+                return Err(InflateError::OversubscribedCode);
             }
             cl_table = CodeToSymbolTable::from_code_lengths(&cl_code_lengths);
         }
@@ -71,60 +134,87 @@ impl<'a, R: BitRead> BlockDecoder<'a, R> {
         }
 
         let mut ll_lengths = [0; 288];
-        for i in 0..ll_count {
-            ll_lengths[i] = code_lengths[i];
-        }
+        ll_lengths[..ll_count].copy_from_slice(&code_lengths[..ll_count]);
         let mut distance_lengths = [0; 32];
-        for i in 0..distance_count {
-            distance_lengths[i] = code_lengths[ll_count + i];
+        distance_lengths[..distance_count]
+            .copy_from_slice(&code_lengths[ll_count..ll_count + distance_count]);
+        if is_oversubscribed(&ll_lengths) || is_oversubscribed(&distance_lengths) {
+            return Err(InflateError::OversubscribedCode);
+        }
+        if distance_lengths.iter().all(|&length| length == 0) {
+            options
+                .empty_distance_code
+                .apply(InflateError::NoDistanceCodes, warnings)?;
         }
         Ok(Self {
             reader,
-            ll_table: CodeToSymbolTable::from_code_lengths(&ll_lengths),
-            distance_table: CodeToSymbolTable::from_code_lengths(&distance_lengths),
+            ll_table: Cow::Owned(CodeToSymbolTable::from_code_lengths(&ll_lengths)),
+            distance_table: Cow::Owned(CodeToSymbolTable::from_code_lengths(&distance_lengths)),
+            variant: Variant::Deflate,
         })
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> InflateResult<Instruction> {
+        // Only Deflate64 exceeds 16 bits, and it is decoded with next_wide().
+        Ok(match self.next_wide()? {
+            WideInstruction::Literal(byte) => Instruction::Literal(byte),
+            WideInstruction::EndOfBlock => Instruction::EndOfBlock,
+            WideInstruction::BackReference { length, distance } => Instruction::BackReference {
+                length: length as u16,
+                distance: distance as u16,
+            },
+        })
+    }
+
+    // Like next(), for any variant.
+    pub(crate) fn next_wide(&mut self) -> InflateResult<WideInstruction> {
         let symbol = self.ll_table.read_symbol(self.reader)? as u16;
         if symbol < 256 {
-            return Ok(Instruction::Literal(symbol as u8));
+            return Ok(WideInstruction::Literal(symbol as u8));
         }
         if symbol == 256 {
-            return Ok(Instruction::EndOfBlock);
+            return Ok(WideInstruction::EndOfBlock);
         }
         let length = self.read_length(symbol)?;
         let distance = self.read_distance()?;
-        Ok(Instruction::BackReference { length, distance })
+        Ok(WideInstruction::BackReference { length, distance })
     }
 
-    fn read_length(&mut self, symbol: u16) -> InflateResult<u16> {
+    fn read_length(&mut self, symbol: u16) -> InflateResult<u32> {
         // Borrowed from
         // https://github.com/nayuki/Simple-DEFLATE-decompressor/blob/2586b459a84f8918851a1078c2c0482b1b383fba/python/deflatedecompress.py#L439
         if symbol <= 264 {
-            return Ok(symbol - 254);
+            return Ok((symbol - 254) as u32);
         }
         if symbol <= 284 {
             let extra_bit_count = (symbol - 261) / 4;
             let extra_bits = self.reader.read_bits::<u16>(extra_bit_count as u8)?;
             let base = ((symbol - 265) % 4 + 4) << extra_bit_count;
-            return Ok(3 + base + extra_bits);
+            return Ok((3 + base + extra_bits) as u32);
         }
         if symbol == 285 {
-            return Ok(258);
+            return match self.variant {
+                Variant::Deflate => Ok(258),
+                Variant::Deflate64 => Ok(3 + self.reader.read_bits::<u32>(16)?),
+            };
         }
         Err(InflateError::InvalidLengthSymbol(symbol))
     }
 
-    fn read_distance(&mut self) -> InflateResult<u16> {
+    fn read_distance(&mut self) -> InflateResult<u32> {
         // Borrowed from https://github.com/nayuki/Simple-DEFLATE-decompressor/blob/2586b459a84f8918851a1078c2c0482b1b383fba/python/deflatedecompress.py#L456
-        let symbol = self.distance_table.read_symbol(self.reader)? as u16;
+        let symbol = self.distance_table.read_symbol(self.reader)?;
         if symbol <= 3 {
             return Ok(symbol + 1);
         }
-        if symbol <= 29 {
-            let extra_bit_count = symbol / 2 + 1;
-            let extra_bits = self.reader.read_bits::<u16>(extra_bit_count as u8)?;
+        let last_symbol = match self.variant {
+            Variant::Deflate => 29,
+            Variant::Deflate64 => 31,
+        };
+        if symbol <= last_symbol {
+            let extra_bit_count = symbol / 2 - 1;
+            let extra_bits = self.reader.read_bits::<u32>(extra_bit_count as u8)?;
             let base = (symbol % 2 + 2) << extra_bit_count;
             return Ok(1 + base + extra_bits);
         }
@@ -137,6 +227,10 @@ mod tests {
     use super::*;
     use crate::bit_reader::BitReader;
     use crate::bit_string::bit_string;
+    use crate::bit_writer::BitWriter;
+    use crate::code_table::SymbolToCodeTable;
+    use std::assert_matches;
+    use std::io;
 
     #[test]
     fn test_read_literal() -> InflateResult<()> {
@@ -175,4 +269,164 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_oversubscribed_code_lengths() -> InflateResult<()> {
+        // HLIT, HDIST and HCLEN at their minimums, then four code length
+        // code lengths of 1: more codes than fit in one bit.
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_bits(0, 5 + 5 + 4)?;
+        for _ in 0..4 {
+            writer.write_bits(1, 3)?;
+        }
+        writer.align()?;
+        let raw = writer.into_inner();
+        let mut reader = BitReader::new(raw.as_slice());
+        assert_matches!(
+            BlockDecoder::new_dynamic(&mut reader).err(),
+            Some(InflateError::OversubscribedCode)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_variants() -> InflateResult<()> {
+        // Length symbol 285 with 16 extra bits, then distance symbol 31 with
+        // 14 extra bits, all at their maximums.
+        let ll_code = SymbolToCodeTable::fixed_ll().code(285);
+        let distance_code = SymbolToCodeTable::fixed_distance().code(31);
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_code(ll_code)?;
+        writer.write_bits(0xffff, 16)?;
+        writer.write_code(distance_code)?;
+        writer.write_bits(0x3fff, 14)?;
+        writer.align()?;
+        let raw = writer.into_inner();
+
+        let mut reader = BitReader::new(raw.as_slice());
+        let mut decoder = BlockDecoder::new_fixed(&mut reader).with_variant(Variant::Deflate64);
+        assert_eq!(
+            decoder.next_wide()?,
+            WideInstruction::BackReference {
+                length: 65538,
+                distance: 65536
+            }
+        );
+
+        // Standard DEFLATE has no extra bits for symbol 285, so it reads a
+        // distance symbol from the start of the extra bits: 0b11111 is 31,
+        // which is invalid.
+        let mut reader = BitReader::new(raw.as_slice());
+        let mut decoder = BlockDecoder::new_fixed(&mut reader);
+        assert_matches!(decoder.next(), Err(InflateError::InvalidDistanceSymbol(31)));
+
+        // With 1000 as the extra bits instead, 0b00000_01111101000, the first
+        // five bits reversed are 0b00010.
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_code(ll_code)?;
+        writer.write_bits(1000, 16)?;
+        writer.align()?;
+        let raw = writer.into_inner();
+        let mut reader = BitReader::new(raw.as_slice());
+        let mut decoder = BlockDecoder::new_fixed(&mut reader);
+        assert_eq!(
+            decoder.next()?,
+            Instruction::BackReference {
+                length: 258,
+                distance: 3
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_distance_symbols_30_31() -> InflateResult<()> {
+        for symbol in [30, 31] {
+            let mut writer = BitWriter::new(Vec::new());
+            writer.write_code(SymbolToCodeTable::fixed_ll().code(257))?;
+            writer.write_code(SymbolToCodeTable::fixed_distance().code(symbol))?;
+            writer.write_bits(0, 14)?;
+            writer.align()?;
+            let raw = writer.into_inner();
+            let mut reader = BitReader::new(raw.as_slice());
+            assert_matches!(
+                BlockDecoder::new_fixed(&mut reader).next(),
+                Err(InflateError::InvalidDistanceSymbol(s)) if s as usize == symbol
+            );
+            let mut reader = BitReader::new(raw.as_slice());
+            let mut decoder = BlockDecoder::new_fixed(&mut reader).with_variant(Variant::Deflate64);
+            assert_eq!(
+                decoder.next_wide()?,
+                WideInstruction::BackReference {
+                    length: 3,
+                    distance: [32769, 49153][symbol - 30]
+                }
+            );
+        }
+        Ok(())
+    }
+
+    // Dynamic block header sending the code lengths verbatim, each with a
+    // 4-bit code length code.
+    fn dynamic_header(ll_lengths: &[u8], distance_lengths: &[u8]) -> io::Result<Vec<u8>> {
+        let cl_order = [
+            16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+        ];
+        let mut cl_lengths = [4; 19];
+        cl_lengths[16..].fill(0);
+        let cl_codes = SymbolToCodeTable::from_code_lengths(&cl_lengths);
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_bits(ll_lengths.len() as u32 - 257, 5)?;
+        writer.write_bits(distance_lengths.len() as u32 - 1, 5)?;
+        writer.write_bits(19 - 4, 4)?;
+        for symbol in cl_order {
+            writer.write_bits(cl_lengths[symbol] as u32, 3)?;
+        }
+        for &length in ll_lengths.iter().chain(distance_lengths) {
+            writer.write_code(cl_codes.code(length as usize))?;
+        }
+        writer.align()?;
+        Ok(writer.into_inner())
+    }
+
+    fn check_header(raw: &[u8], options: &InflateOptions) -> InflateResult<Vec<InflateError>> {
+        let mut reader = BitReader::new(raw);
+        let mut warnings = Vec::new();
+        BlockDecoder::new_dynamic_checked(&mut reader, options, &mut warnings)?;
+        Ok(warnings)
+    }
+
+    #[test]
+    fn test_too_many_length_codes() -> InflateResult<()> {
+        let mut ll_lengths = [9; 288];
+        ll_lengths[256..].fill(6);
+        let raw = dynamic_header(&ll_lengths, &[1])?;
+        assert!(check_header(&raw, &InflateOptions::lenient())?.is_empty());
+        assert_matches!(
+            check_header(&raw, &InflateOptions::strict()),
+            Err(InflateError::TooManyLengthCodes(288))
+        );
+        assert_matches!(
+            check_header(&raw, &InflateOptions::validating())?[..],
+            [InflateError::TooManyLengthCodes(288)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_distance_codes() -> InflateResult<()> {
+        let mut ll_lengths = [9; 286];
+        ll_lengths[256..].fill(6);
+        let raw = dynamic_header(&ll_lengths, &[0])?;
+        assert!(check_header(&raw, &InflateOptions::lenient())?.is_empty());
+        assert_matches!(
+            check_header(&raw, &InflateOptions::strict()),
+            Err(InflateError::NoDistanceCodes)
+        );
+        assert_matches!(
+            check_header(&raw, &InflateOptions::validating())?[..],
+            [InflateError::NoDistanceCodes]
+        );
+        Ok(())
+    }
 }
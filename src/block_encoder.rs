@@ -0,0 +1,498 @@
+use crate::bit_writer::BitWriter;
+use crate::code_table::{
+    CodeLength, MAX_CODE_LENGTH, SymbolToCodeTable, code_lengths_from_frequencies,
+};
+use crate::lz77::Instruction;
+use std::io::{self, Write};
+
+const END_OF_BLOCK: usize = 256;
+pub const LL_COUNT: usize = 286;
+pub const DISTANCE_COUNT: usize = 30;
+const MAX_CL_CODE_LENGTH: CodeLength = 7;
+const MAX_STORED_LENGTH: usize = u16::MAX as usize;
+
+// Order in which code length code lengths are written.
+const CL_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+// Base lengths and extra bit counts for length symbols 257 through 285.
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+// Base distances and extra bit counts for distance symbols 0 through 29.
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+// A symbol along with the extra bits that follow it.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct SymbolWithExtraBits {
+    pub symbol: usize,
+    pub extra_bit_count: u8,
+    pub extra_bits: u16,
+}
+
+// Inverse of BlockDecoder::read_length.
+pub fn length_symbol(length: u16) -> SymbolWithExtraBits {
+    let index = LENGTH_BASES.partition_point(|&base| base <= length) - 1;
+    SymbolWithExtraBits {
+        symbol: 257 + index,
+        extra_bit_count: LENGTH_EXTRA_BITS[index],
+        extra_bits: length - LENGTH_BASES[index],
+    }
+}
+
+// Inverse of BlockDecoder::read_distance.
+pub fn distance_symbol(distance: u16) -> SymbolWithExtraBits {
+    let index = DISTANCE_BASES.partition_point(|&base| base <= distance) - 1;
+    SymbolWithExtraBits {
+        symbol: index,
+        extra_bit_count: DISTANCE_EXTRA_BITS[index],
+        extra_bits: distance - DISTANCE_BASES[index],
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockType {
+    Stored,
+    Fixed,
+    Dynamic,
+}
+
+// Symbol frequencies for a block.
+#[derive(Debug, Clone)]
+pub struct Frequencies {
+    pub ll: [u32; LL_COUNT],
+    pub distance: [u32; DISTANCE_COUNT],
+}
+
+impl Frequencies {
+    pub fn new(instructions: &[Instruction]) -> Self {
+        let mut frequencies = Self {
+            ll: [0; LL_COUNT],
+            distance: [0; DISTANCE_COUNT],
+        };
+        for instruction in instructions {
+            frequencies.add(instruction);
+        }
+        frequencies.ll[END_OF_BLOCK] = 1;
+        frequencies
+    }
+
+    pub fn add(&mut self, instruction: &Instruction) {
+        match *instruction {
+            Instruction::Literal(byte) => self.ll[byte as usize] += 1,
+            Instruction::EndOfBlock => {}
+            Instruction::BackReference { length, distance } => {
+                self.ll[length_symbol(length).symbol] += 1;
+                self.distance[distance_symbol(distance).symbol] += 1;
+            }
+        }
+    }
+
+    // Size in bits of the symbols and extra bits when encoded with the given
+    // code lengths.
+    pub fn cost(&self, ll_lengths: &[CodeLength], distance_lengths: &[CodeLength]) -> usize {
+        let mut bits = 0;
+        for (symbol, &count) in self.ll.iter().enumerate() {
+            let mut length = ll_lengths[symbol] as usize;
+            if symbol > END_OF_BLOCK {
+                length += LENGTH_EXTRA_BITS[symbol - 257] as usize;
+            }
+            bits += count as usize * length;
+        }
+        for (symbol, &count) in self.distance.iter().enumerate() {
+            let length = distance_lengths[symbol] as usize + DISTANCE_EXTRA_BITS[symbol] as usize;
+            bits += count as usize * length;
+        }
+        bits
+    }
+}
+
+pub fn fixed_ll_lengths() -> [CodeLength; 288] {
+    let mut lengths = [8; 288];
+    lengths[144..=255].fill(9);
+    lengths[256..=279].fill(7);
+    lengths
+}
+
+// Run-length encoded code lengths, as (symbol, extra bits) pairs of the code
+// length alphabet.
+fn run_length_encode(lengths: &[CodeLength]) -> Vec<(u8, u8)> {
+    let mut encoded = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let length = lengths[i];
+        let run = lengths[i..].iter().take_while(|&&l| l == length).count();
+        let mut remaining = run;
+        if length == 0 {
+            while remaining >= 11 {
+                let count = remaining.min(138);
+                encoded.push((18, (count - 11) as u8));
+                remaining -= count;
+            }
+            if remaining >= 3 {
+                encoded.push((17, (remaining - 3) as u8));
+                remaining = 0;
+            }
+        } else if remaining >= 4 {
+            // The first length is written verbatim, then repeated.
+            encoded.push((length, 0));
+            remaining -= 1;
+            while remaining >= 3 {
+                let count = remaining.min(6);
+                encoded.push((16, (count - 3) as u8));
+                remaining -= count;
+            }
+        }
+        for _ in 0..remaining {
+            encoded.push((length, 0));
+        }
+        i += run;
+    }
+    encoded
+}
+
+fn cl_extra_bit_count(symbol: u8) -> u8 {
+    match symbol {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+// Huffman codes for a block type 2, along with its encoded header.
+#[derive(Debug)]
+pub struct DynamicCodes {
+    pub ll_lengths: Vec<CodeLength>,
+    pub distance_lengths: Vec<CodeLength>,
+    cl_lengths: [CodeLength; 19],
+    cl_symbols: Vec<(u8, u8)>,
+    cl_count: usize,
+}
+
+impl DynamicCodes {
+    pub fn new(frequencies: &Frequencies) -> Self {
+        let ll_lengths = code_lengths_from_frequencies(&frequencies.ll, MAX_CODE_LENGTH);
+        let distance_lengths =
+            code_lengths_from_frequencies(&frequencies.distance, MAX_CODE_LENGTH);
+        Self::from_lengths(ll_lengths, distance_lengths)
+    }
+
+    pub fn from_lengths(
+        mut ll_lengths: Vec<CodeLength>,
+        mut distance_lengths: Vec<CodeLength>,
+    ) -> Self {
+        // Trailing unused codes don't need to be sent.
+        let ll_count = 257.max(ll_lengths.iter().rposition(|&l| l != 0).unwrap_or(0) + 1);
+        let distance_count = 1.max(distance_lengths.iter().rposition(|&l| l != 0).unwrap_or(0) + 1);
+        ll_lengths.truncate(ll_count);
+        distance_lengths.truncate(distance_count);
+
+        let cl_symbols = run_length_encode(&[ll_lengths.as_slice(), &distance_lengths].concat());
+        let mut cl_frequencies = [0; 19];
+        for &(symbol, _) in &cl_symbols {
+            cl_frequencies[symbol as usize] += 1;
+        }
+        // Decoders reject an incomplete code length code, so make sure there
+        // are at least two codes.
+        if cl_frequencies.iter().filter(|&&f| f != 0).count() == 1 {
+            let unused = cl_frequencies.iter().position(|&f| f == 0).unwrap();
+            cl_frequencies[unused] = 1;
+        }
+        let cl_lengths: [CodeLength; 19] =
+            code_lengths_from_frequencies(&cl_frequencies, MAX_CL_CODE_LENGTH)
+                .try_into()
+                .unwrap();
+        let cl_count = 4.max(
+            CL_ORDER
+                .iter()
+                .rposition(|&symbol| cl_lengths[symbol] != 0)
+                .unwrap_or(0)
+                + 1,
+        );
+        Self {
+            ll_lengths,
+            distance_lengths,
+            cl_lengths,
+            cl_symbols,
+            cl_count,
+        }
+    }
+
+    // Size in bits of the block header after the block type.
+    pub fn header_cost(&self) -> usize {
+        let mut bits = 5 + 5 + 4 + 3 * self.cl_count;
+        for &(symbol, _) in &self.cl_symbols {
+            bits += (self.cl_lengths[symbol as usize] + cl_extra_bit_count(symbol)) as usize;
+        }
+        bits
+    }
+
+    fn write_header<W: Write>(&self, writer: &mut BitWriter<W>) -> io::Result<()> {
+        writer.write_bits((self.ll_lengths.len() - 257) as u32, 5)?;
+        writer.write_bits((self.distance_lengths.len() - 1) as u32, 5)?;
+        writer.write_bits((self.cl_count - 4) as u32, 4)?;
+        for &symbol in &CL_ORDER[..self.cl_count] {
+            writer.write_bits(self.cl_lengths[symbol] as u32, 3)?;
+        }
+        let cl_codes = SymbolToCodeTable::from_code_lengths(&self.cl_lengths);
+        for &(symbol, extra_bits) in &self.cl_symbols {
+            writer.write_code(cl_codes.code(symbol as usize))?;
+            writer.write_bits(extra_bits as u32, cl_extra_bit_count(symbol))?;
+        }
+        Ok(())
+    }
+}
+
+// Write the symbols for the given instructions, followed by an end of block
+// symbol.
+fn write_instructions<W: Write>(
+    writer: &mut BitWriter<W>,
+    ll_codes: &SymbolToCodeTable,
+    distance_codes: &SymbolToCodeTable,
+    instructions: &[Instruction],
+) -> io::Result<()> {
+    for instruction in instructions {
+        match *instruction {
+            Instruction::Literal(byte) => writer.write_code(ll_codes.code(byte as usize))?,
+            Instruction::EndOfBlock => {}
+            Instruction::BackReference { length, distance } => {
+                let length = length_symbol(length);
+                writer.write_code(ll_codes.code(length.symbol))?;
+                writer.write_bits(length.extra_bits as u32, length.extra_bit_count)?;
+                let distance = distance_symbol(distance);
+                writer.write_code(distance_codes.code(distance.symbol))?;
+                writer.write_bits(distance.extra_bits as u32, distance.extra_bit_count)?;
+            }
+        }
+    }
+    writer.write_code(ll_codes.code(END_OF_BLOCK))
+}
+
+// Encode a block type 1 (fixed codes).
+pub fn write_fixed_block<W: Write>(
+    writer: &mut BitWriter<W>,
+    instructions: &[Instruction],
+    is_final: bool,
+) -> io::Result<()> {
+    writer.write_bit(is_final)?;
+    writer.write_bits(1, 2)?;
+    write_instructions(
+        writer,
+        &SymbolToCodeTable::fixed_ll(),
+        &SymbolToCodeTable::fixed_distance(),
+        instructions,
+    )
+}
+
+// Write a non-final fixed block with no data, as zlib does for a partial flush.
+pub fn write_empty_fixed_block<W: Write>(writer: &mut BitWriter<W>) -> io::Result<()> {
+    write_fixed_block(writer, &[], false)
+}
+
+// Encode a block type 2 (dynamic codes).
+pub fn write_dynamic_block<W: Write>(
+    writer: &mut BitWriter<W>,
+    codes: &DynamicCodes,
+    instructions: &[Instruction],
+    is_final: bool,
+) -> io::Result<()> {
+    writer.write_bit(is_final)?;
+    writer.write_bits(2, 2)?;
+    codes.write_header(writer)?;
+    write_instructions(
+        writer,
+        &SymbolToCodeTable::from_code_lengths(&codes.ll_lengths),
+        &SymbolToCodeTable::from_code_lengths(&codes.distance_lengths),
+        instructions,
+    )
+}
+
+// Encode one or more blocks of type 0 (no compression). Only the last block is
+// marked as final.
+pub fn write_stored_blocks<W: Write>(
+    writer: &mut BitWriter<W>,
+    bytes: &[u8],
+    is_final: bool,
+) -> io::Result<()> {
+    let mut chunks = bytes.chunks(MAX_STORED_LENGTH).peekable();
+    if chunks.peek().is_none() {
+        return write_stored_block(writer, &[], is_final);
+    }
+    while let Some(chunk) = chunks.next() {
+        write_stored_block(writer, chunk, is_final && chunks.peek().is_none())?;
+    }
+    Ok(())
+}
+
+fn write_stored_block<W: Write>(
+    writer: &mut BitWriter<W>,
+    bytes: &[u8],
+    is_final: bool,
+) -> io::Result<()> {
+    writer.write_bit(is_final)?;
+    writer.write_bits(0, 2)?;
+    writer.align()?;
+    let length = bytes.len() as u16;
+    writer.write_u16(length)?;
+    writer.write_u16(!length)?;
+    writer.write_bytes(bytes)
+}
+
+// Size in bits of the stored blocks for `length` bytes, when starting with
+// `pending_bits` bits already written to the current byte.
+fn stored_cost(length: usize, pending_bits: u8) -> usize {
+    let block_count = length.div_ceil(MAX_STORED_LENGTH).max(1);
+    // The first header is padded to the end of the current byte, subsequent
+    // headers start on a byte boundary.
+    let first_padding = (8 - (pending_bits as usize + 3) % 8) % 8;
+    let padding = first_padding + (block_count - 1) * 5;
+    block_count * (3 + 32) + padding + 8 * length
+}
+
+// Encoded sizes in bits of a block in each of the block types.
+#[derive(Debug)]
+pub struct BlockCosts {
+    pub stored: usize,
+    pub fixed: usize,
+    pub dynamic: usize,
+}
+
+impl BlockCosts {
+    pub fn new(
+        frequencies: &Frequencies,
+        codes: &DynamicCodes,
+        byte_count: usize,
+        pending_bits: u8,
+    ) -> Self {
+        let dynamic_distance_lengths = {
+            let mut lengths = codes.distance_lengths.clone();
+            lengths.resize(DISTANCE_COUNT, 0);
+            lengths
+        };
+        let dynamic_ll_lengths = {
+            let mut lengths = codes.ll_lengths.clone();
+            lengths.resize(LL_COUNT, 0);
+            lengths
+        };
+        Self {
+            stored: stored_cost(byte_count, pending_bits),
+            fixed: 3 + frequencies.cost(&fixed_ll_lengths(), &[5; DISTANCE_COUNT]),
+            dynamic: 3
+                + codes.header_cost()
+                + frequencies.cost(&dynamic_ll_lengths, &dynamic_distance_lengths),
+        }
+    }
+
+    // The cheapest block type. Ties prefer the simpler block type.
+    pub fn best(&self) -> (BlockType, usize) {
+        if self.stored <= self.fixed && self.stored <= self.dynamic {
+            (BlockType::Stored, self.stored)
+        } else if self.fixed <= self.dynamic {
+            (BlockType::Fixed, self.fixed)
+        } else {
+            (BlockType::Dynamic, self.dynamic)
+        }
+    }
+}
+
+// Encode a block using whichever block type is smallest, only considering
+// stored and fixed blocks unless `allow_dynamic` is set. `bytes` holds the
+// uncompressed data that the instructions expand to.
+pub fn write_block<W: Write>(
+    writer: &mut BitWriter<W>,
+    instructions: &[Instruction],
+    bytes: &[u8],
+    is_final: bool,
+    allow_dynamic: bool,
+) -> io::Result<()> {
+    let frequencies = Frequencies::new(instructions);
+    let codes = DynamicCodes::new(&frequencies);
+    let mut costs = BlockCosts::new(&frequencies, &codes, bytes.len(), writer.pending_bits());
+    if !allow_dynamic {
+        costs.dynamic = usize::MAX;
+    }
+    match costs.best().0 {
+        BlockType::Stored => write_stored_blocks(writer, bytes, is_final),
+        BlockType::Fixed => write_fixed_block(writer, instructions, is_final),
+        BlockType::Dynamic => write_dynamic_block(writer, &codes, instructions, is_final),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_reader::{BitRead, BitReader};
+    use crate::block_decoder::BlockDecoder;
+    use crate::error::InflateResult;
+
+    #[test]
+    fn test_length_symbol() {
+        let symbol = |symbol, extra_bit_count, extra_bits| SymbolWithExtraBits {
+            symbol,
+            extra_bit_count,
+            extra_bits,
+        };
+        assert_eq!(length_symbol(3), symbol(257, 0, 0));
+        assert_eq!(length_symbol(10), symbol(264, 0, 0));
+        assert_eq!(length_symbol(12), symbol(265, 1, 1));
+        assert_eq!(length_symbol(257), symbol(284, 5, 30));
+        assert_eq!(length_symbol(258), symbol(285, 0, 0));
+    }
+
+    #[test]
+    fn test_distance_symbol() {
+        let symbol = |symbol, extra_bit_count, extra_bits| SymbolWithExtraBits {
+            symbol,
+            extra_bit_count,
+            extra_bits,
+        };
+        assert_eq!(distance_symbol(1), symbol(0, 0, 0));
+        assert_eq!(distance_symbol(4), symbol(3, 0, 0));
+        assert_eq!(distance_symbol(6), symbol(4, 1, 1));
+        assert_eq!(distance_symbol(32768), symbol(29, 13, 8191));
+    }
+
+    #[test]
+    fn test_fixed_block_round_trip() -> InflateResult<()> {
+        let instructions = vec![
+            Instruction::Literal(0),
+            Instruction::Literal(200),
+            Instruction::BackReference {
+                length: 258,
+                distance: 1,
+            },
+            Instruction::BackReference {
+                length: 100,
+                distance: 32768,
+            },
+        ];
+        let mut writer = BitWriter::new(Vec::new());
+        write_fixed_block(&mut writer, &instructions, true)?;
+        writer.align()?;
+        let raw = std::mem::take(writer.get_mut());
+
+        let mut reader = BitReader::new(raw.as_slice());
+        assert!(reader.read_bit()?);
+        assert_eq!(reader.read_bits::<u8>(2)?, 1);
+        let mut decoder = BlockDecoder::new_fixed(&mut reader);
+        for instruction in instructions {
+            assert_eq!(decoder.next()?, instruction);
+        }
+        assert_eq!(decoder.next()?, Instruction::EndOfBlock);
+        Ok(())
+    }
+}
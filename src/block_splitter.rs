@@ -0,0 +1,212 @@
+use crate::bit_writer::BitWriter;
+use crate::block_encoder::{
+    BlockCosts, DISTANCE_COUNT, DynamicCodes, Frequencies, LL_COUNT, write_block,
+};
+use crate::lz77::Instruction;
+use std::io::{self, Write};
+
+// Blocks are only split at multiples of this many instructions.
+const SPLIT_STEP: usize = 512;
+
+// Rough cost in bits of describing one used symbol in a dynamic block header.
+const HEADER_BITS_PER_SYMBOL: f64 = 4.0;
+
+// Symbol counts and uncompressed size of all instructions before a candidate
+// split point.
+#[derive(Debug, Clone)]
+struct Prefix {
+    frequencies: Frequencies,
+    byte_count: usize,
+}
+
+// Statistics for the instructions between two split points.
+fn range_statistics(start: &Prefix, end: &Prefix) -> (Frequencies, usize) {
+    let mut frequencies = Frequencies::new(&[]);
+    for symbol in 0..LL_COUNT {
+        frequencies.ll[symbol] += end.frequencies.ll[symbol] - start.frequencies.ll[symbol];
+    }
+    for symbol in 0..DISTANCE_COUNT {
+        frequencies.distance[symbol] +=
+            end.frequencies.distance[symbol] - start.frequencies.distance[symbol];
+    }
+    (frequencies, end.byte_count - start.byte_count)
+}
+
+// Estimate of a dynamic block's size from the entropy of its symbols, which is
+// much cheaper to compute than building the actual codes.
+fn estimated_cost(frequencies: &Frequencies) -> f64 {
+    fn entropy_bits(counts: &[u32]) -> f64 {
+        let total: u32 = counts.iter().sum();
+        counts
+            .iter()
+            .filter(|&&count| count != 0)
+            .map(|&count| {
+                count as f64 * (total as f64 / count as f64).log2() + HEADER_BITS_PER_SYMBOL
+            })
+            .sum()
+    }
+    let extra_bits = frequencies.cost(&[0; LL_COUNT], &[0; DISTANCE_COUNT]);
+    entropy_bits(&frequencies.ll) + entropy_bits(&frequencies.distance) + extra_bits as f64
+}
+
+// Size in bits of a block with the given statistics, using the cheapest of the
+// stored, fixed and dynamic block types.
+fn actual_cost(frequencies: &Frequencies, byte_count: usize, allow_dynamic: bool) -> usize {
+    let codes = DynamicCodes::new(frequencies);
+    let mut costs = BlockCosts::new(frequencies, &codes, byte_count, 0);
+    if !allow_dynamic {
+        costs.dynamic = usize::MAX;
+    }
+    costs.best().1
+}
+
+// Split points within prefixes[start..=end], appended to `out` in order.
+fn split_range(
+    prefixes: &[Prefix],
+    start: usize,
+    end: usize,
+    allow_dynamic: bool,
+    out: &mut Vec<usize>,
+) {
+    if end - start < 2 {
+        return;
+    }
+    // The candidate that best separates the symbol statistics of the two
+    // sides.
+    let estimate =
+        |a: usize, b: usize| estimated_cost(&range_statistics(&prefixes[a], &prefixes[b]).0);
+    let middle = (start + 1..end)
+        .min_by(|&a, &b| {
+            let cost_a = estimate(start, a) + estimate(a, end);
+            let cost_b = estimate(start, b) + estimate(b, end);
+            cost_a.total_cmp(&cost_b)
+        })
+        .unwrap();
+
+    // Only split if the actual encoded size improves.
+    let cost = |a: usize, b: usize| {
+        let (frequencies, byte_count) = range_statistics(&prefixes[a], &prefixes[b]);
+        actual_cost(&frequencies, byte_count, allow_dynamic)
+    };
+    if cost(start, middle) + cost(middle, end) < cost(start, end) {
+        split_range(prefixes, start, middle, allow_dynamic, out);
+        out.push(middle);
+        split_range(prefixes, middle, end, allow_dynamic, out);
+    }
+}
+
+// Choose where to split a sequence of instructions into blocks, so that each
+// block gets Huffman codes suited to its own statistics. Returns the indices
+// of the instructions that start a new block, in increasing order. Unless
+// `allow_dynamic` is set, blocks are costed as stored or fixed blocks only.
+pub fn split_points(instructions: &[Instruction], allow_dynamic: bool) -> Vec<usize> {
+    let mut prefixes = vec![Prefix {
+        frequencies: Frequencies::new(&[]),
+        byte_count: 0,
+    }];
+    for chunk in instructions.chunks(SPLIT_STEP) {
+        let mut prefix = prefixes.last().unwrap().clone();
+        for instruction in chunk {
+            prefix.frequencies.add(instruction);
+        }
+        prefix.byte_count += byte_count(chunk);
+        prefixes.push(prefix);
+    }
+    let mut points = Vec::new();
+    split_range(&prefixes, 0, prefixes.len() - 1, allow_dynamic, &mut points);
+    points.iter().map(|&point| point * SPLIT_STEP).collect()
+}
+
+fn byte_count(instructions: &[Instruction]) -> usize {
+    instructions
+        .iter()
+        .map(|instruction| match *instruction {
+            Instruction::Literal(_) => 1,
+            Instruction::BackReference { length, .. } => length as usize,
+            Instruction::EndOfBlock => 0,
+        })
+        .sum()
+}
+
+// Encode the instructions as one or more blocks, split at `split_points()`,
+// each using whichever block type is smallest, as in `write_block()`. Only
+// the last block is marked as final.
+pub fn write_split_blocks<W: Write>(
+    writer: &mut BitWriter<W>,
+    instructions: &[Instruction],
+    bytes: &[u8],
+    is_final: bool,
+    allow_dynamic: bool,
+) -> io::Result<()> {
+    let points = split_points(instructions, allow_dynamic);
+    let mut start = 0;
+    let mut byte_start = 0;
+    for (i, &end) in points.iter().chain([instructions.len()].iter()).enumerate() {
+        let block = &instructions[start..end];
+        let byte_end = byte_start + byte_count(block);
+        let is_last = i == points.len();
+        write_block(
+            writer,
+            block,
+            &bytes[byte_start..byte_end],
+            is_final && is_last,
+            allow_dynamic,
+        )?;
+        start = end;
+        byte_start = byte_end;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::InflateResult;
+    use crate::inflate::RawInflator;
+
+    fn literals(bytes: impl IntoIterator<Item = u8>) -> Vec<Instruction> {
+        bytes.into_iter().map(Instruction::Literal).collect()
+    }
+
+    #[test]
+    fn test_uniform_input_is_not_split() {
+        let instructions = literals((0..20_000u32).map(|i| (i % 7) as u8));
+        assert_eq!(split_points(&instructions, true), vec![]);
+    }
+
+    #[test]
+    fn test_split_at_change_in_statistics() {
+        // Two halves that use disjoint sets of symbols.
+        let instructions = [
+            literals((0..8192u32).map(|i| b'a' + (i % 4) as u8)),
+            literals((0..8192u32).map(|i| 0x80 + (i * 7 % 64) as u8)),
+        ]
+        .concat();
+        assert_eq!(split_points(&instructions, true), vec![8192]);
+    }
+
+    #[test]
+    fn test_write_split_blocks() -> InflateResult<()> {
+        let bytes: Vec<u8> = (0..8192u32)
+            .map(|i| b'a' + (i % 4) as u8)
+            .chain((0..8192u32).map(|i| 0x80 + (i * 7 % 64) as u8))
+            .collect();
+        let instructions = literals(bytes.iter().copied());
+        let mut writer = BitWriter::new(Vec::new());
+        write_split_blocks(&mut writer, &instructions, &bytes, true, true)?;
+        writer.align()?;
+        let compressed = std::mem::take(writer.get_mut());
+
+        let mut inflator = RawInflator::new(compressed.as_slice());
+        assert_eq!(inflator.next_block()?, &bytes[..8192]);
+        assert_eq!(inflator.next_block()?, &bytes[8192..]);
+        assert!(inflator.is_finished());
+        Ok(())
+    }
+
+    #[test]
+    fn test_short_input() {
+        assert_eq!(split_points(&[], true), vec![]);
+        assert_eq!(split_points(&literals(*b"abc"), true), vec![]);
+    }
+}
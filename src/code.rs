@@ -1,6 +1,6 @@
 use std::fmt;
 
-#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Default)]
 pub struct Code {
     pub bits: u32,
     pub length: u8,
@@ -34,12 +34,6 @@ impl fmt::Debug for Code {
     }
 }
 
-impl Default for Code {
-    fn default() -> Self {
-        Code { bits: 0, length: 0 }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,10 +1,24 @@
 use crate::bit_reader::BitRead;
 use crate::code::Code;
-use crate::error::InflateResult;
+use crate::error::{InflateError, InflateResult};
 use std::collections::HashMap;
 
 pub type CodeLength = u8;
 
+// Longest code DEFLATE allows.
+pub const MAX_CODE_LENGTH: CodeLength = 15;
+
+// Whether the code lengths describe more codes than there is room for, so
+// that some codes would be prefixes of others.
+pub fn is_oversubscribed(code_lengths: &[CodeLength]) -> bool {
+    let space: u32 = code_lengths
+        .iter()
+        .filter(|&&length| length != 0)
+        .map(|&length| 1 << (MAX_CODE_LENGTH - length))
+        .sum();
+    space > 1 << MAX_CODE_LENGTH
+}
+
 // Each index is a code length, each value is the number of code lengths of that
 // value. The [0] value is always 0.
 fn code_length_counts(code_lengths: &[CodeLength]) -> Vec<u32> {
@@ -35,6 +49,101 @@ fn min_codes_by_length(code_lengths: &[CodeLength]) -> Vec<Code> {
     min_codes
 }
 
+// An item in one of the package-merge lists: either a single symbol, or a
+// package of two adjacent items from the list one level deeper.
+#[derive(Debug, Clone, Copy)]
+enum PackageMergeItem {
+    Leaf(usize),
+    Package,
+}
+
+// Compute optimal code lengths for the given symbol frequencies, such that no
+// code is longer than `max_length` bits. Symbols with a frequency of 0 are
+// assigned a length of 0 (unused).
+//
+// DEFLATE requires special handling for small alphabets: if no symbols are
+// used, all lengths are 0, and if only a single symbol is used, it is assigned
+// a code of length 1 rather than 0.
+//
+// Uses the package-merge algorithm:
+// https://en.wikipedia.org/wiki/Package-merge_algorithm
+//
+// Panics if the used symbols cannot fit in a code limited to `max_length`
+// bits.
+pub fn code_lengths_from_frequencies(
+    frequencies: &[u32],
+    max_length: CodeLength,
+) -> Vec<CodeLength> {
+    let mut lengths = vec![0; frequencies.len()];
+    // Used symbols, sorted by increasing frequency.
+    let mut leaves: Vec<(u64, usize)> = frequencies
+        .iter()
+        .enumerate()
+        .filter(|(_, f)| **f != 0)
+        .map(|(symbol, &f)| (f as u64, symbol))
+        .collect();
+    leaves.sort();
+
+    match leaves.len() {
+        0 => return lengths,
+        1 => {
+            lengths[leaves[0].1] = 1;
+            return lengths;
+        }
+        _ => {}
+    }
+    assert!(
+        max_length < 64 && leaves.len() as u64 <= 1 << max_length,
+        "{} symbols do not fit in a code of at most {} bits",
+        leaves.len(),
+        max_length
+    );
+
+    // lists[0] corresponds to the deepest level (codes of length `max_length`),
+    // and the final list corresponds to the top level.
+    let mut lists: Vec<Vec<(u64, PackageMergeItem)>> = Vec::new();
+    let leaf_items = || leaves.iter().map(|&(w, s)| (w, PackageMergeItem::Leaf(s)));
+    lists.push(leaf_items().collect());
+    for _ in 1..max_length {
+        let previous = lists.last().unwrap();
+        let packages = previous
+            .chunks_exact(2)
+            .map(|pair| (pair[0].0 + pair[1].0, PackageMergeItem::Package));
+        // Merge the leaves and packages by weight. Leaves go first on ties.
+        let mut merged = Vec::with_capacity(leaves.len() + previous.len() / 2);
+        let mut leaf_items = leaf_items().peekable();
+        let mut packages = packages.peekable();
+        loop {
+            let item = match (leaf_items.peek(), packages.peek()) {
+                (Some(leaf), Some(package)) if leaf.0 <= package.0 => leaf_items.next(),
+                (_, Some(_)) => packages.next(),
+                (Some(_), None) => leaf_items.next(),
+                (None, None) => break,
+            };
+            merged.push(item.unwrap());
+        }
+        lists.push(merged);
+    }
+
+    // Select the cheapest 2n - 2 items from the top list. Each time a symbol
+    // appears in a selected item (directly, or inside a selected package), its
+    // code length increases by one. The packages in a selected prefix are made
+    // up of a prefix of the list below, so we only need to track the prefix
+    // length at each level.
+    let mut selected = 2 * leaves.len() - 2;
+    for list in lists.iter().rev() {
+        let mut package_count = 0;
+        for (_, item) in &list[..selected] {
+            match item {
+                PackageMergeItem::Leaf(symbol) => lengths[*symbol] += 1,
+                PackageMergeItem::Package => package_count += 1,
+            }
+        }
+        selected = 2 * package_count;
+    }
+    lengths
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct SymbolToCodeTable(Vec<Code>);
 
@@ -42,10 +151,7 @@ impl SymbolToCodeTable {
     pub fn from_code_lengths(code_lengths: &[CodeLength]) -> Self {
         let mut codes = Vec::new();
         for &length in code_lengths {
-            codes.push(Code {
-                bits: 0,
-                length: length,
-            });
+            codes.push(Code { bits: 0, length });
         }
         let mut next_codes = min_codes_by_length(code_lengths);
         // Step 3 of algorithm from https://datatracker.ietf.org/doc/html/rfc1951#page-9
@@ -71,16 +177,24 @@ impl SymbolToCodeTable {
         Self::from_code_lengths(&[5; 32])
     }
 
+    pub(crate) fn code(&self, symbol: usize) -> Code {
+        self.0[symbol]
+    }
+
     pub fn inverse(&self) -> CodeToSymbolTable {
         let mut inverse = HashMap::new();
         for (symbol, code) in self.0.iter().enumerate() {
+            // Unused symbols have no code.
+            if code.length == 0 {
+                continue;
+            }
             inverse.insert(*code, symbol as u32);
         }
         CodeToSymbolTable(inverse)
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CodeToSymbolTable(HashMap<Code, u32>);
 
 impl CodeToSymbolTable {
@@ -102,6 +216,9 @@ impl CodeToSymbolTable {
             if let Some(&symbol) = self.0.get(&code) {
                 return Ok(symbol);
             }
+            if code.length == MAX_CODE_LENGTH {
+                return Err(InflateError::InvalidCode);
+            }
             code = code.append_bit(reader.read_bit()?);
         }
     }
@@ -117,6 +234,7 @@ impl<const N: usize> From<[(Code, u32); N]> for CodeToSymbolTable {
 mod tests {
     use super::*;
     use crate::bit_reader::BitReader;
+    use std::assert_matches;
 
     #[test]
     fn test_code_length_counts() {
@@ -140,6 +258,91 @@ mod tests {
         );
     }
 
+    // Sum of 2^-length over all used codes, scaled by 2^max_length.
+    fn kraft_sum(lengths: &[CodeLength], max_length: CodeLength) -> u64 {
+        lengths
+            .iter()
+            .filter(|&&l| l != 0)
+            .map(|&l| 1 << (max_length - l))
+            .sum()
+    }
+
+    #[test]
+    fn test_code_lengths_from_frequencies() {
+        let lengths = code_lengths_from_frequencies(&[10, 1, 1, 2, 0, 4], 15);
+        assert_eq!(lengths, vec![1, 4, 4, 3, 0, 2]);
+    }
+
+    #[test]
+    fn test_code_lengths_no_symbols() {
+        assert_eq!(code_lengths_from_frequencies(&[0, 0, 0], 15), vec![0, 0, 0]);
+        assert_eq!(code_lengths_from_frequencies(&[], 15), vec![]);
+    }
+
+    #[test]
+    fn test_code_lengths_single_symbol() {
+        assert_eq!(code_lengths_from_frequencies(&[0, 7, 0], 15), vec![0, 1, 0]);
+    }
+
+    #[test]
+    fn test_code_lengths_length_limited() {
+        // Fibonacci frequencies produce a maximally unbalanced Huffman tree.
+        let mut frequencies = vec![1, 1];
+        while frequencies.len() < 20 {
+            let n = frequencies.len();
+            frequencies.push(frequencies[n - 1] + frequencies[n - 2]);
+        }
+        let unlimited = code_lengths_from_frequencies(&frequencies, 32);
+        assert_eq!(*unlimited.iter().max().unwrap(), 19);
+        assert_eq!(kraft_sum(&unlimited, 32), 1 << 32);
+
+        let limited = code_lengths_from_frequencies(&frequencies, 7);
+        assert_eq!(*limited.iter().max().unwrap(), 7);
+        assert_eq!(kraft_sum(&limited, 7), 1 << 7);
+
+        // The limited code should cost more, but only a little.
+        let cost = |lengths: &[CodeLength]| -> u64 {
+            lengths
+                .iter()
+                .zip(&frequencies)
+                .map(|(&l, &f)| l as u64 * f as u64)
+                .sum()
+        };
+        assert!(cost(&limited) > cost(&unlimited));
+        assert!(cost(&limited) < cost(&unlimited) * 11 / 10);
+    }
+
+    #[test]
+    fn test_code_lengths_full_alphabet() {
+        // Every symbol used with a flat distribution fills the code exactly.
+        let lengths = code_lengths_from_frequencies(&[1; 16], 4);
+        assert_eq!(lengths, vec![4; 16]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_code_lengths_too_many_symbols() {
+        code_lengths_from_frequencies(&[1; 17], 4);
+    }
+
+    #[test]
+    fn test_code_lengths_round_trip() -> InflateResult<()> {
+        let lengths = code_lengths_from_frequencies(&[5, 9, 12, 13, 16, 45], 15);
+        let table = CodeToSymbolTable::from_code_lengths(&lengths);
+        let SymbolToCodeTable(codes) = SymbolToCodeTable::from_code_lengths(&lengths);
+        let mut bits = String::new();
+        for symbol in [5, 0, 3] {
+            bits += &format!("{:?}", codes[symbol]);
+        }
+        let raw = crate::bit_string::bit_string(&bits);
+        let raw: Vec<u8> = raw.iter().map(|b| b.reverse_bits()).collect();
+        let mut reader = BitReader::new(raw.as_slice());
+        assert_eq!(table.read_symbol(&mut reader)?, 5);
+        assert_eq!(table.read_symbol(&mut reader)?, 0);
+        assert_eq!(table.read_symbol(&mut reader)?, 3);
+        Ok(())
+    }
+
     #[test]
     fn test_from_code_lengths() {
         let code_lengths = &[3, 3, 3, 3, 3, 2, 4, 4];
@@ -212,6 +415,17 @@ mod tests {
     }
 
     #[test]
+    fn test_inverse_skips_unused_symbols() {
+        let code_lengths = &[0, 1, 0, 1];
+        let table = SymbolToCodeTable::from_code_lengths(code_lengths);
+        assert_eq!(
+            table.inverse(),
+            CodeToSymbolTable::from([(Code::from("0"), 1), (Code::from("1"), 3)])
+        )
+    }
+
+    #[test]
+    #[allow(clippy::unusual_byte_groupings)]
     fn test_read_code() -> InflateResult<()> {
         let table = CodeToSymbolTable::from([
             (Code::from("0"), 0),
@@ -226,4 +440,30 @@ mod tests {
         assert_eq!(reader.read_bits::<u8>(3)?, 0b010);
         Ok(())
     }
+
+    #[test]
+    fn test_read_unmatched_code() {
+        // Only "0" is a code, so no symbol starts with a 1.
+        let table = CodeToSymbolTable::from_code_lengths(&[1, 0]);
+        let raw: &[u8] = &[0xff, 0xff];
+        let mut reader = BitReader::new(raw);
+        assert_matches!(
+            table.read_symbol(&mut reader),
+            Err(InflateError::InvalidCode)
+        );
+        assert_eq!(reader.bit_position(), MAX_CODE_LENGTH as u64);
+    }
+
+    #[test]
+    fn test_is_oversubscribed() {
+        assert!(!is_oversubscribed(&[1, 2, 2]));
+        // Incomplete codes are fine.
+        assert!(!is_oversubscribed(&[1, 2, 0]));
+        assert!(!is_oversubscribed(&[0, 0]));
+        assert!(is_oversubscribed(&[1, 1, 2]));
+        assert!(!is_oversubscribed(&[MAX_CODE_LENGTH; 1 << MAX_CODE_LENGTH]));
+        assert!(is_oversubscribed(
+            &[MAX_CODE_LENGTH; (1 << MAX_CODE_LENGTH) + 1]
+        ));
+    }
 }
@@ -0,0 +1,390 @@
+// CRC-32, as used in the gzip member trailer.
+// https://datatracker.ietf.org/doc/html/rfc1952#section-8
+
+// Reversed form of the polynomial, for processing bits LSB-first.
+const POLYNOMIAL: u32 = 0xedb8_8320;
+
+// Slicing-by-8 tables: TABLES[0] is the usual byte-at-a-time table, and
+// TABLES[k][i] is the CRC of byte i followed by k zero bytes, so that eight
+// bytes can be folded in with eight independent lookups.
+const TABLES: [[u32; 256]; 8] = {
+    let mut tables = [[0u32; 256]; 8];
+    let mut i = 0;
+    while i < 256 {
+        let mut value = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            value = if value & 1 != 0 {
+                (value >> 1) ^ POLYNOMIAL
+            } else {
+                value >> 1
+            };
+            bit += 1;
+        }
+        tables[0][i] = value;
+        i += 1;
+    }
+    let mut k = 1;
+    while k < 8 {
+        let mut i = 0;
+        while i < 256 {
+            let previous = tables[k - 1][i];
+            tables[k][i] = (previous >> 8) ^ tables[0][(previous & 0xff) as usize];
+            i += 1;
+        }
+        k += 1;
+    }
+    tables
+};
+
+// Inputs shorter than this use the tables, as the carry-less multiplication
+// path needs several 16-byte blocks to get going.
+#[cfg(target_arch = "x86_64")]
+const CLMUL_MIN_LENGTH: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crc32 {
+    // The CRC register, i.e. the inverted checksum.
+    value: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self { value: !0 }
+    }
+
+    // Resume from the checksum of some earlier data.
+    pub fn from_checksum(checksum: u32) -> Self {
+        Self { value: !checksum }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        #[cfg(target_arch = "x86_64")]
+        if data.len() >= CLMUL_MIN_LENGTH
+            && is_x86_feature_detected!("pclmulqdq")
+            && is_x86_feature_detected!("sse4.1")
+        {
+            // SAFETY: the CPU supports the required features.
+            self.value = unsafe { x86::update_clmul(self.value, data) };
+            return;
+        }
+        self.value = update_slicing(self.value, data);
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.value
+    }
+
+    // Checksum of the concatenation of the data checksummed by `self` and by
+    // `other`, where `other` covers `other_length` bytes.
+    pub fn combine(&self, other: &Crc32, other_length: usize) -> Crc32 {
+        // Appending n bytes multiplies the first CRC by x^(8n), and the
+        // pre- and post-conditioning cancel out in the sum.
+        let shifted = multiply(x_to_power(8 * other_length as u64), self.finish());
+        Crc32 {
+            value: !(shifted ^ other.finish()),
+        }
+    }
+}
+
+// CRC-32 of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+// CRC-32 of the concatenation of two pieces of data, given the checksum of
+// each and the length of the second, like zlib's crc32_combine().
+pub fn crc32_combine(first: u32, second: u32, second_length: usize) -> u32 {
+    Crc32::from_checksum(first)
+        .combine(&Crc32::from_checksum(second), second_length)
+        .finish()
+}
+
+fn update_bytewise(mut value: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        value = TABLES[0][((value ^ byte as u32) & 0xff) as usize] ^ (value >> 8);
+    }
+    value
+}
+
+// Update the CRC register eight bytes at a time.
+fn update_slicing(mut value: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(8);
+    for chunk in chunks.by_ref() {
+        let low = u32::from_le_bytes(chunk[..4].try_into().unwrap()) ^ value;
+        let high = u32::from_le_bytes(chunk[4..].try_into().unwrap());
+        value = TABLES[7][(low & 0xff) as usize]
+            ^ TABLES[6][((low >> 8) & 0xff) as usize]
+            ^ TABLES[5][((low >> 16) & 0xff) as usize]
+            ^ TABLES[4][(low >> 24) as usize]
+            ^ TABLES[3][(high & 0xff) as usize]
+            ^ TABLES[2][((high >> 8) & 0xff) as usize]
+            ^ TABLES[1][((high >> 16) & 0xff) as usize]
+            ^ TABLES[0][(high >> 24) as usize];
+    }
+    update_bytewise(value, chunks.remainder())
+}
+
+// Carry-less multiplication: the data is folded 64 bytes at a time into four
+// 128-bit accumulators, which are then folded into one and reduced to 32 bits
+// with a Barrett reduction. See Intel's "Fast CRC Computation for Generic
+// Polynomials Using PCLMULQDQ Instruction". The constants are powers of x
+// modulo the polynomial, bit-reflected.
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use super::update_slicing;
+    use std::arch::x86_64::*;
+
+    // x^(4*128+32) and x^(4*128-32), for folding by 512 bits.
+    const K1: i64 = 0x1_5444_2bd4;
+    const K2: i64 = 0x1_c6e4_1596;
+    // x^(128+32) and x^(128-32), for folding by 128 bits.
+    const K3: i64 = 0x1_7519_97d0;
+    const K4: i64 = 0x0_ccaa_009e;
+    // x^64, for reducing 96 bits to 64.
+    const K5: i64 = 0x1_63cd_6124;
+    // The polynomial, and floor(x^64 / polynomial).
+    const P_X: i64 = 0x1_db71_0641;
+    const U_PRIME: i64 = 0x1_f701_1641;
+
+    #[target_feature(enable = "pclmulqdq,sse4.1")]
+    fn fold(accumulator: __m128i, next: __m128i, keys: __m128i) -> __m128i {
+        let low = _mm_clmulepi64_si128(accumulator, keys, 0x00);
+        let high = _mm_clmulepi64_si128(accumulator, keys, 0x11);
+        _mm_xor_si128(_mm_xor_si128(next, low), high)
+    }
+
+    #[target_feature(enable = "pclmulqdq,sse4.1")]
+    fn load(block: &[u8]) -> __m128i {
+        assert!(block.len() >= 16);
+        // SAFETY: `block` has at least 16 bytes.
+        unsafe { _mm_loadu_si128(block.as_ptr().cast()) }
+    }
+
+    // Update the CRC register with `data`, which must be at least 64 bytes.
+    #[target_feature(enable = "pclmulqdq,sse4.1")]
+    pub fn update_clmul(value: u32, data: &[u8]) -> u32 {
+        let mut x3 = _mm_xor_si128(load(data), _mm_cvtsi32_si128(value as i32));
+        let mut x2 = load(&data[16..]);
+        let mut x1 = load(&data[32..]);
+        let mut x0 = load(&data[48..]);
+        let mut data = &data[64..];
+
+        let k1k2 = _mm_set_epi64x(K2, K1);
+        while data.len() >= 64 {
+            x3 = fold(x3, load(data), k1k2);
+            x2 = fold(x2, load(&data[16..]), k1k2);
+            x1 = fold(x1, load(&data[32..]), k1k2);
+            x0 = fold(x0, load(&data[48..]), k1k2);
+            data = &data[64..];
+        }
+
+        let k3k4 = _mm_set_epi64x(K4, K3);
+        let mut x = fold(x3, x2, k3k4);
+        x = fold(x, x1, k3k4);
+        x = fold(x, x0, k3k4);
+        while data.len() >= 16 {
+            x = fold(x, load(data), k3k4);
+            data = &data[16..];
+        }
+
+        // Reduce 128 bits to 64, multiplied by x^32.
+        let low_32 = _mm_set_epi32(0, 0, 0, !0);
+        x = _mm_xor_si128(_mm_clmulepi64_si128(x, k3k4, 0x10), _mm_srli_si128(x, 8));
+        x = _mm_xor_si128(
+            _mm_clmulepi64_si128(_mm_and_si128(x, low_32), _mm_set_epi64x(0, K5), 0x00),
+            _mm_srli_si128(x, 4),
+        );
+
+        // Barrett reduction to 32 bits.
+        let pu = _mm_set_epi64x(U_PRIME, P_X);
+        let t1 = _mm_clmulepi64_si128(_mm_and_si128(x, low_32), pu, 0x10);
+        let t2 = _mm_clmulepi64_si128(_mm_and_si128(t1, low_32), pu, 0x00);
+        let value = _mm_extract_epi32(_mm_xor_si128(x, t2), 1) as u32;
+        update_slicing(value, data)
+    }
+}
+
+// Product of two polynomials modulo the CRC polynomial, in the reflected
+// representation where the MSB holds the x^0 coefficient.
+fn multiply(a: u32, mut b: u32) -> u32 {
+    let mut product = 0;
+    let mut mask = 1 << 31;
+    while mask != 0 {
+        if a & mask != 0 {
+            product ^= b;
+        }
+        mask >>= 1;
+        b = if b & 1 != 0 {
+            (b >> 1) ^ POLYNOMIAL
+        } else {
+            b >> 1
+        };
+    }
+    product
+}
+
+// x^n modulo the CRC polynomial, by repeated squaring.
+fn x_to_power(mut n: u64) -> u32 {
+    let mut result = 1 << 31;
+    // x^1, then x^2, x^4, ...
+    let mut square = 1 << 30;
+    while n != 0 {
+        if n & 1 != 0 {
+            result = multiply(square, result);
+        }
+        square = multiply(square, square);
+        n >>= 1;
+    }
+    result
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The definition, one bit at a time.
+    fn reference(data: &[u8]) -> u32 {
+        let mut value = !0u32;
+        for &byte in data {
+            value ^= byte as u32;
+            for _ in 0..8 {
+                value = if value & 1 != 0 {
+                    (value >> 1) ^ POLYNOMIAL
+                } else {
+                    value >> 1
+                };
+            }
+        }
+        !value
+    }
+
+    fn test_data(length: usize) -> Vec<u8> {
+        let mut state: u32 = 1;
+        (0..length)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(
+            crc32(b"The quick brown fox jumps over the lazy dog"),
+            0x414f_a339
+        );
+    }
+
+    #[test]
+    fn test_known_vectors() {
+        let vectors: [(&[u8], u32); 8] = [
+            (b"a", 0xe8b7_be43),
+            (b"abc", 0x3524_41c2),
+            (b"message digest", 0x2015_9d7f),
+            (b"abcdefghijklmnopqrstuvwxyz", 0x4c27_50bd),
+            (
+                b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789",
+                0x1fc2_e6d2,
+            ),
+            (&[0; 32], 0x190a_55ad),
+            (&[0xff; 32], 0xff6c_ab0b),
+            (&[b'a'; 1_000_000], 0xdc25_bfbc),
+        ];
+        for (data, expected) in vectors {
+            assert_eq!(crc32(data), expected, "{:?}", &data[..data.len().min(16)]);
+        }
+    }
+
+    #[test]
+    fn test_every_byte_value() {
+        for byte in 0..=255u8 {
+            assert_eq!(crc32(&[byte]), reference(&[byte]));
+            assert_eq!(crc32(&[byte; 200]), reference(&[byte; 200]));
+        }
+    }
+
+    #[test]
+    fn test_all_lengths_and_alignments() {
+        let data = test_data(1100);
+        for offset in 0..16 {
+            for length in 0..=data.len() - offset {
+                let bytes = &data[offset..offset + length];
+                assert_eq!(crc32(bytes), reference(bytes), "{} {}", offset, length);
+            }
+        }
+    }
+
+    #[test]
+    fn test_slicing_matches_bytewise() {
+        let data = test_data(10_000);
+        for length in [0, 1, 7, 8, 9, 63, 64, 1000, data.len()] {
+            let bytes = &data[..length];
+            assert_eq!(
+                update_slicing(0x1234_5678, bytes),
+                update_bytewise(0x1234_5678, bytes)
+            );
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_clmul_path() {
+        if !is_x86_feature_detected!("pclmulqdq") || !is_x86_feature_detected!("sse4.1") {
+            return;
+        }
+        let data = test_data(100_000);
+        for length in (64..300).chain([1000, 4096, 65_537, data.len()]) {
+            let bytes = &data[..length];
+            // SAFETY: the CPU supports the required features.
+            let value = unsafe { x86::update_clmul(0x8765_4321, bytes) };
+            assert_eq!(value, update_slicing(0x8765_4321, bytes), "{}", length);
+        }
+    }
+
+    #[test]
+    fn test_incremental() {
+        let data = b"incremental updates give the same result";
+        let mut crc = Crc32::new();
+        for chunk in data.chunks(7) {
+            crc.update(chunk);
+        }
+        assert_eq!(crc.finish(), crc32(data));
+        let data = test_data(5000);
+        let mut crc = Crc32::from_checksum(crc32(&data[..1234]));
+        crc.update(&data[1234..]);
+        assert_eq!(crc.finish(), crc32(&data));
+    }
+
+    #[test]
+    fn test_combine() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i * 131 % 251) as u8).collect();
+        for split in [0, 1, 7, 4096, 99_999, 100_000] {
+            let (first, second) = data.split_at(split);
+            let mut a = Crc32::new();
+            a.update(first);
+            let mut b = Crc32::new();
+            b.update(second);
+            assert_eq!(a.combine(&b, second.len()).finish(), crc32(&data));
+            assert_eq!(
+                crc32_combine(crc32(first), crc32(second), second.len()),
+                crc32(&data)
+            );
+        }
+    }
+}
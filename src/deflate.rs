@@ -0,0 +1,755 @@
+use crate::bit_writer::BitWriter;
+use crate::block_encoder::{write_block, write_empty_fixed_block, write_stored_blocks};
+use crate::block_splitter::write_split_blocks;
+use crate::lz77::Instruction;
+use crate::match_finder::{MIN_MATCH, MatchFinder, MatchParams, Parsing, WINDOW_SIZE};
+use crate::optimal::optimal_parse;
+use std::io;
+
+// Maximum number of instructions buffered before a block is emitted. Same as
+// zlib's default literal buffer size.
+pub(crate) const BLOCK_INSTRUCTIONS: usize = 16 * 1024 - 1;
+
+// With block splitting, more instructions are buffered so that there's more
+// room to choose block boundaries.
+const SPLIT_BLOCK_INSTRUCTIONS: usize = 4 * BLOCK_INSTRUCTIONS;
+
+// Without compression every instruction is a literal, so blocks can be as
+// large as a stored block allows.
+const STORED_BLOCK_INSTRUCTIONS: usize = u16::MAX as usize;
+
+// Input is fed to the match finder in pieces of at most this size, so that the
+// amount of buffered input stays bounded.
+const INPUT_CHUNK_SIZE: usize = WINDOW_SIZE;
+
+// With the Filtered strategy, shorter matches are emitted as literals.
+const FILTERED_MIN_LENGTH: usize = 6;
+
+// Level of Compression::extreme(), beyond zlib's range.
+const EXTREME_LEVEL: u8 = 10;
+
+// In rsyncable mode, the rolling hash covers this many bits, one per input
+// byte, so boundaries depend on the last 12 bytes and occur every 4 KiB on
+// average. Same as pigz.
+const RSYNC_BITS: u32 = 12;
+const RSYNC_MASK: u32 = (1 << RSYNC_BITS) - 1;
+// Hash value that marks a boundary. Runs of a single byte never produce it.
+const RSYNC_HIT: u32 = RSYNC_MASK >> 1;
+
+// Rolling hash over the input that picks content-defined boundaries for
+// rsyncable mode.
+#[derive(Debug, Clone, Default)]
+struct RsyncHash(u32);
+
+impl RsyncHash {
+    // Feed bytes from `input` up to and including the next boundary, and
+    // return the number of bytes consumed, or None if there is no boundary.
+    fn next_boundary(&mut self, input: &[u8]) -> Option<usize> {
+        for (i, &byte) in input.iter().enumerate() {
+            self.0 = ((self.0 << 1) ^ byte as u32) & RSYNC_MASK;
+            if self.0 == RSYNC_HIT {
+                return Some(i + 1);
+            }
+        }
+        None
+    }
+}
+
+// Compression level from 0 (no compression) to 9 (best compression), with the
+// same trade-offs as zlib's levels, plus an extreme level above 9.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression(u8);
+
+impl Compression {
+    // Panics if `level` is greater than 9.
+    pub const fn new(level: u8) -> Self {
+        assert!(level <= 9, "Compression level must be between 0 and 9");
+        Self(level)
+    }
+
+    pub const fn none() -> Self {
+        Self(0)
+    }
+
+    pub const fn fast() -> Self {
+        Self(1)
+    }
+
+    pub const fn best() -> Self {
+        Self(9)
+    }
+
+    // Optimal parsing in the style of Zopfli: typically a few percent smaller
+    // than level 9, but around a hundred times slower. Reported as level 10.
+    pub const fn extreme() -> Self {
+        Self(EXTREME_LEVEL)
+    }
+
+    pub fn is_extreme(&self) -> bool {
+        self.0 == EXTREME_LEVEL
+    }
+
+    pub fn level(&self) -> u8 {
+        self.0
+    }
+
+    // Match finder settings from zlib's configuration table. The extreme level
+    // uses level 9's settings to split the input into blocks.
+    pub fn match_params(&self) -> MatchParams {
+        let (parsing, good_length, max_lazy, nice_length, max_chain) = match self.0 {
+            0 => (Parsing::Greedy, 0, 0, 0, 0),
+            1 => (Parsing::Greedy, 4, 4, 8, 4),
+            2 => (Parsing::Greedy, 4, 5, 16, 8),
+            3 => (Parsing::Greedy, 4, 6, 32, 32),
+            4 => (Parsing::Lazy, 4, 4, 16, 16),
+            5 => (Parsing::Lazy, 8, 16, 32, 32),
+            6 => (Parsing::Lazy, 8, 16, 128, 128),
+            7 => (Parsing::Lazy, 8, 32, 128, 256),
+            8 => (Parsing::Lazy, 32, 128, 258, 1024),
+            _ => (Parsing::Lazy, 32, 258, 258, 4096),
+        };
+        MatchParams {
+            parsing,
+            max_chain,
+            good_length,
+            nice_length,
+            max_lazy,
+            min_length: MIN_MATCH,
+        }
+    }
+
+    // Value of the FLEVEL field in the zlib stream header.
+    pub fn header_level(&self) -> u8 {
+        match self.0 {
+            0..=1 => 0,
+            2..=5 => 1,
+            6 => 2,
+            _ => 3,
+        }
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self(6)
+    }
+}
+
+// How much of the buffered input to compress and write out, with the same
+// semantics as zlib's flush modes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flush {
+    // Compress whatever is convenient, holding back input to find better
+    // matches.
+    None,
+    // Compress all buffered input, then write an empty fixed block so that a
+    // decoder can decode everything up to the previous block. The output may
+    // not end on a byte boundary.
+    Partial,
+    // Compress all buffered input, then write an empty stored block, so that
+    // the output ends on a byte boundary.
+    Sync,
+    // Same as Sync, but also reset the match history, so that decoding can
+    // restart from this point.
+    Full,
+    // Compress all buffered input and write the final block.
+    Finish,
+}
+
+// How instructions are produced and block types chosen, with the same
+// semantics as zlib's strategies. None of them have any effect at level 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strategy {
+    #[default]
+    Default,
+    // Drop short matches in favour of literals, for data with small values in
+    // a roughly random distribution, such as filtered image data. Only has an
+    // effect at levels 4 and above, where lazy matching is used.
+    Filtered,
+    // No matches, only Huffman coding of the bytes.
+    HuffmanOnly,
+    // Only matches at distance 1, i.e. runs of the same byte. Nearly as fast
+    // as HuffmanOnly, but better for data like image residuals.
+    Rle,
+    // Never use dynamic Huffman codes, so that the decoder's tables are
+    // always the same.
+    Fixed,
+}
+
+impl Strategy {
+    // The match finder settings for `compression` under this strategy.
+    fn match_params(&self, compression: Compression) -> MatchParams {
+        let mut params = compression.match_params();
+        match self {
+            Strategy::Filtered => params.min_length = FILTERED_MIN_LENGTH,
+            Strategy::HuffmanOnly => params.parsing = Parsing::Literals,
+            Strategy::Rle => params.parsing = Parsing::Rle,
+            Strategy::Default | Strategy::Fixed => {}
+        }
+        params
+    }
+}
+
+// Raw DEFLATE compressor. Compressed output accumulates in an internal buffer,
+// which callers drain with `take_output()`.
+#[derive(Debug)]
+pub struct Deflater {
+    compression: Compression,
+    strategy: Strategy,
+    finder: MatchFinder,
+    // Instructions for the current block.
+    instructions: Vec<Instruction>,
+    output: BitWriter<Vec<u8>>,
+    finished: bool,
+    block_splitting: bool,
+    // Set in rsyncable mode.
+    rsync: Option<RsyncHash>,
+}
+
+impl Deflater {
+    pub fn new(compression: Compression) -> Self {
+        Self::with_strategy(compression, Strategy::Default)
+    }
+
+    pub fn with_strategy(compression: Compression, strategy: Strategy) -> Self {
+        let mut finder = MatchFinder::new(strategy.match_params(compression));
+        finder.retain_from_position();
+        Self {
+            compression,
+            strategy,
+            finder,
+            instructions: Vec::new(),
+            output: BitWriter::new(Vec::new()),
+            finished: false,
+            block_splitting: false,
+            rsync: None,
+        }
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    // Switch to a different level and strategy for the input that follows,
+    // like zlib's deflateParams(). Buffered input is compressed with the old
+    // settings and ends the current block. Back-references into earlier input
+    // remain possible.
+    pub fn set_params(&mut self, compression: Compression, strategy: Strategy) -> io::Result<()> {
+        if self.finished {
+            return Err(io::Error::other(
+                "Parameters changed after the stream was finished",
+            ));
+        }
+        if compression == self.compression && strategy == self.strategy {
+            return Ok(());
+        }
+        self.compress(true)?;
+        if !self.instructions.is_empty() {
+            self.write_block(false)?;
+        }
+        self.compression = compression;
+        self.strategy = strategy;
+        self.finder.set_params(strategy.match_params(compression));
+        Ok(())
+    }
+
+    // With block splitting enabled, the encoder buffers more input and divides
+    // it into blocks where the symbol statistics change, so that each block
+    // gets its own Huffman codes. This helps with heterogeneous input, at
+    // some cost in speed. Applies from the next block onwards.
+    pub fn set_block_splitting(&mut self, enabled: bool) {
+        self.block_splitting = enabled;
+    }
+
+    pub fn block_splitting(&self) -> bool {
+        self.block_splitting
+    }
+
+    // In rsyncable mode, like `gzip --rsyncable`, the encoder does a full
+    // flush at boundaries chosen by a rolling hash of the input. The output
+    // after a boundary only depends on the input after it, so a local edit
+    // changes the compressed stream only up to the next boundary, at a small
+    // cost in compression.
+    pub fn set_rsyncable(&mut self, enabled: bool) {
+        self.rsync = enabled.then(RsyncHash::default);
+    }
+
+    pub fn rsyncable(&self) -> bool {
+        self.rsync.is_some()
+    }
+
+    // Whether the final block has been written.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    // Prime the window with `dictionary`, so that the start of the input can
+    // reference it. Only its last 32 KiB are used. Replaces any earlier
+    // dictionary. Panics if any input has already been written.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) {
+        self.finder.set_dictionary(dictionary);
+        self.finder.retain_from_position();
+    }
+
+    // Insert `count` bits of `value` at the start of the output, before the
+    // first block, like zlib's deflatePrime(). Used to continue a stream whose
+    // last byte is only partly filled.
+    pub fn prime(&mut self, value: u32, count: u8) -> io::Result<()> {
+        self.output.write_bits(value, count)
+    }
+
+    // Compress `input`. Some of it may be held back until more input arrives
+    // or the stream is flushed.
+    pub fn write(&mut self, input: &[u8]) -> io::Result<()> {
+        if self.finished {
+            return Err(io::Error::other("Write after the stream was finished"));
+        }
+        let mut input = input;
+        while !input.is_empty() {
+            let boundary = self
+                .rsync
+                .as_mut()
+                .and_then(|hash| hash.next_boundary(input));
+            let (part, rest) = input.split_at(boundary.unwrap_or(input.len()));
+            for chunk in part.chunks(INPUT_CHUNK_SIZE) {
+                self.finder.push(chunk);
+                self.compress(false)?;
+            }
+            if boundary.is_some() {
+                self.flush(Flush::Full)?;
+            }
+            input = rest;
+        }
+        Ok(())
+    }
+
+    // Flushing a finished stream does nothing.
+    pub fn flush(&mut self, flush: Flush) -> io::Result<()> {
+        if self.finished || flush == Flush::None {
+            return Ok(());
+        }
+        self.compress(true)?;
+        if flush == Flush::Finish {
+            self.write_block(true)?;
+            self.finished = true;
+            return self.output.align();
+        }
+        if !self.instructions.is_empty() {
+            self.write_block(false)?;
+        }
+        if flush == Flush::Partial {
+            return write_empty_fixed_block(&mut self.output);
+        }
+        write_stored_blocks(&mut self.output, &[], false)?;
+        if flush == Flush::Full {
+            self.finder.reset();
+        }
+        Ok(())
+    }
+
+    // Write bytes directly to the output, e.g. for a container format's header
+    // or trailer. The output must be on a byte boundary.
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.output.write_bytes(bytes)
+    }
+
+    // Compressed bytes produced so far. A partially written byte is kept back.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(self.output.get_mut())
+    }
+
+    // Run the match finder over the buffered input, emitting blocks as they
+    // fill up.
+    fn compress(&mut self, drain: bool) -> io::Result<()> {
+        let limit = if self.compression.level() == 0 {
+            STORED_BLOCK_INSTRUCTIONS
+        } else if self.block_splitting {
+            SPLIT_BLOCK_INSTRUCTIONS
+        } else {
+            BLOCK_INSTRUCTIONS
+        };
+        loop {
+            self.finder.find(&mut self.instructions, limit, drain);
+            if self.instructions.len() < limit {
+                return Ok(());
+            }
+            self.write_block(false)?;
+        }
+    }
+
+    fn write_block(&mut self, is_final: bool) -> io::Result<()> {
+        let bytes = self.finder.retained();
+        if self.compression.level() == 0 {
+            write_stored_blocks(&mut self.output, bytes, is_final)?;
+        } else {
+            let optimal;
+            // HuffmanOnly and Rle take precedence over the level, as in zlib.
+            let reparse = matches!(
+                self.strategy,
+                Strategy::Default | Strategy::Filtered | Strategy::Fixed
+            );
+            let instructions = if self.compression.is_extreme() && reparse {
+                // Re-parse the block, ignoring the match finder's instructions.
+                let (data, start) = self.finder.retained_with_window();
+                optimal = optimal_parse(data, start);
+                &optimal
+            } else {
+                &self.instructions
+            };
+            let allow_dynamic = self.strategy != Strategy::Fixed;
+            if self.block_splitting {
+                write_split_blocks(
+                    &mut self.output,
+                    instructions,
+                    bytes,
+                    is_final,
+                    allow_dynamic,
+                )?;
+            } else {
+                write_block(
+                    &mut self.output,
+                    instructions,
+                    bytes,
+                    is_final,
+                    allow_dynamic,
+                )?;
+            }
+        }
+        self.instructions.clear();
+        self.finder.retain_from_position();
+        Ok(())
+    }
+}
+
+// Compress `input` into a raw DEFLATE stream.
+pub fn deflate(input: &[u8], compression: Compression) -> Vec<u8> {
+    let mut deflater = Deflater::new(compression);
+    // Writes to a Vec can't fail.
+    deflater.write(input).unwrap();
+    deflater.flush(Flush::Finish).unwrap();
+    deflater.take_output()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::InflateResult;
+    use crate::inflate::{RawInflator, inflate};
+
+    fn test_data(length: usize) -> Vec<u8> {
+        let words: [&[u8]; 8] = [
+            b"the ", b"quick ", b"brown ", b"fox ", b"jumps ", b"over ", b"lazy ", b"dog\n",
+        ];
+        let mut state: u32 = 1;
+        let mut data = Vec::new();
+        while data.len() < length {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            let r = (state >> 16) as usize;
+            if r.is_multiple_of(5) {
+                data.extend(format!("{} ", r % 1000).bytes());
+            } else {
+                data.extend_from_slice(words[r % words.len()]);
+            }
+        }
+        data.truncate(length);
+        data
+    }
+
+    #[test]
+    fn test_round_trip_all_levels() -> InflateResult<()> {
+        let data = test_data(200_000);
+        for level in 0..=9 {
+            let compressed = deflate(&data, Compression::new(level));
+            assert_eq!(inflate(&compressed)?, data, "level {}", level);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_input() -> InflateResult<()> {
+        for level in 0..=9 {
+            let compressed = deflate(&[], Compression::new(level));
+            assert_eq!(inflate(&compressed)?, vec![]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_level_zero_is_stored() {
+        let data = test_data(100_000);
+        let compressed = deflate(&data, Compression::none());
+        // Two stored blocks: one full and one partial.
+        assert_eq!(compressed.len(), data.len() + 2 * 5);
+    }
+
+    #[test]
+    fn test_higher_levels_compress_better() {
+        let data = test_data(200_000);
+        let size = |level| deflate(&data, Compression::new(level)).len();
+        assert!(size(1) < data.len() / 2);
+        assert!(size(6) < size(1));
+        assert!(size(9) <= size(6));
+    }
+
+    #[test]
+    fn test_extreme() -> InflateResult<()> {
+        let data = test_data(50_000);
+        let extreme = deflate(&data, Compression::extreme());
+        assert_eq!(inflate(&extreme)?, data);
+        assert!(extreme.len() < deflate(&data, Compression::best()).len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_incompressible_data_is_stored() -> InflateResult<()> {
+        let mut state: u32 = 7;
+        let data: Vec<u8> = (0..50_000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect();
+        let compressed = deflate(&data, Compression::default());
+        assert!(compressed.len() <= data.len() + 5 * data.len().div_ceil(BLOCK_INSTRUCTIONS));
+        assert_eq!(inflate(&compressed)?, data);
+        Ok(())
+    }
+
+    // Alternating runs of text and of binary data with a skewed byte
+    // distribution, which call for different codes.
+    fn mixed_data() -> Vec<u8> {
+        let mut state: u32 = 3;
+        let mut data = Vec::new();
+        for i in 0..8 {
+            if i % 2 == 0 {
+                data.extend(test_data(20_000 + 1000 * i));
+            } else {
+                data.extend((0..20_000).map(|_| {
+                    state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                    0xc0 + ((state >> 16) % 64) as u8 / (1 + (state >> 28) as u8 % 4)
+                }));
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_block_splitting() -> InflateResult<()> {
+        let data = mixed_data();
+        let compress = |block_splitting| {
+            let mut deflater = Deflater::new(Compression::default());
+            deflater.set_block_splitting(block_splitting);
+            deflater.write(&data).unwrap();
+            deflater.flush(Flush::Finish).unwrap();
+            deflater.take_output()
+        };
+        let split = compress(true);
+        assert_eq!(inflate(&split)?, data);
+        let unsplit = compress(false);
+        assert!(
+            split.len() < unsplit.len() * 98 / 100,
+            "{} vs {}",
+            split.len(),
+            unsplit.len()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_strategies_round_trip() -> InflateResult<()> {
+        let data = [test_data(30_000), vec![0; 1000], test_data(10_000)].concat();
+        let strategies = [
+            Strategy::Default,
+            Strategy::Filtered,
+            Strategy::HuffmanOnly,
+            Strategy::Rle,
+            Strategy::Fixed,
+        ];
+        for strategy in strategies {
+            for level in [0, 1, 6, 9] {
+                let mut deflater = Deflater::with_strategy(Compression::new(level), strategy);
+                deflater.write(&data)?;
+                deflater.flush(Flush::Finish)?;
+                let compressed = deflater.take_output();
+                assert_eq!(
+                    inflate(&compressed)?,
+                    data,
+                    "{:?} level {}",
+                    strategy,
+                    level
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn compress_with_strategy(data: &[u8], strategy: Strategy) -> Vec<u8> {
+        let mut deflater = Deflater::with_strategy(Compression::default(), strategy);
+        deflater.write(data).unwrap();
+        deflater.flush(Flush::Finish).unwrap();
+        deflater.take_output()
+    }
+
+    #[test]
+    fn test_huffman_only_and_rle() {
+        // Runs of bytes separated by noise, like image residuals.
+        let mut state: u32 = 5;
+        let mut data = Vec::new();
+        while data.len() < 50_000 {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            let byte = (state >> 24) as u8 % 16;
+            let run = (state >> 16) as usize % 20;
+            data.extend(std::iter::repeat_n(byte, run));
+        }
+        let huffman_only = compress_with_strategy(&data, Strategy::HuffmanOnly);
+        let rle = compress_with_strategy(&data, Strategy::Rle);
+        assert!(rle.len() < huffman_only.len());
+        // Without matches, the output is at most one bit per byte smaller.
+        assert!(huffman_only.len() >= data.len() / 8);
+    }
+
+    #[test]
+    fn test_fixed_strategy() {
+        let data = test_data(20_000);
+        let compressed = compress_with_strategy(&data, Strategy::Fixed);
+        // A single final fixed block.
+        assert_eq!(compressed[0] & 0b111, 0b011);
+        assert!(compressed.len() > compress_with_strategy(&data, Strategy::Default).len());
+    }
+
+    #[test]
+    fn test_dictionary() -> InflateResult<()> {
+        let dictionary = test_data(10_000);
+        let data = test_data(1000);
+        let mut deflater = Deflater::new(Compression::default());
+        deflater.set_dictionary(&dictionary);
+        deflater.write(&data)?;
+        deflater.flush(Flush::Finish)?;
+        let compressed = deflater.take_output();
+        assert!(compressed.len() < deflate(&data, Compression::default()).len() / 4);
+
+        let mut inflator = RawInflator::new(compressed.as_slice());
+        inflator.set_dictionary(&dictionary);
+        let mut out = Vec::new();
+        inflator.read_to_end(&mut out)?;
+        assert_eq!(out, data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_params() -> InflateResult<()> {
+        let text = test_data(10_000);
+        let mut state: u32 = 11;
+        let noise: Vec<u8> = (0..10_000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect();
+
+        let mut deflater = Deflater::new(Compression::best());
+        let mut compressed = Vec::new();
+        deflater.write(&text)?;
+        deflater.set_params(Compression::none(), Strategy::Default)?;
+        assert_eq!(deflater.compression(), Compression::none());
+        compressed.extend(deflater.take_output());
+
+        // The noise is stored.
+        deflater.write(&noise)?;
+        deflater.set_params(Compression::fast(), Strategy::Rle)?;
+        let stored = deflater.take_output();
+        assert!(stored.len() >= noise.len());
+        assert!(stored.len() <= noise.len() + 10);
+        compressed.extend(stored);
+
+        // The window carries over, so repeating the text costs little.
+        deflater.set_params(Compression::default(), Strategy::Default)?;
+        deflater.write(&text)?;
+        deflater.flush(Flush::Finish)?;
+        let repeated = deflater.take_output();
+        assert!(repeated.len() < deflate(&text, Compression::default()).len() / 4);
+        compressed.extend(repeated);
+
+        assert_eq!(
+            inflate(&compressed)?,
+            [text.as_slice(), &noise, &text].concat()
+        );
+        assert!(
+            deflater
+                .set_params(Compression::best(), Strategy::Default)
+                .is_err()
+        );
+        Ok(())
+    }
+
+    fn compress_rsyncable(data: &[u8], rsyncable: bool) -> io::Result<Vec<u8>> {
+        let mut deflater = Deflater::new(Compression::default());
+        deflater.set_rsyncable(rsyncable);
+        // Boundaries don't depend on how the input is split into writes.
+        for piece in data.chunks(7000) {
+            deflater.write(piece)?;
+        }
+        deflater.flush(Flush::Finish)?;
+        Ok(deflater.take_output())
+    }
+
+    fn common_suffix_length(a: &[u8], b: &[u8]) -> usize {
+        a.iter()
+            .rev()
+            .zip(b.iter().rev())
+            .take_while(|(x, y)| x == y)
+            .count()
+    }
+
+    #[test]
+    fn test_rsyncable() -> InflateResult<()> {
+        let data = test_data(200_000);
+        let mut edited = data.clone();
+        edited.splice(1000..1004, *b"EDIT");
+
+        let compressed = compress_rsyncable(&data, true)?;
+        let edited_compressed = compress_rsyncable(&edited, true)?;
+        assert_eq!(inflate(&compressed)?, data);
+        assert_eq!(inflate(&edited_compressed)?, edited);
+        // Everything after the first boundary past the edit is the same. The
+        // test data is repetitive, so boundaries are sparser than usual.
+        let common = common_suffix_length(&compressed, &edited_compressed);
+        assert!(common > compressed.len() * 3 / 4, "{}", common);
+        // At a small cost in size.
+        let plain = compress_rsyncable(&data, false)?;
+        assert!(compressed.len() < plain.len() * 105 / 100);
+        assert!(common_suffix_length(&plain, &compress_rsyncable(&edited, false)?) < 100);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rsync_boundaries() {
+        // Runs of one byte never hit a boundary.
+        assert_eq!(RsyncHash::default().next_boundary(&[0; 100_000]), None);
+        assert_eq!(RsyncHash::default().next_boundary(&[0xff; 100_000]), None);
+        // Boundaries only depend on the last few bytes.
+        let data = test_data(100_000);
+        let first = RsyncHash::default().next_boundary(&data).unwrap();
+        assert!(first > RSYNC_BITS as usize);
+        assert_eq!(RsyncHash(0x5a5).next_boundary(&data), Some(first));
+    }
+
+    #[test]
+    fn test_header_level() {
+        let levels: Vec<u8> = (0..=9)
+            .map(|level| Compression::new(level).header_level())
+            .collect();
+        assert_eq!(levels, vec![0, 0, 1, 1, 1, 1, 2, 3, 3, 3]);
+        assert_eq!(Compression::extreme().header_level(), 3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_level() {
+        Compression::new(10);
+    }
+}
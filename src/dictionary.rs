@@ -0,0 +1,270 @@
+use crate::adler32::Adler32;
+use crate::match_finder::WINDOW_SIZE;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+// Largest useful preset dictionary: the encoder only keeps one window.
+pub const MAX_DICTIONARY_SIZE: usize = WINDOW_SIZE;
+
+// Substrings are tracked as overlapping strings of this many bytes.
+const GRAM_LENGTH: usize = 6;
+
+// A substring that occurs in several samples, with its estimated value.
+#[derive(Debug)]
+struct Candidate<'a> {
+    bytes: &'a [u8],
+    // Number of samples the substring is estimated to occur in.
+    frequency: usize,
+}
+
+impl Candidate<'_> {
+    // Bytes saved across the corpus, roughly.
+    fn score(&self) -> usize {
+        self.frequency * self.bytes.len()
+    }
+}
+
+// Number of samples each gram occurs in.
+fn gram_frequencies<S: AsRef<[u8]>>(samples: &[S]) -> HashMap<&[u8], usize> {
+    // Gram -> (frequency, index of the last sample it was seen in).
+    let mut frequencies: HashMap<&[u8], (usize, usize)> = HashMap::new();
+    for (index, sample) in samples.iter().enumerate() {
+        for gram in sample.as_ref().windows(GRAM_LENGTH) {
+            let entry = frequencies.entry(gram).or_insert((0, usize::MAX));
+            if entry.1 != index {
+                *entry = (entry.0 + 1, index);
+            }
+        }
+    }
+    frequencies
+        .into_iter()
+        .map(|(gram, (frequency, _))| (gram, frequency))
+        .collect()
+}
+
+// For thresholds of 2, 4, 8, ... samples, the maximal substrings of the
+// samples whose grams all occur in at least that many samples. A substring
+// occurs in at most as many samples as its least common gram, which is used
+// as its frequency.
+fn find_candidates<'a, S: AsRef<[u8]>>(
+    samples: &'a [S],
+    frequencies: &HashMap<&[u8], usize>,
+) -> Vec<Candidate<'a>> {
+    let mut thresholds = vec![2];
+    while thresholds.last().unwrap() * 2 <= samples.len() {
+        thresholds.push(thresholds.last().unwrap() * 2);
+    }
+
+    // Substring -> highest frequency found for it.
+    let mut candidates: HashMap<&[u8], usize> = HashMap::new();
+    for sample in samples {
+        let sample = sample.as_ref();
+        let gram_frequency: Vec<usize> = sample
+            .windows(GRAM_LENGTH)
+            .map(|gram| frequencies[gram])
+            .collect();
+        for &threshold in &thresholds {
+            let mut start = 0;
+            while start < gram_frequency.len() {
+                if gram_frequency[start] < threshold {
+                    start += 1;
+                    continue;
+                }
+                let end = start
+                    + gram_frequency[start..]
+                        .iter()
+                        .take_while(|&&f| f >= threshold)
+                        .count();
+                let frequency = *gram_frequency[start..end].iter().min().unwrap();
+                let bytes = &sample[start..end - 1 + GRAM_LENGTH];
+                let best = candidates.entry(bytes).or_default();
+                *best = (*best).max(frequency);
+                start = end;
+            }
+        }
+    }
+    candidates
+        .into_iter()
+        .map(|(bytes, frequency)| Candidate { bytes, frequency })
+        .collect()
+}
+
+// Build a preset dictionary of at most `max_size` bytes for compressing data
+// like `samples`. Sizes above MAX_DICTIONARY_SIZE are reduced to it.
+//
+// Substrings common to several samples are scored by their frequency times
+// their length, and the highest scoring ones are picked. Parts of a substring
+// that earlier picks already cover don't count towards its score, and are
+// left out. The highest scoring substrings are placed at the end of the
+// dictionary, where distances from the compressed data are shortest.
+pub fn train_dictionary<S: AsRef<[u8]>>(samples: &[S], max_size: usize) -> Vec<u8> {
+    let max_size = max_size.min(MAX_DICTIONARY_SIZE);
+    let frequencies = gram_frequencies(samples);
+    // Ties are broken by content so that the result is deterministic.
+    let mut queue: BinaryHeap<(usize, Reverse<&[u8]>, usize)> =
+        find_candidates(samples, &frequencies)
+            .into_iter()
+            .map(|candidate| {
+                (
+                    candidate.score(),
+                    Reverse(candidate.bytes),
+                    candidate.frequency,
+                )
+            })
+            .collect();
+
+    let mut chosen: Vec<&[u8]> = Vec::new();
+    let mut size = 0;
+    let mut covered: HashSet<&[u8]> = HashSet::new();
+    while let Some((score, Reverse(bytes), frequency)) = queue.pop() {
+        // Trim the parts already covered by earlier choices, and requeue the
+        // rest if that makes it less valuable than the next candidate.
+        let grams: Vec<&[u8]> = bytes.windows(GRAM_LENGTH).collect();
+        let Some(first) = grams.iter().position(|gram| !covered.contains(gram)) else {
+            continue;
+        };
+        let last = grams
+            .iter()
+            .rposition(|gram| !covered.contains(gram))
+            .unwrap();
+        let trimmed = &bytes[first..last + GRAM_LENGTH];
+        let candidate = Candidate {
+            bytes: trimmed,
+            frequency,
+        };
+        if candidate.score() < score {
+            queue.push((candidate.score(), Reverse(trimmed), frequency));
+            continue;
+        }
+        if size + trimmed.len() > max_size {
+            continue;
+        }
+        covered.extend(trimmed.windows(GRAM_LENGTH));
+        chosen.push(trimmed);
+        size += trimmed.len();
+        if max_size - size < GRAM_LENGTH {
+            break;
+        }
+    }
+    chosen
+        .iter()
+        .rev()
+        .flat_map(|bytes| bytes.iter().copied())
+        .collect()
+}
+
+// The DICTID that identifies `dictionary` in a zlib stream header.
+pub fn dictionary_id(dictionary: &[u8]) -> u32 {
+    let mut checksum = Adler32::new();
+    checksum.update(dictionary);
+    checksum.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::InflateResult;
+    use crate::{Compression, Inflator, ZlibEncoder};
+    use std::io::{self, Write};
+
+    // JSON messages with a shared structure and varying values.
+    fn messages(count: usize, seed: u32) -> Vec<Vec<u8>> {
+        let names = ["alice", "bob", "carol", "dave", "erin", "frank"];
+        let mut state = seed;
+        (0..count)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                let r = (state >> 8) as usize;
+                format!(
+                    r#"{{"user": {{"id": {}, "name": "{}", "email": "{}@example.com"}}, "status": "{}", "retries": {}}}"#,
+                    r % 100_000,
+                    names[r % names.len()],
+                    names[(r >> 4) % names.len()],
+                    if r.is_multiple_of(3) { "active" } else { "suspended" },
+                    r % 7
+                )
+                .into_bytes()
+            })
+            .collect()
+    }
+
+    fn compress(message: &[u8], dictionary: Option<&[u8]>) -> io::Result<Vec<u8>> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        if let Some(dictionary) = dictionary {
+            encoder.set_dictionary(dictionary)?;
+        }
+        encoder.write_all(message)?;
+        encoder.finish()
+    }
+
+    #[test]
+    fn test_dictionary_halves_size() -> InflateResult<()> {
+        let dictionary = train_dictionary(&messages(500, 1), 4096);
+        assert!(!dictionary.is_empty());
+        assert!(dictionary.len() <= 4096);
+
+        let mut plain_size = 0;
+        let mut dictionary_size = 0;
+        for message in messages(50, 2) {
+            let compressed = compress(&message, Some(&dictionary))?;
+            let mut inflator = Inflator::try_new(compressed.as_slice())?;
+            assert_eq!(inflator.dictionary_id(), Some(dictionary_id(&dictionary)));
+            inflator.set_dictionary(&dictionary)?;
+            assert_eq!(inflator.next_block()?, message);
+            dictionary_size += compressed.len();
+            plain_size += compress(&message, None)?.len();
+        }
+        assert!(
+            dictionary_size * 2 < plain_size,
+            "{} vs {}",
+            dictionary_size,
+            plain_size
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_most_valuable_at_end() {
+        // The long string occurs in every sample, the short one in half,
+        // separated by bytes unique to each sample.
+        let samples: Vec<Vec<u8>> = (0..20u8)
+            .map(|i| {
+                let unique = [128 + i; 8];
+                let mut sample =
+                    [&unique, b" common to every sample ".as_slice(), &unique].concat();
+                if i % 2 == 0 {
+                    sample.extend_from_slice(b" half ");
+                }
+                sample
+            })
+            .collect();
+        let dictionary = train_dictionary(&samples, 1000);
+        assert!(dictionary.ends_with(b" common to every sample "));
+        assert!(dictionary.starts_with(b" half "));
+    }
+
+    #[test]
+    fn test_dedupe() {
+        let samples = vec![b"prefix shared-string suffix".to_vec(); 10];
+        let dictionary = train_dictionary(&samples, 1000);
+        assert_eq!(dictionary, samples[0]);
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let samples = messages(200, 3);
+        assert!(train_dictionary(&samples, 100).len() <= 100);
+        assert!(train_dictionary(&samples, 1 << 20).len() <= MAX_DICTIONARY_SIZE);
+    }
+
+    #[test]
+    fn test_no_common_substrings() {
+        let samples = [b"abcdefgh".as_slice(), b"ijklmnop", b"qrstuvwx"];
+        assert_eq!(train_dictionary(&samples, 1000), vec![]);
+    }
+
+    #[test]
+    fn test_dictionary_id() {
+        assert_eq!(dictionary_id(b"Wikipedia"), 0x11e6_0398);
+    }
+}
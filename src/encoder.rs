@@ -0,0 +1,846 @@
+use crate::deflate::{Compression, Deflater, Flush, Strategy};
+use crate::format::{Format, Framing};
+use crate::gzip::GzHeader;
+use std::io::{self, Read, Write};
+
+// Size of the reads ZlibEncoderReader makes from its input.
+const READ_CHUNK_SIZE: usize = 32 * 1024;
+
+// Encoder state shared by the writer and reader adapters: the DEFLATE
+// compressor wrapped in a container format's header and trailer.
+#[derive(Debug)]
+struct EncoderCore {
+    deflater: Deflater,
+    framing: Framing,
+    // The header is written along with the first data, so that a dictionary
+    // can still be set until then.
+    header_written: bool,
+}
+
+impl EncoderCore {
+    fn new(format: Format, compression: Compression, strategy: Strategy) -> Self {
+        Self::with_framing(Framing::new(format), compression, strategy)
+    }
+
+    fn with_framing(framing: Framing, compression: Compression, strategy: Strategy) -> Self {
+        Self {
+            deflater: Deflater::with_strategy(compression, strategy),
+            framing,
+            header_written: false,
+        }
+    }
+
+    fn set_dictionary(&mut self, dictionary: &[u8]) -> io::Result<()> {
+        if self.header_written {
+            return Err(io::Error::other(
+                "The dictionary must be set before any data is written",
+            ));
+        }
+        self.framing.set_dictionary(dictionary)?;
+        self.deflater.set_dictionary(dictionary);
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            let header = self.framing.header(self.deflater.compression());
+            self.deflater.write_bytes(&header)?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+
+    fn is_finished(&self) -> bool {
+        self.deflater.is_finished()
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.write_header()?;
+        self.deflater.write(buf)?;
+        self.framing.update(buf);
+        Ok(())
+    }
+
+    // Flush::Finish writes the final block and the trailer.
+    fn flush(&mut self, flush: Flush) -> io::Result<()> {
+        self.write_header()?;
+        if flush == Flush::Finish && !self.deflater.is_finished() {
+            self.deflater.flush(Flush::Finish)?;
+            self.deflater.write_bytes(&self.framing.trailer())
+        } else {
+            self.deflater.flush(flush)
+        }
+    }
+
+    fn take_output(&mut self) -> Vec<u8> {
+        self.deflater.take_output()
+    }
+}
+
+// Compresses data written to it into a zlib stream, which is written to the
+// underlying writer as it is produced. At most a window's worth of input plus
+// one block is buffered internally.
+//
+// The stream is finished when the encoder is dropped, but errors are then
+// ignored. Call `finish()` or `try_finish()` to handle them.
+#[derive(Debug)]
+pub struct ZlibEncoder<W: Write> {
+    // None once `finish()` has returned the writer.
+    output: Option<W>,
+    core: EncoderCore,
+    // Compressed bytes not yet accepted by the underlying writer.
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> ZlibEncoder<W> {
+    pub fn new(output: W, compression: Compression) -> Self {
+        Self::with_strategy(output, compression, Strategy::Default)
+    }
+
+    pub fn with_strategy(output: W, compression: Compression, strategy: Strategy) -> Self {
+        Self::with_core(
+            output,
+            EncoderCore::new(Format::Zlib, compression, strategy),
+        )
+    }
+
+    fn with_core(output: W, core: EncoderCore) -> Self {
+        Self {
+            output: Some(output),
+            core,
+            buffer: Vec::new(),
+        }
+    }
+
+    // See Deflater::set_block_splitting().
+    pub fn set_block_splitting(&mut self, enabled: bool) {
+        self.core.deflater.set_block_splitting(enabled);
+    }
+
+    // See Deflater::set_rsyncable().
+    pub fn set_rsyncable(&mut self, enabled: bool) {
+        self.core.deflater.set_rsyncable(enabled);
+    }
+
+    // Compress with a preset dictionary: data that is likely to occur in the
+    // input, such as common strings of a message format. The decoder needs
+    // the same dictionary. Replaces any earlier dictionary, like zlib's
+    // deflateSetDictionary(). Fails if any data has been written or flushed.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) -> io::Result<()> {
+        self.core.set_dictionary(dictionary)
+    }
+
+    // Switch level and strategy for the data written from now on, like zlib's
+    // deflateParams(). Data written so far is compressed with the old settings
+    // and passed on to the underlying writer.
+    pub fn set_params(&mut self, compression: Compression, strategy: Strategy) -> io::Result<()> {
+        self.core.deflater.set_params(compression, strategy)?;
+        self.write_output()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.output.as_ref().unwrap()
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        self.output.as_mut().unwrap()
+    }
+
+    // Compress all input written so far according to `flush`, and pass the
+    // compressed data to the underlying writer. Flush::Finish writes the
+    // final block and the Adler-32 trailer; any later writes fail.
+    pub fn flush_with(&mut self, flush: Flush) -> io::Result<()> {
+        self.core.flush(flush)?;
+        self.write_output()
+    }
+
+    // Write the final block and the trailer, keeping the underlying writer.
+    // Calling this again once it has succeeded does nothing.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        self.flush_with(Flush::Finish)
+    }
+
+    // Write the final block and the trailer, and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.output.take().unwrap())
+    }
+
+    // Pass compressed data through to the underlying writer.
+    fn write_output(&mut self) -> io::Result<()> {
+        self.buffer.extend(self.core.take_output());
+        self.drain_buffer()
+    }
+
+    // Pass buffered compressed data to the underlying writer, retrying when
+    // interrupted like write_all(). What it doesn't accept stays buffered.
+    fn drain_buffer(&mut self) -> io::Result<()> {
+        let output = self.output.as_mut().unwrap();
+        while !self.buffer.is_empty() {
+            let written = match output.write(&self.buffer) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => result?,
+            };
+            if written == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            self.buffer.drain(..written);
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ZlibEncoder<W> {
+    // Output left over from earlier calls is passed on first, and its errors
+    // returned before `buf` is touched. Once `buf` has been compressed, it has
+    // been consumed, so errors passing on its output can't be reported; that
+    // output stays buffered for the next call.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.drain_buffer()?;
+        self.core.write(buf)?;
+        self.buffer.extend(self.core.take_output());
+        let _ = self.drain_buffer();
+        Ok(buf.len())
+    }
+
+    // Performs a sync flush, so that everything written so far can be
+    // decoded by the other end.
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_with(Flush::Sync)?;
+        self.output.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write> Drop for ZlibEncoder<W> {
+    fn drop(&mut self) {
+        if self.output.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+// Compress all of `input` into `output` in the given format.
+pub(crate) fn encode<W: Write>(
+    input: &[u8],
+    output: W,
+    format: Format,
+    compression: Compression,
+) -> io::Result<W> {
+    let core = EncoderCore::new(format, compression, Strategy::Default);
+    let mut encoder = ZlibEncoder::with_core(output, core);
+    encoder.write_all(input)?;
+    encoder.finish()
+}
+
+// Compresses data written to it into a single gzip member, with the given
+// header fields and a CRC-32 and size trailer. Otherwise it behaves like
+// ZlibEncoder, which it is built on; gzip has no preset dictionaries.
+#[derive(Debug)]
+pub struct GzEncoder<W: Write> {
+    inner: ZlibEncoder<W>,
+}
+
+impl<W: Write> GzEncoder<W> {
+    // An encoder whose header has no optional fields.
+    pub fn new(output: W, compression: Compression) -> Self {
+        Self::from_header(output, GzHeader::default(), compression)
+    }
+
+    // Fails with InvalidInput if the header can't be written, see
+    // GzHeader::validate().
+    pub fn with_header(output: W, header: GzHeader, compression: Compression) -> io::Result<Self> {
+        header.validate()?;
+        Ok(Self::from_header(output, header, compression))
+    }
+
+    fn from_header(output: W, header: GzHeader, compression: Compression) -> Self {
+        let core = EncoderCore::with_framing(Framing::gzip(header), compression, Strategy::Default);
+        Self {
+            inner: ZlibEncoder::with_core(output, core),
+        }
+    }
+
+    // See Deflater::set_block_splitting().
+    pub fn set_block_splitting(&mut self, enabled: bool) {
+        self.inner.set_block_splitting(enabled);
+    }
+
+    // See Deflater::set_rsyncable().
+    pub fn set_rsyncable(&mut self, enabled: bool) {
+        self.inner.set_rsyncable(enabled);
+    }
+
+    // See ZlibEncoder::set_params(). The header's XFL reflects the level the
+    // encoder was created with.
+    pub fn set_params(&mut self, compression: Compression, strategy: Strategy) -> io::Result<()> {
+        self.inner.set_params(compression, strategy)
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.get_mut()
+    }
+
+    // See ZlibEncoder::flush_with().
+    pub fn flush_with(&mut self, flush: Flush) -> io::Result<()> {
+        self.inner.flush_with(flush)
+    }
+
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        self.inner.try_finish()
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        self.inner.finish()
+    }
+}
+
+impl<W: Write> Write for GzEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Compresses the data read from an underlying reader into a zlib stream, for
+// handing uncompressed sources to APIs that consume compressed bytes.
+#[derive(Debug)]
+pub struct ZlibEncoderReader<R: Read> {
+    input: R,
+    core: EncoderCore,
+    // Compressed bytes not yet returned to the caller, starting at `position`.
+    buffer: Vec<u8>,
+    position: usize,
+    // Space for reading input, allocated on the first read.
+    chunk: Vec<u8>,
+}
+
+impl<R: Read> ZlibEncoderReader<R> {
+    pub fn new(input: R, compression: Compression) -> Self {
+        Self::with_strategy(input, compression, Strategy::Default)
+    }
+
+    pub fn with_strategy(input: R, compression: Compression, strategy: Strategy) -> Self {
+        Self {
+            input,
+            core: EncoderCore::new(Format::Zlib, compression, strategy),
+            buffer: Vec::new(),
+            position: 0,
+            chunk: Vec::new(),
+        }
+    }
+
+    // See Deflater::set_block_splitting().
+    pub fn set_block_splitting(&mut self, enabled: bool) {
+        self.core.deflater.set_block_splitting(enabled);
+    }
+
+    // See Deflater::set_rsyncable().
+    pub fn set_rsyncable(&mut self, enabled: bool) {
+        self.core.deflater.set_rsyncable(enabled);
+    }
+
+    // Compress with a preset dictionary: data that is likely to occur in the
+    // input, such as common strings of a message format. The decoder needs
+    // the same dictionary. Replaces any earlier dictionary, like zlib's
+    // deflateSetDictionary(). Fails if any data has been written or flushed.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) -> io::Result<()> {
+        self.core.set_dictionary(dictionary)
+    }
+
+    // Switch level and strategy for the input read from now on, like zlib's
+    // deflateParams().
+    pub fn set_params(&mut self, compression: Compression, strategy: Strategy) -> io::Result<()> {
+        self.core.deflater.set_params(compression, strategy)
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.input
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.input
+    }
+
+    pub fn into_inner(self) -> R {
+        self.input
+    }
+
+    // Read more input and compress it, until some output is available or the
+    // stream is finished.
+    fn fill_buffer(&mut self) -> io::Result<()> {
+        while self.position == self.buffer.len() && !self.core.is_finished() {
+            self.chunk.resize(READ_CHUNK_SIZE, 0);
+            let length = self.input.read(&mut self.chunk)?;
+            if length == 0 {
+                self.core.flush(Flush::Finish)?;
+            } else {
+                self.core.write(&self.chunk[..length])?;
+            }
+            self.buffer = self.core.take_output();
+            self.position = 0;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for ZlibEncoderReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.fill_buffer()?;
+        let available = &self.buffer[self.position..];
+        let length = available.len().min(buf.len());
+        buf[..length].copy_from_slice(&available[..length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Inflator;
+    use crate::error::InflateResult;
+    use crate::inflate::RawInflator;
+
+    fn compress(data: &[u8], compression: Compression) -> io::Result<Vec<u8>> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), compression);
+        encoder.write_all(data)?;
+        encoder.finish()
+    }
+
+    fn decompress(compressed: &[u8]) -> InflateResult<Vec<u8>> {
+        let mut inflator = Inflator::try_new(compressed)?;
+        let mut out = Vec::new();
+        while !inflator.is_finished() {
+            out.extend(inflator.next_block()?);
+        }
+        Ok(out)
+    }
+
+    // Decode the blocks of a raw DEFLATE stream that may be cut off, until
+    // `length` bytes have been produced.
+    fn decompress_prefix(raw: &[u8], length: usize) -> InflateResult<Vec<u8>> {
+        let mut inflator = RawInflator::new(raw);
+        let mut out = Vec::new();
+        while out.len() < length {
+            out.extend(inflator.next_block()?);
+        }
+        Ok(out)
+    }
+
+    // Writer that only accepts a few bytes per call.
+    struct TrickleWriter(Vec<u8>);
+
+    impl Write for TrickleWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let length = buf.len().min(3);
+            self.0.extend_from_slice(&buf[..length]);
+            Ok(length)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_round_trip() -> InflateResult<()> {
+        let data = b"hello hello hello hello world".repeat(100);
+        for level in 0..=9 {
+            let compressed = compress(&data, Compression::new(level))?;
+            assert_eq!(decompress(&compressed)?, data);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_header_level() -> InflateResult<()> {
+        for level in 0..=9 {
+            let compression = Compression::new(level);
+            let compressed = compress(b"abc", compression)?;
+            let inflator = Inflator::try_new(compressed.as_slice())?;
+            assert_eq!(
+                inflator.header.flags.compression_level,
+                compression.header_level()
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_zlib_default_stream() -> io::Result<()> {
+        // Same output as zlib.compress(b"") at the default level.
+        let compressed = compress(b"", Compression::default())?;
+        assert_eq!(compressed, [0x78, 0x9c, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_flush() -> InflateResult<()> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        let record = b"record: some log line\n";
+        encoder.write_all(record)?;
+        encoder.flush()?;
+        // The output ends with an empty stored block.
+        let compressed = encoder.get_ref().clone();
+        assert_eq!(&compressed[compressed.len() - 4..], [0, 0, 0xff, 0xff]);
+        assert_eq!(decompress_prefix(&compressed[2..], record.len())?, record);
+
+        // Flushing again with nothing new written adds another marker.
+        encoder.flush_with(Flush::Sync)?;
+        assert_eq!(encoder.get_ref().len(), compressed.len() + 5);
+
+        encoder.write_all(record)?;
+        let compressed = encoder.finish()?;
+        assert_eq!(decompress(&compressed)?, record.repeat(2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_full_flush() -> InflateResult<()> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        let record = b"the same record, repeated\n";
+        encoder.write_all(record)?;
+        encoder.flush_with(Flush::Full)?;
+        let flush_point = encoder.get_ref().len();
+        encoder.write_all(record)?;
+        let compressed = encoder.finish()?;
+        assert_eq!(decompress(&compressed)?, record.repeat(2));
+
+        // Everything after a full flush can be decoded on its own.
+        let trailer_start = compressed.len() - 4;
+        let mut rest = RawInflator::new(&compressed[flush_point..trailer_start]);
+        let mut out = Vec::new();
+        rest.read_to_end(&mut out)?;
+        assert_eq!(out, record);
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial_flush() -> InflateResult<()> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        let record = b"partial flush record\n";
+        encoder.write_all(record)?;
+        encoder.flush_with(Flush::Partial)?;
+        let compressed = encoder.get_ref().clone();
+        assert_eq!(decompress_prefix(&compressed[2..], record.len())?, record);
+
+        encoder.write_all(record)?;
+        let compressed = encoder.finish()?;
+        assert_eq!(decompress(&compressed)?, record.repeat(2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_finish() -> io::Result<()> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"abc")?;
+        encoder.try_finish()?;
+        let compressed = encoder.get_ref().clone();
+        // Finishing again is a no-op.
+        encoder.try_finish()?;
+        assert_eq!(encoder.get_ref(), &compressed);
+        assert!(encoder.write_all(b"more").is_err());
+        assert_eq!(encoder.finish()?, compressed);
+        Ok(())
+    }
+
+    #[test]
+    fn test_finish_on_drop() -> InflateResult<()> {
+        let mut compressed = Vec::new();
+        {
+            let mut encoder = ZlibEncoder::new(&mut compressed, Compression::default());
+            encoder.write_all(b"dropped")?;
+        }
+        assert_eq!(decompress(&compressed)?, b"dropped");
+        Ok(())
+    }
+
+    // Writer that fails every other call, accepting nothing.
+    struct FlakyWriter {
+        output: Vec<u8>,
+        fail: bool,
+        kind: io::ErrorKind,
+    }
+
+    impl Write for FlakyWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.fail = !self.fail;
+            if self.fail {
+                return Err(self.kind.into());
+            }
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_failed_writes() -> InflateResult<()> {
+        let data = b"retried writes ".repeat(5000);
+        for kind in [io::ErrorKind::Interrupted, io::ErrorKind::WouldBlock] {
+            let writer = FlakyWriter {
+                output: Vec::new(),
+                fail: false,
+                kind,
+            };
+            let mut encoder = GzEncoder::new(writer, Compression::default());
+            for chunk in data.chunks(1000) {
+                // Retrying a write that failed must not compress the data
+                // again.
+                loop {
+                    match encoder.write(chunk) {
+                        Ok(length) => {
+                            assert_eq!(length, chunk.len());
+                            break;
+                        }
+                        Err(e) => assert_eq!(e.kind(), kind),
+                    }
+                }
+            }
+            while encoder.try_finish().is_err() {}
+            let compressed = encoder.finish()?.output;
+            assert_eq!(crate::gzip_decompress(&compressed)?, data);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_pending_output_error() -> InflateResult<()> {
+        // Errors passing on earlier output are returned before any input is
+        // consumed.
+        let data = b"pending output ".repeat(5000);
+        let writer = FlakyWriter {
+            output: Vec::new(),
+            fail: false,
+            kind: io::ErrorKind::WouldBlock,
+        };
+        let mut encoder = ZlibEncoder::new(writer, Compression::default());
+        assert_eq!(encoder.write(&data)?, data.len());
+        // Make the next call fail too.
+        encoder.get_mut().fail = false;
+        assert_eq!(
+            encoder.write(b"more").unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        assert_eq!(encoder.write(b"more")?, 4);
+        while encoder.try_finish().is_err() {}
+        let compressed = encoder.finish()?.output;
+        assert_eq!(decompress(&compressed)?, [&data[..], b"more"].concat());
+        Ok(())
+    }
+
+    #[test]
+    fn test_replace_dictionary() -> InflateResult<()> {
+        let data = b"the second dictionary, not the first";
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.set_dictionary(b"the first dictionary")?;
+        encoder.set_dictionary(b"the second dictionary")?;
+        encoder.write_all(data)?;
+        assert!(encoder.set_dictionary(b"too late").is_err());
+        let compressed = encoder.finish()?;
+
+        let mut inflator = Inflator::try_new(compressed.as_slice())?;
+        assert!(inflator.set_dictionary(b"the first dictionary").is_err());
+        inflator.set_dictionary(b"the second dictionary")?;
+        let mut out = Vec::new();
+        inflator.read_to_end(&mut out)?;
+        assert_eq!(out, data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_short_writes() -> InflateResult<()> {
+        let data = b"short writes to the underlying writer ".repeat(50);
+        let mut encoder = ZlibEncoder::new(TrickleWriter(Vec::new()), Compression::default());
+        encoder.write_all(&data)?;
+        let compressed = encoder.finish()?.0;
+        assert_eq!(decompress(&compressed)?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_incremental_writes() -> InflateResult<()> {
+        let data: Vec<u8> = (0..300_000u32)
+            .map(|i| ((i % 251) ^ (i / 1000)) as u8)
+            .collect();
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        for chunk in data.chunks(1000) {
+            encoder.write_all(chunk)?;
+        }
+        let compressed = encoder.finish()?;
+        assert_eq!(decompress(&compressed)?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reader_matches_writer() -> io::Result<()> {
+        let data = b"compressing reader, compressing reader ".repeat(2000);
+        for level in [0, 1, 6, 9] {
+            let compression = Compression::new(level);
+            let mut reader = ZlibEncoderReader::new(data.as_slice(), compression);
+            let mut compressed = Vec::new();
+            reader.read_to_end(&mut compressed)?;
+            assert_eq!(compressed, compress(&data, compression)?, "level {}", level);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_reader_small_reads() -> InflateResult<()> {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 7 * i % 13) as u8).collect();
+        let mut reader = ZlibEncoderReader::new(data.as_slice(), Compression::default());
+        let mut compressed = Vec::new();
+        let mut buf = [0u8; 5];
+        loop {
+            let length = reader.read(&mut buf)?;
+            if length == 0 {
+                break;
+            }
+            compressed.extend_from_slice(&buf[..length]);
+        }
+        assert_eq!(decompress(&compressed)?, data);
+        // Reads past the end keep returning nothing.
+        assert_eq!(reader.read(&mut buf)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reader_empty_input() -> io::Result<()> {
+        let mut reader = ZlibEncoderReader::new(io::empty(), Compression::default());
+        let mut compressed = Vec::new();
+        reader.read_to_end(&mut compressed)?;
+        assert_eq!(compressed, [0x78, 0x9c, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reader_copy_to_writer() -> InflateResult<()> {
+        let data = b"piped through io::copy\n".repeat(500);
+        let mut reader = ZlibEncoderReader::new(data.as_slice(), Compression::fast());
+        let mut compressed = Vec::new();
+        io::copy(&mut reader, &mut compressed)?;
+        assert_eq!(decompress(&compressed)?, data);
+        assert!(reader.into_inner().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_splitting() -> InflateResult<()> {
+        let data = [
+            b"text text text ".repeat(2000),
+            (0..30_000u32).map(|i| ((i * i) >> 7) as u8).collect(),
+        ]
+        .concat();
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.set_block_splitting(true);
+        encoder.write_all(&data)?;
+        let compressed = encoder.finish()?;
+        assert_eq!(decompress(&compressed)?, data);
+
+        let mut reader = ZlibEncoderReader::new(data.as_slice(), Compression::default());
+        reader.set_block_splitting(true);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read)?;
+        assert_eq!(read, compressed);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rsyncable() -> InflateResult<()> {
+        let data: Vec<u8> = (0..100_000u32)
+            .flat_map(|i| format!("line {} of the log\n", i % 997).into_bytes())
+            .take(100_000)
+            .collect();
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.set_rsyncable(true);
+        encoder.write_all(&data)?;
+        let compressed = encoder.finish()?;
+        assert_eq!(decompress(&compressed)?, data);
+
+        let mut reader = ZlibEncoderReader::new(data.as_slice(), Compression::default());
+        reader.set_rsyncable(true);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read)?;
+        assert_eq!(read, compressed);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzip() -> InflateResult<()> {
+        let data = b"hello hello hello hello world".repeat(100);
+        let header = GzHeader::builder()
+            .filename("hello.txt")
+            .mtime(1_700_000_000)
+            .header_crc(true)
+            .build()?;
+        let header_bytes = header.to_bytes(Compression::best());
+        let mut encoder = GzEncoder::with_header(Vec::new(), header, Compression::best())?;
+        encoder.write_all(&data)?;
+        let compressed = encoder.finish()?;
+
+        assert!(compressed.starts_with(&header_bytes));
+        let body = &compressed[header_bytes.len()..compressed.len() - 8];
+        let mut out = Vec::new();
+        RawInflator::new(body).read_to_end(&mut out)?;
+        assert_eq!(out, data);
+        let mut framing = Framing::new(Format::Gzip);
+        framing.update(&data);
+        assert_eq!(compressed[compressed.len() - 8..], framing.trailer());
+        Ok(())
+    }
+
+    #[test]
+    fn test_strategy() -> InflateResult<()> {
+        let data = b"aaaaaaaabbbbbbbbbbbbaaaaaaaaaaaccccccc".repeat(100);
+        let mut encoder =
+            ZlibEncoder::with_strategy(Vec::new(), Compression::default(), Strategy::Rle);
+        encoder.write_all(&data)?;
+        let compressed = encoder.finish()?;
+        assert_eq!(decompress(&compressed)?, data);
+
+        let mut reader = ZlibEncoderReader::with_strategy(
+            data.as_slice(),
+            Compression::default(),
+            Strategy::Rle,
+        );
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read)?;
+        assert_eq!(read, compressed);
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_params() -> InflateResult<()> {
+        let text = b"some highly repetitive text, ".repeat(200);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(&text)?;
+        encoder.set_params(Compression::none(), Strategy::Default)?;
+        // The data written before the switch has been compressed and passed
+        // on, apart from the last partial byte.
+        let before = encoder.get_ref().len();
+        assert!(before > 2);
+        encoder.write_all(b"stored")?;
+        encoder.set_params(Compression::best(), Strategy::Filtered)?;
+        encoder.write_all(&text)?;
+        let compressed = encoder.finish()?;
+        assert!(compressed.len() < before + 100);
+        assert_eq!(
+            decompress(&compressed)?,
+            [text.as_slice(), b"stored", &text].concat()
+        );
+        Ok(())
+    }
+}
@@ -8,11 +8,28 @@ pub enum InflateError {
     InvalidCompressionInfo(u8),
     InvalidCompressionMethod(u8),
     FlagCheckMismatch(u16),
-    UnimplementedBlockType(u8),
+    InvalidBlockType(u8),
     LengthComplementMismatch(u16, u16),
     InvalidLengthSymbol(u16),
     InvalidDistanceSymbol(u8),
     DynamicCodeMalformed,
+    OversubscribedCode,
+    InvalidCode,
+    DistanceTooFarBack(u16, usize),
+    Deflate64DistanceTooFarBack(u32, usize),
+    DistanceBeyondWindow(u32, usize),
+    TooManyLengthCodes(usize),
+    NoDistanceCodes,
+    ChecksumMismatch(u32, u32),
+    TruncatedTrailer(usize),
+    TrailingData,
+    DictionaryRequired(u32),
+    DictionaryMismatch(u32, u32),
+    UnexpectedDictionary,
+    InvalidGzipMagic(u8, u8),
+    InvalidGzipFlags(u8),
+    HeaderChecksumMismatch(u16, u16),
+    SizeMismatch(u32, u32),
 }
 
 impl From<io::Error> for InflateError {
@@ -30,7 +47,7 @@ impl fmt::Display for InflateError {
             InvalidCompressionInfo(i) => write!(f, "Invalid compression info value: {}", i),
             InvalidCompressionMethod(m) => write!(f, "Invalid compression method: {}", m),
             FlagCheckMismatch(c) => write!(f, "Flag checksum is not a multiple of 31: {}", c),
-            UnimplementedBlockType(b) => write!(f, "Unimplemented block type: {}", b),
+            InvalidBlockType(b) => write!(f, "Invalid block type: {}", b),
             LengthComplementMismatch(length, inverse_length) => write!(
                 f,
                 "Corrupted block length. Length: {}, Inverse length: {}",
@@ -41,6 +58,59 @@ impl fmt::Display for InflateError {
             DynamicCodeMalformed => {
                 write!(f, "First code length for dynamic code cannot be a repeat.")
             }
+            OversubscribedCode => write!(f, "Code lengths describe too many codes"),
+            InvalidCode => write!(f, "Input doesn't match any code"),
+            DistanceTooFarBack(distance, available) => write!(
+                f,
+                "Back-reference distance {} exceeds the {} bytes of history",
+                distance, available
+            ),
+            Deflate64DistanceTooFarBack(distance, available) => write!(
+                f,
+                "Deflate64 back-reference distance {} exceeds the {} bytes of history",
+                distance, available
+            ),
+            DistanceBeyondWindow(distance, window_size) => write!(
+                f,
+                "Back-reference distance {} exceeds the {}-byte window declared in the header",
+                distance, window_size
+            ),
+            TooManyLengthCodes(count) => {
+                write!(
+                    f,
+                    "Dynamic block declares {} length codes, at most 286 are valid",
+                    count
+                )
+            }
+            NoDistanceCodes => write!(f, "Dynamic block has no distance codes"),
+            ChecksumMismatch(expected, actual) => write!(
+                f,
+                "Checksum mismatch. Expected: {:#010x}, Actual: {:#010x}",
+                expected, actual
+            ),
+            TruncatedTrailer(length) => write!(f, "Stream ends {} bytes into the trailer", length),
+            TrailingData => write!(f, "Unexpected data after the end of the stream"),
+            DictionaryRequired(id) => write!(f, "Preset dictionary required: {:#010x}", id),
+            DictionaryMismatch(expected, actual) => write!(
+                f,
+                "Dictionary mismatch. Expected: {:#010x}, Actual: {:#010x}",
+                expected, actual
+            ),
+            UnexpectedDictionary => write!(f, "Stream doesn't use a preset dictionary"),
+            InvalidGzipMagic(id1, id2) => {
+                write!(f, "Not a gzip member: {:#04x} {:#04x}", id1, id2)
+            }
+            InvalidGzipFlags(flags) => write!(f, "Reserved gzip flags set: {:#04x}", flags),
+            HeaderChecksumMismatch(expected, actual) => write!(
+                f,
+                "Header checksum mismatch. Expected: {:#06x}, Actual: {:#06x}",
+                expected, actual
+            ),
+            SizeMismatch(expected, actual) => write!(
+                f,
+                "Size mismatch. Expected: {}, Actual: {}",
+                expected, actual
+            ),
         }
     }
 }
@@ -48,3 +118,39 @@ impl fmt::Display for InflateError {
 impl Error for InflateError {}
 
 pub type InflateResult<T> = Result<T, InflateError>;
+
+// Errors from encoding caller-supplied instructions.
+#[derive(Debug)]
+pub enum EncodeError {
+    IoError(io::Error),
+    InvalidLength(u16),
+    InvalidDistance(u16),
+    DistanceTooFarBack(u16, usize),
+}
+
+impl From<io::Error> for EncodeError {
+    fn from(error: io::Error) -> Self {
+        Self::IoError(error)
+    }
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use EncodeError::*;
+
+        match self {
+            IoError(e) => write!(f, "I/O error: {}", e),
+            InvalidLength(l) => write!(f, "Back-reference length must be 3 to 258: {}", l),
+            InvalidDistance(d) => write!(f, "Back-reference distance must be 1 to 32768: {}", d),
+            DistanceTooFarBack(distance, available) => write!(
+                f,
+                "Back-reference distance {} exceeds the {} bytes produced so far",
+                distance, available
+            ),
+        }
+    }
+}
+
+impl Error for EncodeError {}
+
+pub type EncodeResult<T> = Result<T, EncodeError>;
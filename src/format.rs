@@ -0,0 +1,216 @@
+use crate::adler32::Adler32;
+use crate::crc32::Crc32;
+use crate::deflate::Compression;
+use crate::gzip::GzHeader;
+use crate::header::{Flags, StreamHeader};
+use std::io;
+
+// Container around a DEFLATE stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    // No header or trailer.
+    Raw,
+    // RFC 1950: two-byte header and Adler-32 trailer.
+    Zlib,
+    // RFC 1952: gzip member header, and CRC-32 and size trailer.
+    Gzip,
+}
+
+impl Format {
+    // Size of the trailer after the DEFLATE stream.
+    pub(crate) fn trailer_size(&self) -> usize {
+        match self {
+            Format::Raw => 0,
+            Format::Zlib => 4,
+            Format::Gzip => 8,
+        }
+    }
+}
+
+// Produces the header and trailer of a container format, tracking the
+// checksum of the uncompressed data in between.
+#[derive(Debug)]
+pub(crate) enum Framing {
+    Raw,
+    Zlib {
+        checksum: Adler32,
+        dictionary_id: Option<u32>,
+    },
+    Gzip {
+        crc32: Crc32,
+        // The size is only kept modulo 2^32.
+        size: u32,
+        header: GzHeader,
+    },
+}
+
+impl Framing {
+    pub fn new(format: Format) -> Self {
+        match format {
+            Format::Raw => Self::Raw,
+            Format::Zlib => Self::Zlib {
+                checksum: Adler32::new(),
+                dictionary_id: None,
+            },
+            Format::Gzip => Self::gzip(GzHeader::default()),
+        }
+    }
+
+    // Gzip framing with the given member header.
+    pub fn gzip(header: GzHeader) -> Self {
+        Self::Gzip {
+            crc32: Crc32::new(),
+            size: 0,
+            header,
+        }
+    }
+
+    // Record that the stream is compressed with a preset dictionary. Raw
+    // streams leave it to the caller to tell the decoder, and gzip streams
+    // can't use one.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) -> io::Result<()> {
+        match self {
+            Self::Raw => Ok(()),
+            Self::Zlib { dictionary_id, .. } => {
+                let mut adler32 = Adler32::new();
+                adler32.update(dictionary);
+                *dictionary_id = Some(adler32.finish());
+                Ok(())
+            }
+            Self::Gzip { .. } => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "gzip streams don't support preset dictionaries",
+            )),
+        }
+    }
+
+    // Header for a stream compressed at `compression`.
+    pub fn header(&self, compression: Compression) -> Vec<u8> {
+        match self {
+            Self::Raw => Vec::new(),
+            Self::Zlib { dictionary_id, .. } => {
+                let header = StreamHeader::new(Flags {
+                    preset_dictionary: dictionary_id.is_some(),
+                    compression_level: compression.header_level(),
+                });
+                let mut bytes = <[u8; 2]>::from(&header).to_vec();
+                if let Some(id) = dictionary_id {
+                    bytes.extend(id.to_be_bytes());
+                }
+                bytes
+            }
+            Self::Gzip { header, .. } => header.to_bytes(compression),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Raw => {}
+            Self::Zlib { checksum, .. } => checksum.update(data),
+            Self::Gzip { crc32, size, .. } => {
+                crc32.update(data);
+                *size = size.wrapping_add(data.len() as u32);
+            }
+        }
+    }
+
+    // Extend the checksum with that of `other`, which covers the `length`
+    // bytes following the data seen so far. Both must be of the same format.
+    pub fn combine(&mut self, other: &Framing, length: usize) {
+        match (self, other) {
+            (Self::Raw, Self::Raw) => {}
+            (Self::Zlib { checksum, .. }, Self::Zlib { checksum: next, .. }) => {
+                *checksum = checksum.combine(next, length);
+            }
+            (
+                Self::Gzip { crc32, size, .. },
+                Self::Gzip {
+                    crc32: next,
+                    size: next_size,
+                    ..
+                },
+            ) => {
+                *crc32 = crc32.combine(next, length);
+                *size = size.wrapping_add(*next_size);
+            }
+            _ => panic!("Can't combine checksums of different formats"),
+        }
+    }
+
+    pub fn trailer(&self) -> Vec<u8> {
+        match self {
+            Self::Raw => Vec::new(),
+            Self::Zlib { checksum, .. } => checksum.finish().to_be_bytes().to_vec(),
+            Self::Gzip { crc32, size, .. } => {
+                [crc32.finish().to_le_bytes(), size.to_le_bytes()].concat()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zlib() {
+        let mut framing = Framing::new(Format::Zlib);
+        assert_eq!(framing.header(Compression::default()), [0x78, 0x9c]);
+        framing.update(b"Wikipedia");
+        assert_eq!(framing.trailer(), 0x11e6_0398u32.to_be_bytes());
+    }
+
+    #[test]
+    fn test_combine() {
+        for format in [Format::Raw, Format::Zlib, Format::Gzip] {
+            let mut whole = Framing::new(format);
+            whole.update(b"Wikipedia");
+            let mut first = Framing::new(format);
+            first.update(b"Wiki");
+            let mut second = Framing::new(format);
+            second.update(b"pedia");
+            first.combine(&second, 5);
+            assert_eq!(first.trailer(), whole.trailer());
+        }
+    }
+
+    #[test]
+    fn test_zlib_dictionary() -> io::Result<()> {
+        let mut framing = Framing::new(Format::Zlib);
+        framing.set_dictionary(b"Wikipedia")?;
+        // FLG has FDICT set, followed by DICTID.
+        assert_eq!(
+            framing.header(Compression::default()),
+            [0x78, 0xbb, 0x11, 0xe6, 0x03, 0x98]
+        );
+        // The dictionary isn't part of the checksummed data.
+        assert_eq!(framing.trailer(), 1u32.to_be_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzip_dictionary() {
+        let mut framing = Framing::new(Format::Gzip);
+        assert!(framing.set_dictionary(b"dictionary").is_err());
+    }
+
+    #[test]
+    fn test_gzip() {
+        let mut framing = Framing::new(Format::Gzip);
+        assert_eq!(
+            framing.header(Compression::best()),
+            [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 2, 255]
+        );
+        framing.update(b"1234");
+        framing.update(b"56789");
+        assert_eq!(framing.trailer(), [0x26, 0x39, 0xf4, 0xcb, 9, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_raw() {
+        let mut framing = Framing::new(Format::Raw);
+        framing.update(b"data");
+        assert!(framing.header(Compression::default()).is_empty());
+        assert!(framing.trailer().is_empty());
+    }
+}
@@ -0,0 +1,540 @@
+use crate::bit_reader::BitReader;
+use crate::block_decoder::Variant;
+use crate::crc32::Crc32;
+use crate::deflate::Compression;
+use crate::error::{InflateError, InflateResult};
+use crate::inflate::RawInflator;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+// Values of the gzip OS field, from RFC 1952 §2.3.1.
+pub const OS_FAT: u8 = 0;
+pub const OS_UNIX: u8 = 3;
+pub const OS_MACINTOSH: u8 = 7;
+pub const OS_NTFS: u8 = 11;
+pub const OS_UNKNOWN: u8 = 255;
+
+// Bits of the FLG byte.
+const FTEXT: u8 = 1 << 0;
+const FHCRC: u8 = 1 << 1;
+const FEXTRA: u8 = 1 << 2;
+const FNAME: u8 = 1 << 3;
+const FCOMMENT: u8 = 1 << 4;
+
+const KNOWN_FLAGS: u8 = FTEXT | FHCRC | FEXTRA | FNAME | FCOMMENT;
+
+// Largest size of the FEXTRA field, whose length is stored in two bytes.
+const MAX_EXTRA_SIZE: usize = u16::MAX as usize;
+
+// Value of the gzip XFL field for a compression level, using zlib's mapping:
+// 2 for maximum compression and 4 for the fastest levels, including stored.
+// zlib also reports 4 for the HuffmanOnly and Rle strategies; like FLEVEL in
+// zlib headers, this is only advisory, so the strategy isn't passed down.
+pub(crate) fn gzip_extra_flags(compression: Compression) -> u8 {
+    match compression.level() {
+        9.. => 2,
+        0 | 1 => 4,
+        _ => 0,
+    }
+}
+
+// A subfield of the FEXTRA field: a two-byte identifier and its data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtraField {
+    pub id: [u8; 2],
+    pub data: Vec<u8>,
+}
+
+// Fields of a gzip member header (RFC 1952 §2.3). XFL isn't stored, as it is
+// derived from the compression level when the header is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GzHeader {
+    // Modification time of the original file in seconds since the Unix
+    // epoch, or 0 if not available.
+    pub mtime: u32,
+    pub os: u8,
+    // Original file name and comment, without the terminating zero byte.
+    // RFC 1952 specifies ISO 8859-1, but most tools pass bytes through.
+    pub filename: Option<Vec<u8>>,
+    pub comment: Option<Vec<u8>>,
+    pub extra: Vec<ExtraField>,
+    // Whether to protect the header with a CRC-16.
+    pub header_crc: bool,
+}
+
+impl Default for GzHeader {
+    // A header without optional fields, as written by Format::Gzip encoders.
+    fn default() -> Self {
+        Self {
+            mtime: 0,
+            os: OS_UNKNOWN,
+            filename: None,
+            comment: None,
+            extra: Vec::new(),
+            header_crc: false,
+        }
+    }
+}
+
+impl GzHeader {
+    pub fn builder() -> GzHeaderBuilder {
+        GzHeaderBuilder::default()
+    }
+
+    // Check that the header can be serialized as given: to_bytes can't
+    // represent zero bytes in the strings or an FEXTRA field over 64 KiB.
+    // The total limit also bounds each subfield, as their lengths add up to it.
+    pub fn validate(&self) -> io::Result<()> {
+        let strings = [&self.filename, &self.comment];
+        if strings
+            .into_iter()
+            .flatten()
+            .any(|string| string.contains(&0))
+        {
+            return Err(invalid_input(
+                "gzip header strings can't contain zero bytes",
+            ));
+        }
+        // Identifiers with a zero second byte are reserved for future use.
+        if self.extra.iter().any(|field| field.id[1] == 0) {
+            return Err(invalid_input("Reserved gzip extra field identifier"));
+        }
+        if self.extra_size() > MAX_EXTRA_SIZE {
+            return Err(invalid_input("gzip extra field too large"));
+        }
+        Ok(())
+    }
+
+    // Serialize the header for a member compressed at `compression`. Lengths
+    // are truncated unless the header passes validate().
+    pub fn to_bytes(&self, compression: Compression) -> Vec<u8> {
+        let mut flags = 0;
+        if self.header_crc {
+            flags |= FHCRC;
+        }
+        if !self.extra.is_empty() {
+            flags |= FEXTRA;
+        }
+        if self.filename.is_some() {
+            flags |= FNAME;
+        }
+        if self.comment.is_some() {
+            flags |= FCOMMENT;
+        }
+        // ID1, ID2 and CM: deflate.
+        let mut bytes = vec![0x1f, 0x8b, 8, flags];
+        bytes.extend(self.mtime.to_le_bytes());
+        bytes.push(gzip_extra_flags(compression));
+        bytes.push(self.os);
+        if !self.extra.is_empty() {
+            bytes.extend((self.extra_size() as u16).to_le_bytes());
+            for field in &self.extra {
+                bytes.extend(field.id);
+                bytes.extend((field.data.len() as u16).to_le_bytes());
+                bytes.extend(&field.data);
+            }
+        }
+        for string in [&self.filename, &self.comment].into_iter().flatten() {
+            bytes.extend(string);
+            bytes.push(0);
+        }
+        if self.header_crc {
+            let mut crc32 = Crc32::new();
+            crc32.update(&bytes);
+            bytes.extend((crc32.finish() as u16).to_le_bytes());
+        }
+        bytes
+    }
+
+    // Parse a member header from `input`, verifying its CRC-16 if present.
+    // FTEXT is accepted but not recorded.
+    pub fn read_from<R: Read>(input: &mut R) -> InflateResult<Self> {
+        // All header bytes, for the CRC-16.
+        let mut bytes = vec![0u8; 10];
+        input.read_exact(&mut bytes)?;
+        let (id1, id2, method, flags) = (bytes[0], bytes[1], bytes[2], bytes[3]);
+        if (id1, id2) != (0x1f, 0x8b) {
+            return Err(InflateError::InvalidGzipMagic(id1, id2));
+        }
+        if method != 8 {
+            return Err(InflateError::InvalidCompressionMethod(method));
+        }
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(InflateError::InvalidGzipFlags(flags));
+        }
+        let mut header = GzHeader {
+            mtime: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            os: bytes[9],
+            header_crc: flags & FHCRC != 0,
+            ..GzHeader::default()
+        };
+
+        let mut read = |length: usize, bytes: &mut Vec<u8>| -> io::Result<Vec<u8>> {
+            let mut field = vec![0u8; length];
+            input.read_exact(&mut field)?;
+            bytes.extend(&field);
+            Ok(field)
+        };
+        if flags & FEXTRA != 0 {
+            let size = read(2, &mut bytes)?;
+            let extra = read(u16::from_le_bytes([size[0], size[1]]) as usize, &mut bytes)?;
+            // Subfields that don't fit are dropped, as gzip does.
+            let mut extra = extra.as_slice();
+            while extra.len() >= 4 {
+                let length = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+                if extra.len() < 4 + length {
+                    break;
+                }
+                header.extra.push(ExtraField {
+                    id: [extra[0], extra[1]],
+                    data: extra[4..4 + length].to_vec(),
+                });
+                extra = &extra[4 + length..];
+            }
+        }
+        let mut read_string = |bytes: &mut Vec<u8>| -> io::Result<Vec<u8>> {
+            let mut string = Vec::new();
+            loop {
+                let byte = read(1, bytes)?[0];
+                if byte == 0 {
+                    return Ok(string);
+                }
+                string.push(byte);
+            }
+        };
+        if flags & FNAME != 0 {
+            header.filename = Some(read_string(&mut bytes)?);
+        }
+        if flags & FCOMMENT != 0 {
+            header.comment = Some(read_string(&mut bytes)?);
+        }
+        if header.header_crc {
+            let mut crc = [0u8; 2];
+            input.read_exact(&mut crc)?;
+            let expected = u16::from_le_bytes(crc);
+            let mut crc32 = Crc32::new();
+            crc32.update(&bytes);
+            let actual = crc32.finish() as u16;
+            if expected != actual {
+                return Err(InflateError::HeaderChecksumMismatch(expected, actual));
+            }
+        }
+        Ok(header)
+    }
+
+    fn extra_size(&self) -> usize {
+        self.extra.iter().map(|field| 4 + field.data.len()).sum()
+    }
+}
+
+// Builds a GzHeader, checking that the fields can be represented.
+#[derive(Debug, Clone, Default)]
+pub struct GzHeaderBuilder {
+    header: GzHeader,
+}
+
+impl GzHeaderBuilder {
+    // A builder preset with the name and modification time of the file at
+    // `path`, as gzip records them by default.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut builder = Self::default();
+        if let Some(name) = path.file_name() {
+            builder = builder.filename(name.to_string_lossy().as_bytes());
+        }
+        let modified = fs::metadata(path)?.modified()?;
+        // Times before the epoch or past 2106 can't be stored.
+        let mtime = modified
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .and_then(|duration| u32::try_from(duration.as_secs()).ok())
+            .unwrap_or(0);
+        Ok(builder.mtime(mtime))
+    }
+
+    pub fn mtime(mut self, mtime: u32) -> Self {
+        self.header.mtime = mtime;
+        self
+    }
+
+    pub fn os(mut self, os: u8) -> Self {
+        self.header.os = os;
+        self
+    }
+
+    pub fn filename(mut self, filename: impl Into<Vec<u8>>) -> Self {
+        self.header.filename = Some(filename.into());
+        self
+    }
+
+    pub fn comment(mut self, comment: impl Into<Vec<u8>>) -> Self {
+        self.header.comment = Some(comment.into());
+        self
+    }
+
+    // Append an FEXTRA subfield.
+    pub fn extra_field(mut self, id: [u8; 2], data: impl Into<Vec<u8>>) -> Self {
+        self.header.extra.push(ExtraField {
+            id,
+            data: data.into(),
+        });
+        self
+    }
+
+    pub fn header_crc(mut self, enabled: bool) -> Self {
+        self.header.header_crc = enabled;
+        self
+    }
+
+    // Fails if the file name or comment contains a zero byte, a subfield
+    // identifier is reserved, or the extra field is too large.
+    pub fn build(self) -> io::Result<GzHeader> {
+        self.header.validate()?;
+        Ok(self.header)
+    }
+}
+
+// Decoder for a single gzip member: the header, a raw DEFLATE stream, and a
+// CRC-32 and size trailer, which is verified after the final block. Input
+// after the member is left unread, so another member can follow.
+#[derive(Debug)]
+pub struct GzDecoder<R: Read> {
+    inflator: RawInflator<R>,
+    header: GzHeader,
+    checksum: Crc32,
+    size: u32,
+}
+
+impl<R: Read> GzDecoder<R> {
+    pub fn try_new(input: R) -> InflateResult<Self> {
+        let mut input = BitReader::new(input);
+        let header = GzHeader::read_from(&mut input)?;
+        Ok(Self {
+            inflator: RawInflator::from_bit_reader(input, Variant::Deflate),
+            header,
+            checksum: Crc32::new(),
+            size: 0,
+        })
+    }
+
+    pub fn header(&self) -> &GzHeader {
+        &self.header
+    }
+
+    // Whether the final block and the trailer have been read.
+    pub fn is_finished(&self) -> bool {
+        self.inflator.is_finished()
+    }
+
+    // Decode the next block. After the final block, the trailer is read and
+    // verified.
+    pub fn next_block(&mut self) -> InflateResult<Vec<u8>> {
+        if self.inflator.is_finished() {
+            return Ok(Vec::new());
+        }
+        let block = self.inflator.next_block()?;
+        self.checksum.update(&block);
+        self.size = self.size.wrapping_add(block.len() as u32);
+        if self.inflator.is_finished() {
+            let mut trailer = [0u8; 8];
+            self.inflator.input_mut().read_exact(&mut trailer)?;
+            let expected = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+            let actual = self.checksum.finish();
+            if expected != actual {
+                return Err(InflateError::ChecksumMismatch(expected, actual));
+            }
+            let expected = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+            if expected != self.size {
+                return Err(InflateError::SizeMismatch(expected, self.size));
+            }
+        }
+        Ok(block)
+    }
+
+    // Decode all remaining blocks and verify the trailer.
+    pub fn read_to_end(&mut self, out: &mut Vec<u8>) -> InflateResult<()> {
+        while !self.is_finished() {
+            out.extend(self.next_block()?);
+        }
+        Ok(())
+    }
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GzEncoder;
+    use std::assert_matches;
+    use std::io::Write;
+
+    #[test]
+    fn test_default_header() {
+        let header = GzHeader::default();
+        assert_eq!(
+            header.to_bytes(Compression::default()),
+            [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255]
+        );
+        assert_eq!(header.to_bytes(Compression::fast())[8], 4);
+        assert_eq!(header.to_bytes(Compression::best())[8], 2);
+    }
+
+    #[test]
+    fn test_extra_flags() {
+        let flags = [0, 1, 6, 9].map(|level| gzip_extra_flags(Compression::new(level)));
+        assert_eq!(flags, [4, 4, 0, 2]);
+    }
+
+    #[test]
+    fn test_all_fields() -> io::Result<()> {
+        let header = GzHeader::builder()
+            .mtime(0x5f5e_1000)
+            .os(OS_UNIX)
+            .filename("data.txt")
+            .comment("hi")
+            .extra_field(*b"AB", [1, 2, 3])
+            .header_crc(true)
+            .build()?;
+        let bytes = header.to_bytes(Compression::default());
+        let expected_without_crc = [
+            &[0x1f, 0x8b, 8, 0x1e, 0x00, 0x10, 0x5e, 0x5f, 0, 3][..],
+            // XLEN, then SI1 SI2 LEN and the data.
+            &[7, 0, b'A', b'B', 3, 0, 1, 2, 3],
+            b"data.txt\0hi\0",
+        ]
+        .concat();
+        let (start, crc) = bytes.split_at(bytes.len() - 2);
+        assert_eq!(start, expected_without_crc);
+        let mut crc32 = Crc32::new();
+        crc32.update(start);
+        assert_eq!(crc, (crc32.finish() as u16).to_le_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_fields() {
+        assert!(GzHeader::builder().filename("a\0b").build().is_err());
+        assert!(GzHeader::builder().comment(vec![0]).build().is_err());
+        assert!(GzHeader::builder().extra_field([1, 0], []).build().is_err());
+        assert!(
+            GzHeader::builder()
+                .extra_field(*b"AB", vec![0; MAX_EXTRA_SIZE - 4])
+                .build()
+                .is_ok()
+        );
+        assert!(
+            GzHeader::builder()
+                .extra_field(*b"AB", vec![0; MAX_EXTRA_SIZE - 3])
+                .build()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_encoder_validates_header() {
+        let invalid = [
+            GzHeader {
+                filename: Some(b"a\0b".to_vec()),
+                ..GzHeader::default()
+            },
+            GzHeader {
+                extra: vec![ExtraField {
+                    id: *b"AB",
+                    data: vec![0; MAX_EXTRA_SIZE + 1],
+                }],
+                ..GzHeader::default()
+            },
+            GzHeader {
+                extra: vec![
+                    ExtraField {
+                        id: *b"AB",
+                        data: vec![0; MAX_EXTRA_SIZE / 2],
+                    };
+                    2
+                ],
+                ..GzHeader::default()
+            },
+        ];
+        for header in invalid {
+            assert_matches!(
+                GzEncoder::with_header(Vec::new(), header, Compression::default()),
+                Err(error) if error.kind() == io::ErrorKind::InvalidInput
+            );
+        }
+    }
+
+    #[test]
+    fn test_decoder() -> InflateResult<()> {
+        let header = GzHeader::builder()
+            .mtime(1_234_567_890)
+            .os(OS_UNIX)
+            .filename("notes.txt")
+            .comment("comment")
+            .extra_field(*b"AB", [1, 2, 3])
+            .header_crc(true)
+            .build()?;
+        let mut encoder = GzEncoder::with_header(Vec::new(), header.clone(), Compression::best())?;
+        encoder.write_all(b"gzip member contents")?;
+        let compressed = encoder.finish()?;
+
+        let mut decoder = GzDecoder::try_new(compressed.as_slice())?;
+        assert_eq!(decoder.header(), &header);
+        let mut out = Vec::new();
+        decoder.read_to_end(&mut out)?;
+        assert_eq!(out, b"gzip member contents");
+
+        // Corrupting the file name breaks the header CRC.
+        let mut corrupted = compressed.clone();
+        corrupted[20] ^= 1;
+        assert_matches!(
+            GzDecoder::try_new(corrupted.as_slice()),
+            Err(InflateError::HeaderChecksumMismatch(..))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_decoder_python_stream() -> InflateResult<()> {
+        // gzip.compress(b"hello", mtime=0) with FNAME "a" added.
+        let compressed = [
+            0x1f, 0x8b, 0x08, 0x08, 0x00, 0x00, 0x00, 0x00, 0x02, 0xff, b'a', 0, 0xcb, 0x48, 0xcd,
+            0xc9, 0xc9, 0x07, 0x00, 0x86, 0xa6, 0x10, 0x36, 0x05, 0x00, 0x00, 0x00,
+        ];
+        let mut decoder = GzDecoder::try_new(compressed.as_slice())?;
+        assert_eq!(decoder.header().filename.as_deref(), Some(b"a".as_slice()));
+        let mut out = Vec::new();
+        decoder.read_to_end(&mut out)?;
+        assert_eq!(out, b"hello");
+        Ok(())
+    }
+
+    #[test]
+    fn test_reserved_flags() {
+        let bytes = [0x1f, 0x8b, 8, 0x20, 0, 0, 0, 0, 0, 255];
+        assert_matches!(
+            GzHeader::read_from(&mut bytes.as_slice()),
+            Err(InflateError::InvalidGzipFlags(0x20))
+        );
+    }
+
+    #[test]
+    fn test_from_file() -> io::Result<()> {
+        let path = std::env::temp_dir().join(format!("gz_header_test_{}", std::process::id()));
+        fs::write(&path, b"contents")?;
+        let header = GzHeaderBuilder::from_file(&path)?.build();
+        fs::remove_file(&path)?;
+        let header = header?;
+        assert_eq!(
+            header.filename,
+            path.file_name()
+                .map(|name| name.as_encoded_bytes().to_vec())
+        );
+        assert!(header.mtime > 1_500_000_000);
+        Ok(())
+    }
+}
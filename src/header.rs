@@ -33,7 +33,7 @@ impl TryFrom<u8> for CompressionMethod {
 impl CompressionInfo {
     pub fn window_size(&self) -> u16 {
         let exponent = self.0 + 8;
-        return 1 << exponent;
+        1 << exponent
     }
 }
 
@@ -54,6 +54,12 @@ pub struct Flags {
     pub compression_level: u8,
 }
 
+impl From<Flags> for u8 {
+    fn from(flags: Flags) -> Self {
+        (flags.compression_level << 6) | ((flags.preset_dictionary as u8) << 5)
+    }
+}
+
 impl From<u8> for Flags {
     fn from(value: u8) -> Self {
         Self {
@@ -70,24 +76,51 @@ pub struct StreamHeader {
     pub flags: Flags,
 }
 
+impl StreamHeader {
+    // Header for a DEFLATE stream using the maximum window size.
+    pub fn new(flags: Flags) -> Self {
+        Self {
+            method: CompressionMethod::Deflate,
+            info: CompressionInfo(7),
+            flags,
+        }
+    }
+}
+
+// Serialize the header, filling in FCHECK.
+impl From<&StreamHeader> for [u8; 2] {
+    fn from(header: &StreamHeader) -> Self {
+        let cmf = (header.info.0 << 4) | header.method as u8;
+        let flg = u8::from(header.flags);
+        let remainder = (((cmf as u16) << 8) + flg as u16) % 31;
+        let check = (31 - remainder) % 31;
+        [cmf, flg + check as u8]
+    }
+}
+
+impl StreamHeader {
+    // Parse the header without verifying FCHECK.
+    pub(crate) fn parse_unchecked(bytes: &[u8; 2]) -> InflateResult<Self> {
+        let [cmf, flg] = *bytes;
+        Ok(StreamHeader {
+            method: CompressionMethod::try_from(cmf.bits(0..=3))?,
+            info: CompressionInfo::try_from(cmf.bits(4..=7))?,
+            flags: Flags::from(flg),
+        })
+    }
+}
+
 impl TryFrom<&[u8; 2]> for StreamHeader {
     type Error = InflateError;
 
     fn try_from(bytes: &[u8; 2]) -> InflateResult<Self> {
+        let header = Self::parse_unchecked(bytes)?;
         let [cmf, flg] = *bytes;
-        let method = CompressionMethod::try_from(cmf.bits(0..=3))?;
-        let info = CompressionInfo::try_from(cmf.bits(4..=7))?;
-        let flags = Flags::from(flg);
-
         let checksum = ((cmf as u16) << 8) + (flg as u16);
-        if checksum % 31 != 0 {
+        if !checksum.is_multiple_of(31) {
             return Err(InflateError::FlagCheckMismatch(checksum));
         }
-        Ok(StreamHeader {
-            method,
-            info,
-            flags,
-        })
+        Ok(header)
     }
 }
 
@@ -95,7 +128,7 @@ impl TryFrom<&[u8; 2]> for StreamHeader {
 mod tests {
     use super::InflateError::*;
     use super::*;
-    use std::assert_matches::assert_matches;
+    use std::assert_matches;
 
     #[test]
     fn test_bits() {
@@ -148,8 +181,32 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_serialize_stream_header() -> InflateResult<()> {
+        let bytes = [0x48, 0b1010_0000 + 8];
+        let header = StreamHeader::try_from(&bytes)?;
+        assert_eq!(<[u8; 2]>::from(&header), bytes);
+
+        for level in 0..=3 {
+            let header = StreamHeader::new(Flags {
+                preset_dictionary: false,
+                compression_level: level,
+            });
+            let bytes = <[u8; 2]>::from(&header);
+            assert_eq!(StreamHeader::try_from(&bytes)?, header);
+        }
+        // zlib's default header.
+        let header = StreamHeader::new(Flags {
+            preset_dictionary: false,
+            compression_level: 2,
+        });
+        assert_eq!(<[u8; 2]>::from(&header), [0x78, 0x9c]);
+        Ok(())
+    }
+
     #[test]
     fn test_window_size() {
+        assert_eq!(CompressionInfo(0).window_size(), 256);
         assert_eq!(CompressionInfo(7).window_size(), 32768);
     }
 }
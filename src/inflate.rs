@@ -0,0 +1,391 @@
+use crate::bit_reader::{BitRead, BitReader};
+use crate::block_decoder::{BlockDecoder, Variant, WideInstruction};
+use crate::error::{InflateError, InflateResult};
+use crate::lz77::History;
+use crate::options::{InflateOptions, Policy};
+use std::io::Read;
+
+// Decoder for a raw DEFLATE stream, without any container format.
+#[derive(Debug)]
+pub struct RawInflator<R: Read> {
+    input: BitReader<R>,
+    history: History,
+    variant: Variant,
+    // Size of the history kept for back-references.
+    window_size: usize,
+    // Window declared by a zlib header, for
+    // InflateOptions::distance_beyond_window.
+    declared_window_size: Option<usize>,
+    options: InflateOptions,
+    // Deviations accepted with Policy::Warning.
+    warnings: Vec<InflateError>,
+    finished: bool,
+    // Input bit position of the final block's header, once it has been read.
+    final_block_position: Option<u64>,
+}
+
+impl<R: Read> RawInflator<R> {
+    pub fn new(input: R) -> Self {
+        Self::with_variant(input, Variant::Deflate)
+    }
+
+    // Decoder for `variant`, e.g. for Deflate64 data from a ZIP archive.
+    pub fn with_variant(input: R, variant: Variant) -> Self {
+        Self::from_bit_reader(BitReader::new(input), variant)
+    }
+
+    pub(crate) fn from_bit_reader(input: BitReader<R>, variant: Variant) -> Self {
+        Self {
+            input,
+            history: History::new(variant.window_size()),
+            variant,
+            window_size: variant.window_size(),
+            declared_window_size: None,
+            options: InflateOptions::lenient(),
+            warnings: Vec::new(),
+            finished: false,
+            final_block_position: None,
+        }
+    }
+
+    // Shrink the window to `window_size` bytes, as declared by a zlib header.
+    // Must be called before the first block.
+    pub(crate) fn set_window_size(&mut self, window_size: usize) {
+        self.history = History::new(window_size);
+        self.window_size = window_size;
+    }
+
+    // Record the window a zlib header declares, for
+    // InflateOptions::distance_beyond_window, whatever the history kept.
+    pub(crate) fn set_declared_window_size(&mut self, window_size: usize) {
+        self.declared_window_size = Some(window_size);
+    }
+
+    // Apply the block checks of `options`, including distances beyond the
+    // declared window. Lenient by default.
+    pub(crate) fn set_options(&mut self, options: InflateOptions) {
+        self.options = options;
+    }
+
+    // Deviations accepted so far with Policy::Warning, including any the
+    // container format adds.
+    pub(crate) fn warnings(&self) -> &[InflateError] {
+        &self.warnings
+    }
+
+    pub(crate) fn warnings_mut(&mut self) -> &mut Vec<InflateError> {
+        &mut self.warnings
+    }
+
+    // Make `dictionary` available to back-references, as if it had been
+    // decoded before the stream. Must be called before the first block.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) {
+        self.history
+            .extend(&dictionary[dictionary.len().saturating_sub(self.window_size)..]);
+    }
+
+    // Whether the final block has been decoded.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    // Number of bits of input consumed so far. Once the stream is finished,
+    // this is where the final block ends.
+    pub fn bit_position(&self) -> u64 {
+        self.input.bit_position()
+    }
+
+    // Input bit position of the final block's BFINAL bit, once the final
+    // block has been started.
+    pub fn final_block_position(&self) -> Option<u64> {
+        self.final_block_position
+    }
+
+    // The last 32 KiB (64 KiB for Deflate64, or the size a zlib header
+    // declares unless InflateOptions::full_window is set) of decoded data, or
+    // all of it if there is less: the window that later back-references can
+    // reach into.
+    pub fn window(&self) -> Vec<u8> {
+        self.history.to_vec()
+    }
+
+    // Underlying input, e.g. for reading a container format's trailer. Any
+    // partially consumed byte is discarded by the next byte-level read.
+    pub(crate) fn input_mut(&mut self) -> &mut BitReader<R> {
+        &mut self.input
+    }
+
+    // Give back the input, e.g. to decode a stream that follows.
+    pub(crate) fn into_input(self) -> BitReader<R> {
+        self.input
+    }
+
+    // Decode the next block. Returns an empty block once the stream is
+    // finished.
+    pub fn next_block(&mut self) -> InflateResult<Vec<u8>> {
+        if self.finished {
+            return Ok(Vec::new());
+        }
+        let block_start = self.input.bit_position();
+        let is_final_block = self.input.read_bit()?;
+        if is_final_block {
+            self.final_block_position = Some(block_start);
+        }
+        let block_type = self.input.read_bits::<u8>(2)?;
+        let window_check = self
+            .declared_window_size
+            .map(|window_size| (window_size, self.options.distance_beyond_window))
+            .filter(|&(_, policy)| policy != Policy::Ignore);
+        let data = match block_type {
+            0 => self.read_uncompressed_block()?,
+            1 => {
+                let decoder = BlockDecoder::new_fixed(&mut self.input).with_variant(self.variant);
+                decode_block(
+                    decoder,
+                    &mut self.history,
+                    self.variant,
+                    window_check,
+                    &mut self.warnings,
+                )?
+            }
+            2 => {
+                let decoder = BlockDecoder::new_dynamic_checked(
+                    &mut self.input,
+                    &self.options,
+                    &mut self.warnings,
+                )?
+                .with_variant(self.variant);
+                decode_block(
+                    decoder,
+                    &mut self.history,
+                    self.variant,
+                    window_check,
+                    &mut self.warnings,
+                )?
+            }
+            _ => return Err(InflateError::InvalidBlockType(block_type)),
+        };
+        self.finished = is_final_block;
+        Ok(data)
+    }
+
+    // Decode all remaining blocks.
+    pub fn read_to_end(&mut self, out: &mut Vec<u8>) -> InflateResult<()> {
+        while !self.finished {
+            out.extend(self.next_block()?);
+        }
+        Ok(())
+    }
+
+    fn read_uncompressed_block(&mut self) -> InflateResult<Vec<u8>> {
+        let length = self.input.read_u16()?;
+        let inverse_length = self.input.read_u16()?;
+        if inverse_length != (!length) {
+            return Err(InflateError::LengthComplementMismatch(
+                length,
+                inverse_length,
+            ));
+        }
+        let mut data = vec![0u8; length as usize];
+        self.input.read_exact(&mut data)?;
+        self.history.extend(&data);
+        Ok(data)
+    }
+}
+
+// Expand the instructions of a Huffman-coded block. Distances beyond the
+// window size in `window_check`, if given, are handled by its policy even if
+// history is available; a block records at most one warning.
+fn decode_block<R: BitRead>(
+    mut decoder: BlockDecoder<R>,
+    history: &mut History,
+    variant: Variant,
+    mut window_check: Option<(usize, Policy)>,
+    warnings: &mut Vec<InflateError>,
+) -> InflateResult<Vec<u8>> {
+    let mut data = Vec::new();
+    loop {
+        match decoder.next_wide()? {
+            WideInstruction::Literal(byte) => {
+                history.append(byte);
+                data.push(byte);
+            }
+            WideInstruction::BackReference { length, distance } => {
+                if let Some((window_size, policy)) = window_check
+                    && distance as usize > window_size
+                {
+                    policy.apply(
+                        InflateError::DistanceBeyondWindow(distance, window_size),
+                        warnings,
+                    )?;
+                    window_check = None;
+                }
+                if distance as usize > history.len() {
+                    return Err(match variant {
+                        Variant::Deflate => {
+                            InflateError::DistanceTooFarBack(distance as u16, history.len())
+                        }
+                        Variant::Deflate64 => {
+                            InflateError::Deflate64DistanceTooFarBack(distance, history.len())
+                        }
+                    });
+                }
+                history.copy_back_reference(length as usize, distance as usize, &mut data);
+            }
+            WideInstruction::EndOfBlock => return Ok(data),
+        }
+    }
+}
+
+// Decompress a complete raw DEFLATE stream.
+pub fn inflate(input: &[u8]) -> InflateResult<Vec<u8>> {
+    let mut inflator = RawInflator::new(input);
+    let mut out = Vec::new();
+    inflator.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+// Decompress a complete raw Deflate64 stream.
+pub fn inflate64(input: &[u8]) -> InflateResult<Vec<u8>> {
+    let mut inflator = RawInflator::with_variant(input, Variant::Deflate64);
+    let mut out = Vec::new();
+    inflator.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InflateError::*;
+    use super::*;
+    use crate::bit_string::bit_string;
+    use crate::bit_writer::BitWriter;
+    use crate::block_encoder::write_stored_blocks;
+    use crate::code_table::SymbolToCodeTable;
+    use std::assert_matches;
+    use std::io;
+
+    // bit_string() packs bits MSB-first, but DEFLATE packs them LSB-first.
+    fn lsb_bit_string(s: &str) -> Vec<u8> {
+        bit_string(s).iter().map(|b| b.reverse_bits()).collect()
+    }
+
+    #[test]
+    fn test_fixed_block() -> InflateResult<()> {
+        // Final fixed block with literals 'a' (0x61 -> 10010001), 'b', then a
+        // back-reference of length 4 (symbol 258 -> 0000010) and distance 2
+        // (symbol 1 -> 00001), then end of block.
+        let raw = lsb_bit_string("1 10 10010001 10010010 0000010 00001 0000000");
+        assert_eq!(inflate(&raw)?, b"ababab");
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiple_blocks() -> InflateResult<()> {
+        let raw = [
+            // Non-final stored block.
+            [0, 2, 0, !2, !0].as_slice(),
+            b"ab",
+            // Final stored block.
+            &[1, 1, 0, !1, !0],
+            b"c",
+        ]
+        .concat();
+        let mut inflator = RawInflator::new(raw.as_slice());
+        assert_eq!(inflator.next_block()?, b"ab");
+        assert!(!inflator.is_finished());
+        assert_eq!(inflator.final_block_position(), None);
+        assert_eq!(inflator.next_block()?, b"c");
+        assert!(inflator.is_finished());
+        assert_eq!(inflator.next_block()?, b"");
+        // The final block starts after the first one's 7 bytes, and the
+        // stream ends with the input.
+        assert_eq!(inflator.final_block_position(), Some(7 * 8));
+        assert_eq!(inflator.bit_position(), raw.len() as u64 * 8);
+        assert_eq!(inflator.window(), b"abc");
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_block_type() {
+        assert_matches!(inflate(&[0b111]), Err(InvalidBlockType(3)));
+    }
+
+    #[test]
+    fn test_distance_too_far_back() {
+        // Literal 'a', then a back-reference with distance 2.
+        let raw = lsb_bit_string("1 10 10010001 0000010 00001 0000000");
+        assert_matches!(inflate(&raw), Err(DistanceTooFarBack(2, 1)));
+    }
+
+    // A Deflate64 stream holding `prefix` in stored blocks, then a final
+    // fixed block of back-references, each coded with length symbol 285.
+    fn deflate64_stream(prefix: &[u8], back_references: &[(u32, u32)]) -> io::Result<Vec<u8>> {
+        let ll_table = SymbolToCodeTable::fixed_ll();
+        let distance_table = SymbolToCodeTable::fixed_distance();
+        let mut writer = BitWriter::new(Vec::new());
+        write_stored_blocks(&mut writer, prefix, false)?;
+        writer.write_bits(0b011, 3)?;
+        for &(length, distance) in back_references {
+            writer.write_code(ll_table.code(285))?;
+            writer.write_bits(length - 3, 16)?;
+            // The last distance symbol whose base isn't past the distance.
+            let (symbol, base, extra_bit_count) = (0..32u32)
+                .map(|symbol| {
+                    let extra_bit_count = (symbol / 2).saturating_sub(1);
+                    let base = match symbol {
+                        0..4 => symbol + 1,
+                        _ => 1 + ((symbol % 2 + 2) << extra_bit_count),
+                    };
+                    (symbol, base, extra_bit_count)
+                })
+                .rfind(|&(_, base, _)| base <= distance)
+                .unwrap();
+            writer.write_code(distance_table.code(symbol as usize))?;
+            writer.write_bits(distance - base, extra_bit_count as u8)?;
+        }
+        writer.write_code(ll_table.code(256))?;
+        writer.align()?;
+        Ok(writer.into_inner())
+    }
+
+    #[test]
+    fn test_deflate64() -> InflateResult<()> {
+        let prefix: Vec<u8> = (0..70_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        // The longest match at the furthest distance, then distance symbols
+        // 30 and 31.
+        let back_references = [(65538, 65536), (1000, 40000), (300, 50000), (3, 1)];
+        let compressed = deflate64_stream(&prefix, &back_references)?;
+        let mut expected = prefix.clone();
+        for (length, distance) in back_references {
+            for _ in 0..length {
+                expected.push(expected[expected.len() - distance as usize]);
+            }
+        }
+        assert_eq!(inflate64(&compressed)?, expected);
+        // Standard DEFLATE doesn't read the extra bits, and stops at the
+        // distance.
+        assert!(inflate(&compressed).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_deflate64_window() -> InflateResult<()> {
+        let compressed = deflate64_stream(b"abc", &[(3, 4)])?;
+        assert_matches!(
+            inflate64(&compressed),
+            Err(Deflate64DistanceTooFarBack(4, 3))
+        );
+
+        let mut inflator = RawInflator::with_variant(compressed.as_slice(), Variant::Deflate64);
+        let dictionary: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        inflator.set_dictionary(&dictionary);
+        let mut out = Vec::new();
+        inflator.read_to_end(&mut out)?;
+        assert_eq!(
+            out,
+            [b"abc".as_slice(), &[dictionary[99_999]], b"ab"].concat()
+        );
+        assert_eq!(inflator.window().len(), 64 * 1024);
+        Ok(())
+    }
+}
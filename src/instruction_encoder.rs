@@ -0,0 +1,248 @@
+use crate::bit_writer::BitWriter;
+use crate::block_encoder::write_block;
+use crate::deflate::{BLOCK_INSTRUCTIONS, Compression};
+use crate::error::{EncodeError, EncodeResult};
+use crate::format::{Format, Framing};
+use crate::lz77::{History, Instruction};
+use crate::match_finder::{MAX_MATCH, MIN_MATCH, WINDOW_SIZE};
+use std::io::Write;
+
+// Encodes LZ77 instructions from a caller's own match finder into a DEFLATE
+// stream, taking care of the entropy coding and the container format.
+//
+// Instruction::EndOfBlock ends the current block early, e.g. where the caller
+// knows the data changes character. Otherwise blocks are ended after a fixed
+// number of instructions. Each block uses whichever block type is smallest.
+#[derive(Debug)]
+pub struct InstructionEncoder<W: Write> {
+    output: BitWriter<W>,
+    framing: Framing,
+    // Data produced so far, for checking and expanding back-references.
+    history: History,
+    // Instructions for the current block, and the data they expand to.
+    instructions: Vec<Instruction>,
+    bytes: Vec<u8>,
+}
+
+impl<W: Write> InstructionEncoder<W> {
+    pub fn new(output: W, format: Format) -> EncodeResult<Self> {
+        let framing = Framing::new(format);
+        let mut output = BitWriter::new(output);
+        // The level isn't known, so the header claims the default.
+        output.write_bytes(&framing.header(Compression::default()))?;
+        Ok(Self {
+            output,
+            framing,
+            history: History::new(WINDOW_SIZE),
+            instructions: Vec::new(),
+            bytes: Vec::new(),
+        })
+    }
+
+    // Add an instruction to the stream. An invalid instruction is rejected
+    // without affecting the stream.
+    pub fn push(&mut self, instruction: Instruction) -> EncodeResult<()> {
+        match instruction {
+            Instruction::Literal(byte) => {
+                self.history.append(byte);
+                self.bytes.push(byte);
+            }
+            Instruction::BackReference { length, distance } => {
+                if !(MIN_MATCH..=MAX_MATCH).contains(&(length as usize)) {
+                    return Err(EncodeError::InvalidLength(length));
+                }
+                if !(1..=WINDOW_SIZE).contains(&(distance as usize)) {
+                    return Err(EncodeError::InvalidDistance(distance));
+                }
+                if distance as usize > self.history.len() {
+                    return Err(EncodeError::DistanceTooFarBack(
+                        distance,
+                        self.history.len(),
+                    ));
+                }
+                self.history.copy_back_reference(
+                    length as usize,
+                    distance as usize,
+                    &mut self.bytes,
+                );
+            }
+            Instruction::EndOfBlock => return self.end_block(false),
+        }
+        self.instructions.push(instruction);
+        if self.instructions.len() >= BLOCK_INSTRUCTIONS {
+            self.end_block(false)?;
+        }
+        Ok(())
+    }
+
+    // Write the final block and the trailer, and return the underlying writer.
+    pub fn finish(mut self) -> EncodeResult<W> {
+        self.end_block(true)?;
+        self.output.align()?;
+        let trailer = self.framing.trailer();
+        self.output.write_bytes(&trailer)?;
+        Ok(self.output.into_inner())
+    }
+
+    // Write out the current block. Empty non-final blocks are skipped.
+    fn end_block(&mut self, is_final: bool) -> EncodeResult<()> {
+        if self.instructions.is_empty() && !is_final {
+            return Ok(());
+        }
+        write_block(
+            &mut self.output,
+            &self.instructions,
+            &self.bytes,
+            is_final,
+            true,
+        )?;
+        self.framing.update(&self.bytes);
+        self.instructions.clear();
+        self.bytes.clear();
+        Ok(())
+    }
+}
+
+// Encode a complete sequence of instructions into a stream of the given
+// format.
+pub fn encode_instructions<I>(instructions: I, format: Format) -> EncodeResult<Vec<u8>>
+where
+    I: IntoIterator<Item = Instruction>,
+{
+    let mut encoder = InstructionEncoder::new(Vec::new(), format)?;
+    for instruction in instructions {
+        encoder.push(instruction)?;
+    }
+    encoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Inflator;
+    use crate::crc32::Crc32;
+    use crate::error::InflateResult;
+    use crate::inflate::{RawInflator, inflate};
+    use crate::match_finder::{MatchParams, find_matches};
+    use std::assert_matches;
+
+    fn back_reference(length: u16, distance: u16) -> Instruction {
+        Instruction::BackReference { length, distance }
+    }
+
+    fn test_data() -> Vec<u8> {
+        b"records, records and more records; ".repeat(3000)
+    }
+
+    #[test]
+    fn test_raw() -> InflateResult<()> {
+        let instructions = [
+            Instruction::Literal(b'a'),
+            Instruction::Literal(b'b'),
+            back_reference(4, 2),
+        ];
+        let compressed = encode_instructions(instructions, Format::Raw).unwrap();
+        assert_eq!(inflate(&compressed)?, b"ababab");
+        Ok(())
+    }
+
+    #[test]
+    fn test_zlib() -> InflateResult<()> {
+        let data = test_data();
+        let instructions = find_matches(&data, MatchParams::default());
+        let compressed = encode_instructions(instructions, Format::Zlib).unwrap();
+        let mut inflator = Inflator::try_new(compressed.as_slice())?;
+        let mut out = Vec::new();
+        while !inflator.is_finished() {
+            out.extend(inflator.next_block()?);
+        }
+        assert_eq!(out, data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzip() -> InflateResult<()> {
+        let data = test_data();
+        let instructions = find_matches(&data, MatchParams::default());
+        let compressed = encode_instructions(instructions, Format::Gzip).unwrap();
+        assert_eq!(compressed[..4], [0x1f, 0x8b, 8, 0]);
+
+        let mut inflator = RawInflator::new(&compressed[10..]);
+        let mut out = Vec::new();
+        inflator.read_to_end(&mut out)?;
+        assert_eq!(out, data);
+
+        let mut crc = Crc32::new();
+        crc.update(&data);
+        let trailer = &compressed[compressed.len() - 8..];
+        assert_eq!(trailer[..4], crc.finish().to_le_bytes());
+        assert_eq!(trailer[4..], (data.len() as u32).to_le_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_boundary_hints() -> InflateResult<()> {
+        let instructions = [
+            Instruction::Literal(b'a'),
+            back_reference(3, 1),
+            Instruction::EndOfBlock,
+            // Consecutive hints don't produce empty blocks.
+            Instruction::EndOfBlock,
+            Instruction::Literal(b'b'),
+            back_reference(8, 5),
+        ];
+        let compressed = encode_instructions(instructions, Format::Raw).unwrap();
+        let mut inflator = RawInflator::new(compressed.as_slice());
+        assert_eq!(inflator.next_block()?, b"aaaa");
+        // Back-references can reach into previous blocks.
+        assert_eq!(inflator.next_block()?, b"baaaabaaa");
+        assert!(inflator.is_finished());
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty() -> InflateResult<()> {
+        let compressed = encode_instructions([], Format::Zlib).unwrap();
+        assert_eq!(compressed, [0x78, 0x9c, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_instructions() {
+        let mut encoder = InstructionEncoder::new(Vec::new(), Format::Raw).unwrap();
+        encoder.push(Instruction::Literal(b'x')).unwrap();
+        assert_matches!(
+            encoder.push(back_reference(2, 1)),
+            Err(EncodeError::InvalidLength(2))
+        );
+        assert_matches!(
+            encoder.push(back_reference(259, 1)),
+            Err(EncodeError::InvalidLength(259))
+        );
+        assert_matches!(
+            encoder.push(back_reference(3, 0)),
+            Err(EncodeError::InvalidDistance(0))
+        );
+        assert_matches!(
+            encoder.push(back_reference(3, 32769)),
+            Err(EncodeError::InvalidDistance(32769))
+        );
+        assert_matches!(
+            encoder.push(back_reference(3, 2)),
+            Err(EncodeError::DistanceTooFarBack(2, 1))
+        );
+        // The stream is still usable.
+        encoder.push(back_reference(3, 1)).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(inflate(&compressed).unwrap(), b"xxxx");
+    }
+
+    #[test]
+    fn test_distance_limited_to_window() {
+        let mut encoder = InstructionEncoder::new(Vec::new(), Format::Raw).unwrap();
+        for i in 0..WINDOW_SIZE + 10 {
+            encoder.push(Instruction::Literal(i as u8)).unwrap();
+        }
+        encoder.push(back_reference(3, 32768)).unwrap();
+    }
+}
@@ -0,0 +1,431 @@
+// Joining compressed streams into one without recompressing them, like
+// zlib's gzjoin example. Each stream is scanned to find its last block, whose
+// BFINAL bit is cleared, and the compressed blocks are copied through. The
+// checksums are combined from the streams' trailers.
+
+use crate::adler32::adler32_combine;
+use crate::bit_reader::{BitRead, BitReader};
+use crate::bit_writer::BitWriter;
+use crate::block_decoder::BlockDecoder;
+use crate::block_encoder::write_stored_blocks;
+use crate::crc32::crc32_combine;
+use crate::deflate::Compression;
+use crate::encoder::encode;
+use crate::error::{InflateError, InflateResult};
+use crate::format::Format;
+use crate::gzip::GzHeader;
+use crate::header::StreamHeader;
+use crate::lz77::Instruction;
+use std::io::{self, Cursor, Read};
+
+// Layout of a DEFLATE stream, as bit offsets from its start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeflateLayout {
+    // Start of the final block's header, i.e. its BFINAL bit.
+    pub final_block: u64,
+    // End of the final block.
+    pub end: u64,
+    // Size of the decompressed data.
+    pub length: u64,
+}
+
+// Find the final block of the raw DEFLATE stream at the start of `data` by
+// walking its blocks. Huffman-coded blocks are decoded to find their end,
+// but back-references aren't expanded.
+pub fn scan_deflate(data: &[u8]) -> InflateResult<DeflateLayout> {
+    let mut input = BitReader::new(data);
+    let mut length = 0u64;
+    loop {
+        let block_start = input.bit_position();
+        if skip_block(&mut input, &mut length)? {
+            return Ok(DeflateLayout {
+                final_block: block_start,
+                end: input.bit_position(),
+                length,
+            });
+        }
+    }
+}
+
+// Skip over the next block of `input` without expanding it, returning whether
+// it is the final block. `length` is the size of the data decoded so far,
+// which back-references can't reach beyond, and is advanced past the block.
+pub(crate) fn skip_block<R: Read>(
+    input: &mut BitReader<R>,
+    length: &mut u64,
+) -> InflateResult<bool> {
+    let is_final = input.read_bit()?;
+    let block_type = input.read_bits::<u8>(2)?;
+    let mut decoder = match block_type {
+        0 => {
+            let stored_length = input.read_u16()?;
+            let inverse_length = input.read_u16()?;
+            if inverse_length != !stored_length {
+                return Err(InflateError::LengthComplementMismatch(
+                    stored_length,
+                    inverse_length,
+                ));
+            }
+            // Skip the data, checking that it is all there.
+            let skipped =
+                std::io::copy(&mut input.take(stored_length as u64), &mut std::io::sink())?;
+            if skipped < stored_length as u64 {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+            }
+            *length += stored_length as u64;
+            return Ok(is_final);
+        }
+        1 => BlockDecoder::new_fixed(input),
+        2 => BlockDecoder::new_dynamic(input)?,
+        _ => return Err(InflateError::InvalidBlockType(block_type)),
+    };
+    loop {
+        match decoder.next()? {
+            Instruction::Literal(_) => *length += 1,
+            Instruction::BackReference {
+                length: match_length,
+                distance,
+            } => {
+                if distance as u64 > *length {
+                    return Err(InflateError::DistanceTooFarBack(distance, *length as usize));
+                }
+                *length += match_length as u64;
+            }
+            Instruction::EndOfBlock => return Ok(is_final),
+        }
+    }
+}
+
+// One stream of the input, split into its parts.
+#[derive(Debug)]
+pub(crate) struct Stream<'a> {
+    pub header: &'a [u8],
+    pub deflate: &'a [u8],
+    pub layout: DeflateLayout,
+    // Checksum from the trailer, 0 for raw streams.
+    pub checksum: u32,
+}
+
+// Split the stream at the start of `data`, returning it and the remaining
+// data.
+pub(crate) fn parse_stream(
+    data: &[u8],
+    format: Format,
+    is_first: bool,
+) -> InflateResult<(Stream<'_>, &[u8])> {
+    let mut input = Cursor::new(data);
+    match format {
+        Format::Raw => {}
+        Format::Zlib => {
+            let mut header = [0u8; 2];
+            input.read_exact(&mut header)?;
+            let header = StreamHeader::try_from(&header)?;
+            if header.flags.preset_dictionary {
+                let mut id = [0u8; 4];
+                input.read_exact(&mut id)?;
+                // Only the first stream's dictionary would be known to the
+                // decoder.
+                if !is_first {
+                    return Err(InflateError::DictionaryRequired(u32::from_be_bytes(id)));
+                }
+            }
+        }
+        Format::Gzip => {
+            GzHeader::read_from(&mut input)?;
+        }
+    }
+    let (header, rest) = data.split_at(input.position() as usize);
+    let layout = scan_deflate(rest)?;
+    let (deflate, rest) = rest.split_at(layout.end.div_ceil(8) as usize);
+    let trailer_size = format.trailer_size();
+    if rest.len() < trailer_size {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+    }
+    let (trailer, rest) = rest.split_at(trailer_size);
+    let checksum = match format {
+        Format::Raw => 0,
+        Format::Zlib => u32::from_be_bytes(trailer.try_into().unwrap()),
+        Format::Gzip => {
+            let size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+            if size != layout.length as u32 {
+                return Err(InflateError::SizeMismatch(size, layout.length as u32));
+            }
+            u32::from_le_bytes(trailer[..4].try_into().unwrap())
+        }
+    };
+    let stream = Stream {
+        header,
+        deflate,
+        layout,
+        checksum,
+    };
+    Ok((stream, rest))
+}
+
+// The first zlib stream's header, including any DICTID, but declaring the
+// largest window of all the streams, as back-references may reach that far.
+fn zlib_header(streams: &[Stream]) -> InflateResult<Vec<u8>> {
+    let mut headers = Vec::new();
+    for stream in streams {
+        headers.push(StreamHeader::try_from(&[
+            stream.header[0],
+            stream.header[1],
+        ])?);
+    }
+    let info = headers
+        .iter()
+        .map(|header| header.info)
+        .max_by_key(|info| info.window_size())
+        .unwrap();
+    let header = StreamHeader {
+        info,
+        ..headers.swap_remove(0)
+    };
+    let mut bytes = <[u8; 2]>::from(&header).to_vec();
+    bytes.extend_from_slice(&streams[0].header[2..]);
+    Ok(bytes)
+}
+
+// Join compressed streams of the given format into a single stream that
+// decompresses to the concatenation of their contents. The result has the
+// first stream's header, though zlib output declares the largest window of
+// all the streams. Gzip inputs may contain several members, which are all
+// joined; other formats hold exactly one stream per input.
+//
+// The streams are scanned for their block structure but not decompressed, so
+// their data isn't checked against their checksums, which are trusted.
+pub fn join_streams<S: AsRef<[u8]>>(streams: &[S], format: Format) -> InflateResult<Vec<u8>> {
+    let mut parsed = Vec::new();
+    for data in streams {
+        let mut data = data.as_ref();
+        loop {
+            let (stream, rest) = parse_stream(data, format, parsed.is_empty())?;
+            parsed.push(stream);
+            data = rest;
+            if data.is_empty() {
+                break;
+            }
+            if format != Format::Gzip {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Data after the end of the stream",
+                )
+                .into());
+            }
+        }
+    }
+    let Some((last, rest)) = parsed.split_last() else {
+        return Ok(encode(&[], Vec::new(), format, Compression::default())?);
+    };
+
+    let header = match format {
+        Format::Zlib => zlib_header(&parsed)?,
+        _ => parsed[0].header.to_vec(),
+    };
+    let mut output = BitWriter::new(header);
+    for stream in rest {
+        let mut deflate = stream.deflate.to_vec();
+        let DeflateLayout {
+            final_block, end, ..
+        } = stream.layout;
+        deflate[(final_block / 8) as usize] &= !(1 << (final_block % 8));
+        output.write_bytes(&deflate[..(end / 8) as usize])?;
+        let trailing_bits = (end % 8) as u8;
+        if trailing_bits != 0 {
+            // Finish the last partial byte, and get back to a byte boundary
+            // with an empty stored block.
+            let byte = deflate[(end / 8) as usize] & ((1 << trailing_bits) - 1);
+            output.write_bits(byte as u32, trailing_bits)?;
+            write_stored_blocks(&mut output, &[], false)?;
+        }
+    }
+    output.write_bytes(last.deflate)?;
+
+    let (mut checksum, mut length) = (parsed[0].checksum, parsed[0].layout.length);
+    for stream in &parsed[1..] {
+        let next_length = stream.layout.length as usize;
+        checksum = match format {
+            Format::Raw => 0,
+            Format::Zlib => adler32_combine(checksum, stream.checksum, next_length),
+            Format::Gzip => crc32_combine(checksum, stream.checksum, next_length),
+        };
+        length += stream.layout.length;
+    }
+    match format {
+        Format::Raw => {}
+        Format::Zlib => output.write_bytes(&checksum.to_be_bytes())?,
+        Format::Gzip => {
+            output.write_bytes(&checksum.to_le_bytes())?;
+            output.write_bytes(&(length as u32).to_le_bytes())?;
+        }
+    }
+    Ok(output.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::CompressionInfo;
+    use crate::{
+        Flush, Inflator, ZlibEncoder, compress, decompress, gzip_compress, gzip_decompress,
+        raw_compress, raw_decompress,
+    };
+    use std::assert_matches;
+    use std::io::Write;
+
+    fn shards() -> Vec<Vec<u8>> {
+        vec![
+            b"first shard, first shard, first shard\n".repeat(50),
+            vec![],
+            (0..5000u32).map(|i| (i * 7 % 256) as u8).collect(),
+            b"x".to_vec(),
+            b"last shard".repeat(1000),
+        ]
+    }
+
+    #[test]
+    fn test_scan_deflate() -> InflateResult<()> {
+        let data = b"scan me, scan me, scan me".repeat(100);
+        let compressed = raw_compress(&data, Compression::default());
+        let layout = scan_deflate(&compressed)?;
+        assert_eq!(layout.length, data.len() as u64);
+        assert_eq!(layout.end.div_ceil(8), compressed.len() as u64);
+        // A single block.
+        assert_eq!(layout.final_block, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_join_zlib() -> InflateResult<()> {
+        let shards = shards();
+        for level in [0, 1, 6, 9] {
+            let compressed: Vec<Vec<u8>> = shards
+                .iter()
+                .map(|shard| compress(shard, Compression::new(level)))
+                .collect();
+            let joined = join_streams(&compressed, Format::Zlib)?;
+            assert_eq!(decompress(&joined)?, shards.concat());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_join_gzip() -> InflateResult<()> {
+        let shards = shards();
+        let compressed: Vec<Vec<u8>> = shards
+            .iter()
+            .map(|shard| gzip_compress(shard, Compression::best()))
+            .collect();
+        let joined = join_streams(&compressed, Format::Gzip)?;
+        assert_eq!(gzip_decompress(&joined)?, shards.concat());
+        // A single member, with the first member's header.
+        let header_length = 10;
+        assert_eq!(joined[..header_length], compressed[0][..header_length]);
+        let mut input = joined.as_slice();
+        let mut out = Vec::new();
+        crate::GzDecoder::try_new(&mut input)?.read_to_end(&mut out)?;
+        assert!(input.is_empty());
+
+        // Multi-member inputs are joined too.
+        let multi_member = [compressed[0].clone(), compressed[2].clone()].concat();
+        let joined = join_streams(&[multi_member, compressed[4].clone()], Format::Gzip)?;
+        let expected = [shards[0].as_slice(), &shards[2], &shards[4]].concat();
+        assert_eq!(gzip_decompress(&joined)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_join_raw() -> InflateResult<()> {
+        let shards = shards();
+        let compressed: Vec<Vec<u8>> = shards
+            .iter()
+            .map(|shard| raw_compress(shard, Compression::fast()))
+            .collect();
+        let joined = join_streams(&compressed, Format::Raw)?;
+        assert_eq!(raw_decompress(&joined)?, shards.concat());
+        Ok(())
+    }
+
+    #[test]
+    fn test_join_multi_block_streams() -> InflateResult<()> {
+        // Streams with flushes have several blocks, some of them stored.
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"before the flush ")?;
+        encoder.flush_with(Flush::Sync)?;
+        encoder.write_all(b"after the flush")?;
+        let flushed = encoder.finish()?;
+        let other = compress(b" and another stream", Compression::default());
+        let joined = join_streams(&[flushed.clone(), other, flushed], Format::Zlib)?;
+        assert_eq!(
+            decompress(&joined)?,
+            b"before the flush after the flush and another streambefore the flush after the flush"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_join_nothing() -> InflateResult<()> {
+        let joined = join_streams::<Vec<u8>>(&[], Format::Gzip)?;
+        assert_eq!(gzip_decompress(&joined)?, b"");
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        let mut truncated = compress(b"some data to truncate", Compression::default());
+        truncated.pop();
+        assert_matches!(
+            join_streams(&[truncated], Format::Zlib),
+            Err(InflateError::IoError(_))
+        );
+        let mut bad_size = gzip_compress(b"data", Compression::default());
+        let length = bad_size.len();
+        bad_size[length - 4] ^= 1;
+        assert_matches!(
+            join_streams(&[bad_size], Format::Gzip),
+            Err(InflateError::SizeMismatch(..))
+        );
+    }
+
+    #[test]
+    fn test_join_window_sizes() -> InflateResult<()> {
+        // A stream declaring a 512-byte window, then one using a larger one.
+        let mut small = compress(b"small window", Compression::fast());
+        let header = StreamHeader::try_from(&[small[0], small[1]])?;
+        let small_window = StreamHeader {
+            info: CompressionInfo::try_from(1)?,
+            ..header
+        };
+        small[..2].copy_from_slice(&<[u8; 2]>::from(&small_window));
+        let noise: Vec<u8> = (0..1000u32).map(|i| (i * i % 251) as u8).collect();
+        let large = compress(&noise.repeat(2), Compression::default());
+
+        let joined = join_streams(&[&small, &large], Format::Zlib)?;
+        let joined_header = StreamHeader::try_from(&[joined[0], joined[1]])?;
+        assert_eq!(joined_header.info.window_size(), 32768);
+        assert_eq!(joined_header.flags, small_window.flags);
+        let mut inflator = Inflator::try_new_strict(joined.as_slice())?;
+        let mut out = Vec::new();
+        inflator.read_to_end(&mut out)?;
+        assert_eq!(out, [&b"small window"[..], &noise, &noise].concat());
+        Ok(())
+    }
+
+    #[test]
+    fn test_data_after_stream() {
+        let compressed = [
+            compress(b"abc", Compression::default()),
+            compress(b"def", Compression::default()),
+        ]
+        .concat();
+        assert_matches!(
+            join_streams(&[compressed], Format::Zlib),
+            Err(InflateError::IoError(e)) if e.kind() == io::ErrorKind::InvalidData
+        );
+        let compressed = [raw_compress(b"abc", Compression::default()), vec![0]].concat();
+        assert_matches!(
+            join_streams(&[compressed], Format::Raw),
+            Err(InflateError::IoError(e)) if e.kind() == io::ErrorKind::InvalidData
+        );
+    }
+}
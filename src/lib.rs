@@ -1,56 +1,209 @@
-#![feature(assert_matches)]
-
+pub mod adler32;
+pub mod append;
+mod auto;
 mod bit_reader;
 pub mod bit_string;
+mod bit_writer;
 pub mod block_decoder;
+mod block_encoder;
+mod block_splitter;
 mod code;
 pub mod code_table;
+pub mod crc32;
+pub mod deflate;
+pub mod dictionary;
+mod encoder;
 mod error;
+mod format;
+pub mod gzip;
 mod header;
-mod lz77;
+pub mod inflate;
+mod instruction_encoder;
+pub mod join;
+pub mod lz77;
+pub mod match_finder;
+mod oneshot;
+pub mod optimal;
+mod options;
+mod parallel;
+pub mod salvage;
+
+pub use crate::auto::{AutoDecoder, detect_format};
+pub use crate::deflate::{Compression, Flush, Strategy};
+pub use crate::encoder::{GzEncoder, ZlibEncoder, ZlibEncoderReader};
+pub use crate::error::{EncodeError, EncodeResult, InflateError, InflateResult};
+pub use crate::format::Format;
+pub use crate::gzip::{GzDecoder, GzHeader, GzHeaderBuilder};
+pub use crate::instruction_encoder::{InstructionEncoder, encode_instructions};
+pub use crate::oneshot::{
+    compress, compress_bound, compress_to_slice, decompress, gzip_compress, gzip_compress_bound,
+    gzip_compress_to_slice, gzip_decompress, raw_compress, raw_compress_bound,
+    raw_compress_to_slice, raw_decompress,
+};
+pub use crate::options::{InflateOptions, Policy};
+pub use crate::parallel::{CHUNK_SIZE, ParallelEncoder};
 
-use crate::bit_reader::{BitRead, BitReader};
-use crate::error::{InflateError, InflateResult};
+use crate::adler32::Adler32;
+use crate::bit_reader::BitReader;
+use crate::block_decoder::Variant;
 use crate::header::*;
+use crate::inflate::RawInflator;
 
 use std::io::{self, Read};
 
 #[derive(Debug)]
 pub struct Inflator<R: io::Read> {
-    input: BitReader<R>,
+    inflator: RawInflator<R>,
     pub header: StreamHeader,
+    // DICTID of the preset dictionary, if the stream uses one.
+    dictionary_id: Option<u32>,
+    // Whether the preset dictionary has yet to be supplied.
+    needs_dictionary: bool,
+    checksum: Adler32,
+    options: InflateOptions,
 }
 
 impl<R: io::Read> Inflator<R> {
+    // Read the stream header. The window is sized as declared by its CINFO
+    // field, so streams with small windows need less memory.
     pub fn try_new(input: R) -> InflateResult<Self> {
+        Self::with_options(input, InflateOptions::default())
+    }
+
+    // Like try_new(), but failing on every deviation InflateOptions covers,
+    // including back-references beyond the window declared in the header.
+    pub fn try_new_strict(input: R) -> InflateResult<Self> {
+        Self::with_options(input, InflateOptions::strict())
+    }
+
+    // Read the stream header, then decode with the given tolerance.
+    pub fn with_options(input: R, options: InflateOptions) -> InflateResult<Self> {
         let mut header = [0u8; 2];
         let mut input = BitReader::new(input);
         input.read_exact(&mut header)?;
-        let header = StreamHeader::try_from(&header)?;
-        Ok(Self { input, header })
+        let mut warnings = Vec::new();
+        let header = match StreamHeader::try_from(&header) {
+            Err(InflateError::FlagCheckMismatch(checksum)) => {
+                options
+                    .flag_check
+                    .apply(InflateError::FlagCheckMismatch(checksum), &mut warnings)?;
+                StreamHeader::parse_unchecked(&header)?
+            }
+            result => result?,
+        };
+        let dictionary_id = if header.flags.preset_dictionary {
+            let mut id = [0u8; 4];
+            input.read_exact(&mut id)?;
+            Some(u32::from_be_bytes(id))
+        } else {
+            None
+        };
+        let mut inflator = RawInflator::from_bit_reader(input, Variant::Deflate);
+        let window_size = header.info.window_size() as usize;
+        inflator.set_declared_window_size(window_size);
+        if !options.full_window {
+            inflator.set_window_size(window_size);
+        }
+        inflator.set_options(options);
+        inflator.warnings_mut().extend(warnings);
+        Ok(Self {
+            inflator,
+            header,
+            dictionary_id,
+            needs_dictionary: dictionary_id.is_some(),
+            checksum: Adler32::new(),
+            options,
+        })
+    }
+
+    // The Adler-32 checksum of the preset dictionary the stream was
+    // compressed with, if any.
+    pub fn dictionary_id(&self) -> Option<u32> {
+        self.dictionary_id
+    }
+
+    // Supply the preset dictionary named by `dictionary_id()`. Equivalent to
+    // zlib's inflateSetDictionary().
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) -> InflateResult<()> {
+        let Some(expected) = self.dictionary_id.filter(|_| self.needs_dictionary) else {
+            return Err(InflateError::UnexpectedDictionary);
+        };
+        let mut checksum = Adler32::new();
+        checksum.update(dictionary);
+        let actual = checksum.finish();
+        if actual != expected {
+            return Err(InflateError::DictionaryMismatch(expected, actual));
+        }
+        self.inflator.set_dictionary(dictionary);
+        self.needs_dictionary = false;
+        Ok(())
+    }
+
+    // Deviations accepted so far with Policy::Warning, in input order.
+    pub fn warnings(&self) -> &[InflateError] {
+        self.inflator.warnings()
     }
 
+    // Whether the final block and the trailer have been read.
+    pub fn is_finished(&self) -> bool {
+        self.inflator.is_finished()
+    }
+
+    // Decode all remaining blocks and verify the trailer.
+    pub fn read_to_end(&mut self, out: &mut Vec<u8>) -> InflateResult<()> {
+        while !self.is_finished() {
+            out.extend(self.next_block()?);
+        }
+        Ok(())
+    }
+
+    // Decode the next block. After the final block, the Adler-32 trailer is
+    // read and verified. Fails with DictionaryRequired until the preset
+    // dictionary, if any, has been set.
     pub fn next_block(&mut self) -> InflateResult<Vec<u8>> {
-        let _is_final_block = self.input.read_bit()?;
-        let block_type = self.input.read_bits::<u8>(2)?;
-        if block_type != 0 {
-            return Err(InflateError::UnimplementedBlockType(block_type));
+        if self.inflator.is_finished() {
+            return Ok(Vec::new());
+        }
+        if let Some(id) = self.dictionary_id.filter(|_| self.needs_dictionary) {
+            return Err(InflateError::DictionaryRequired(id));
         }
-        self.read_uncompressed_block()
+        let block = self.inflator.next_block()?;
+        self.checksum.update(&block);
+        if self.inflator.is_finished() {
+            self.read_trailer()?;
+        }
+        Ok(block)
     }
 
-    fn read_uncompressed_block(&mut self) -> InflateResult<Vec<u8>> {
-        let length = self.input.read_u16()?;
-        let inverse_length = self.input.read_u16()?;
-        if inverse_length != (!length) {
-            return Err(InflateError::LengthComplementMismatch(
-                length,
-                inverse_length,
-            ));
+    // Verify the Adler-32 trailer, then check for data after it.
+    fn read_trailer(&mut self) -> InflateResult<()> {
+        let options = self.options;
+        let mut trailer = Vec::with_capacity(4);
+        self.inflator
+            .input_mut()
+            .take(4)
+            .read_to_end(&mut trailer)?;
+        if let Ok(trailer) = <[u8; 4]>::try_from(trailer.as_slice()) {
+            let expected = u32::from_be_bytes(trailer);
+            let actual = self.checksum.finish();
+            if expected != actual {
+                return Err(InflateError::ChecksumMismatch(expected, actual));
+            }
+        } else {
+            options.truncated_trailer.apply(
+                InflateError::TruncatedTrailer(trailer.len()),
+                self.inflator.warnings_mut(),
+            )?;
         }
-        let mut data = vec![0u8; length as usize];
-        self.input.read_exact(&mut data)?;
-        Ok(data)
+        if options.trailing_data != Policy::Ignore {
+            let mut byte = [0u8];
+            if self.inflator.input_mut().read(&mut byte)? != 0 {
+                options
+                    .trailing_data
+                    .apply(InflateError::TrailingData, self.inflator.warnings_mut())?;
+            }
+        }
+        Ok(())
     }
 }
 
@@ -58,7 +211,8 @@ impl<R: io::Read> Inflator<R> {
 mod tests {
     use super::InflateError::*;
     use super::*;
-    use std::assert_matches::assert_matches;
+    use crate::lz77::Instruction;
+    use std::assert_matches;
 
     #[test]
     fn test_truncated_header() {
@@ -68,7 +222,7 @@ mod tests {
 
     #[test]
     fn test_begin_stream() -> InflateResult<()> {
-        let mut raw: &[u8] = &[0x48, 0b1010_0000 + 8];
+        let mut raw: &[u8] = &[0x48, 0b1010_0000 + 8, 0x12, 0x34, 0x56, 0x78];
         let inflator = Inflator::try_new(&mut raw)?;
         assert_eq!(
             inflator.header,
@@ -81,6 +235,7 @@ mod tests {
                 }
             }
         );
+        assert_eq!(inflator.dictionary_id(), Some(0x12345678));
 
         Ok(())
     }
@@ -90,6 +245,11 @@ mod tests {
         let mut raw: &[u8] = &[
             0x48,
             0b1010_0000 + 8,
+            // Adler-32 of the dictionary b"dict"
+            0x04,
+            0x09,
+            0x01,
+            0xa5,
             // header
             0,
             // length
@@ -106,8 +266,276 @@ mod tests {
             5,
         ];
         let mut inflator = Inflator::try_new(&mut raw)?;
+        inflator.set_dictionary(b"dict")?;
         let block = inflator.next_block()?;
         assert_eq!(block, vec![1, 2, 3, 4, 5]);
         Ok(())
     }
+
+    fn decompress_with_dictionary(compressed: &[u8], dictionary: &[u8]) -> InflateResult<Vec<u8>> {
+        let mut inflator = Inflator::try_new(compressed)?;
+        inflator.set_dictionary(dictionary)?;
+        let mut out = Vec::new();
+        while !inflator.is_finished() {
+            out.extend(inflator.next_block()?);
+        }
+        Ok(out)
+    }
+
+    // Output of Python's zlib.compressobj(zdict=b"hello, hello").compress(b"hello!").
+    const ZLIB_DICTIONARY_STREAM: [u8; 14] = [
+        0x78, 0xbb, 0x1c, 0xda, 0x04, 0x75, 0x03, 0x13, 0x8a, 0x00, 0x08, 0x62, 0x02, 0x36,
+    ];
+
+    #[test]
+    fn test_dictionary_required() {
+        let mut inflator = Inflator::try_new(ZLIB_DICTIONARY_STREAM.as_slice()).unwrap();
+        assert_matches!(inflator.next_block(), Err(DictionaryRequired(0x1cda0475)));
+        assert_matches!(
+            inflator.set_dictionary(b"wrong"),
+            Err(DictionaryMismatch(0x1cda0475, _))
+        );
+    }
+
+    #[test]
+    fn test_zlib_dictionary_stream() -> InflateResult<()> {
+        assert_eq!(
+            decompress_with_dictionary(&ZLIB_DICTIONARY_STREAM, b"hello, hello")?,
+            b"hello!"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unexpected_dictionary() -> InflateResult<()> {
+        let compressed = encode_instructions([], Format::Zlib).unwrap();
+        let mut inflator = Inflator::try_new(compressed.as_slice())?;
+        assert_matches!(inflator.set_dictionary(b"dict"), Err(UnexpectedDictionary));
+        Ok(())
+    }
+
+    #[test]
+    fn test_encoder_dictionary() -> InflateResult<()> {
+        let dictionary = br#"{"id": , "name": "", "email": "@example.com", "active": true}"#;
+        let messages: Vec<Vec<u8>> = (0..20)
+            .map(|i| {
+                format!(
+                    r#"{{"id": {}, "name": "user{}", "email": "user{}@example.com", "active": true}}"#,
+                    i, i, i
+                )
+                .into_bytes()
+            })
+            .collect();
+        let compress = |message: &[u8], dictionary: Option<&[u8]>| -> io::Result<Vec<u8>> {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            if let Some(dictionary) = dictionary {
+                encoder.set_dictionary(dictionary)?;
+            }
+            io::Write::write_all(&mut encoder, message)?;
+            encoder.finish()
+        };
+        let mut plain_size = 0;
+        let mut dictionary_size = 0;
+        for message in &messages {
+            let compressed = compress(message, Some(dictionary))?;
+            assert_eq!(
+                decompress_with_dictionary(&compressed, dictionary)?,
+                *message
+            );
+            dictionary_size += compressed.len();
+            plain_size += compress(message, None)?.len();
+        }
+        assert!(dictionary_size * 2 < plain_size + plain_size / 10);
+        Ok(())
+    }
+
+    // Zlib stream of 256 distinct literals, repeated with back-references
+    // until 772 bytes long, then a back-reference at `distance`. The header
+    // declares a 256-byte window.
+    fn small_window_stream(distance: u16) -> InflateResult<Vec<u8>> {
+        let literals = (0..=255).map(Instruction::Literal);
+        let repeats = [Instruction::BackReference {
+            length: 258,
+            distance: 256,
+        }; 2];
+        let back_reference = Instruction::BackReference {
+            length: 10,
+            distance,
+        };
+        let instructions = literals.chain(repeats).chain([back_reference]);
+        let mut compressed = encode_instructions(instructions, Format::Zlib)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        let header = StreamHeader {
+            info: CompressionInfo::try_from(0)?,
+            ..StreamHeader::try_from(&[compressed[0], compressed[1]])?
+        };
+        compressed[..2].copy_from_slice(&<[u8; 2]>::from(&header));
+        Ok(compressed)
+    }
+
+    fn decompress(inflator: InflateResult<Inflator<&[u8]>>) -> InflateResult<Vec<u8>> {
+        let mut out = Vec::new();
+        inflator?.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_declared_window_size() -> InflateResult<()> {
+        let compressed = small_window_stream(256)?;
+        let inflator = Inflator::try_new(compressed.as_slice())?;
+        assert_eq!(inflator.header.info.window_size(), 256);
+        let out = decompress(Inflator::try_new_strict(compressed.as_slice()))?;
+        assert_eq!(out.len(), 782);
+        assert_eq!(out[772..], out[516..526]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_distance_beyond_window() -> InflateResult<()> {
+        let compressed = small_window_stream(300)?;
+        // Only the declared window is kept, so the data is gone.
+        assert_matches!(
+            decompress(Inflator::try_new(compressed.as_slice())),
+            Err(DistanceTooFarBack(300, 256))
+        );
+        assert_matches!(
+            decompress(Inflator::try_new_strict(compressed.as_slice())),
+            Err(DistanceBeyondWindow(300, 256))
+        );
+        // With a full window, it decodes like zlib does by default.
+        let full_window = InflateOptions {
+            full_window: true,
+            ..InflateOptions::default()
+        };
+        let out = decompress_with(&compressed, full_window)?;
+        assert_eq!(out.len(), 782);
+        assert_eq!(out[772..], out[472..482]);
+        assert_matches!(
+            decompress_with(
+                &compressed,
+                InflateOptions {
+                    full_window: true,
+                    ..InflateOptions::strict()
+                }
+            ),
+            Err(DistanceBeyondWindow(300, 256))
+        );
+        let mut inflator = Inflator::with_options(
+            compressed.as_slice(),
+            InflateOptions {
+                full_window: true,
+                ..InflateOptions::validating()
+            },
+        )?;
+        let mut out = Vec::new();
+        inflator.read_to_end(&mut out)?;
+        assert_eq!(out.len(), 782);
+        assert_matches!(inflator.warnings(), [DistanceBeyondWindow(300, 256)]);
+        // Without the full window, the warning is followed by the failure.
+        assert_matches!(
+            decompress_with_warnings(&compressed),
+            Err(DistanceTooFarBack(300, 256))
+        );
+        Ok(())
+    }
+
+    fn decompress_with(compressed: &[u8], options: InflateOptions) -> InflateResult<Vec<u8>> {
+        let mut inflator = Inflator::with_options(compressed, options)?;
+        let mut out = Vec::new();
+        inflator.read_to_end(&mut out)?;
+        assert!(inflator.warnings().is_empty());
+        Ok(out)
+    }
+
+    // Decompress with InflateOptions::validating(), returning the inflator
+    // for its warnings.
+    fn decompress_with_warnings(compressed: &[u8]) -> InflateResult<(Vec<u8>, Inflator<&[u8]>)> {
+        let mut inflator = Inflator::with_options(compressed, InflateOptions::validating())?;
+        let mut out = Vec::new();
+        inflator.read_to_end(&mut out)?;
+        Ok((out, inflator))
+    }
+
+    #[test]
+    fn test_flag_check_policy() -> InflateResult<()> {
+        let mut compressed = compress(b"data", Compression::default());
+        compressed[1] += 1;
+        assert_matches!(
+            decompress_with(&compressed, InflateOptions::default()),
+            Err(FlagCheckMismatch(_))
+        );
+        assert_eq!(
+            decompress_with(&compressed, InflateOptions::lenient())?,
+            b"data"
+        );
+        let (out, inflator) = decompress_with_warnings(&compressed)?;
+        assert_eq!(out, b"data");
+        assert_matches!(inflator.warnings(), [FlagCheckMismatch(_)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated_trailer_policy() -> InflateResult<()> {
+        let compressed = compress(b"data", Compression::default());
+        for length in 0..4 {
+            let truncated = &compressed[..compressed.len() - 4 + length];
+            assert_matches!(
+                decompress_with(truncated, InflateOptions::default()),
+                Err(TruncatedTrailer(l)) if l == length
+            );
+            assert_eq!(
+                decompress_with(truncated, InflateOptions::lenient())?,
+                b"data"
+            );
+            let (out, inflator) = decompress_with_warnings(truncated)?;
+            assert_eq!(out, b"data");
+            assert_matches!(inflator.warnings(), [TruncatedTrailer(l)] if *l == length);
+        }
+        // A bad checksum is an error regardless.
+        let mut corrupted = compressed.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert_matches!(
+            decompress_with(&corrupted, InflateOptions::lenient()),
+            Err(ChecksumMismatch(_, _))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_trailing_data_policy() -> InflateResult<()> {
+        let mut compressed = compress(b"data", Compression::default());
+        assert_eq!(
+            decompress_with(&compressed, InflateOptions::strict())?,
+            b"data"
+        );
+        compressed.extend(b"garbage");
+        assert_eq!(
+            decompress_with(&compressed, InflateOptions::default())?,
+            b"data"
+        );
+        assert_matches!(
+            decompress_with(&compressed, InflateOptions::strict()),
+            Err(TrailingData)
+        );
+        let (out, inflator) = decompress_with_warnings(&compressed)?;
+        assert_eq!(out, b"data");
+        assert_matches!(inflator.warnings(), [TrailingData]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_options() {
+        assert_eq!(
+            InflateOptions::default(),
+            InflateOptions {
+                flag_check: Policy::Error,
+                truncated_trailer: Policy::Error,
+                trailing_data: Policy::Ignore,
+                excess_length_codes: Policy::Ignore,
+                empty_distance_code: Policy::Ignore,
+                distance_beyond_window: Policy::Ignore,
+                full_window: false,
+            }
+        );
+    }
 }
@@ -1,6 +1,6 @@
 use std::ops::Index;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Instruction {
     Literal(u8),
     EndOfBlock,
@@ -31,6 +31,17 @@ impl History {
         self.length
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    // The bytes in the history, oldest first.
+    pub fn to_vec(&self) -> Vec<u8> {
+        (0..self.length)
+            .map(|index| self.buffer[self.buffer_index(index)])
+            .collect()
+    }
+
     // Translate a history index to an internal buffer index.
     fn buffer_index(&self, index: usize) -> usize {
         (self.start + index) % self.max_length()
@@ -52,6 +63,16 @@ impl History {
             self.append(byte);
         }
     }
+
+    // Copy `length` bytes starting `distance` bytes back, appending them to
+    // both the history and `out`. The source and destination may overlap.
+    pub fn copy_back_reference(&mut self, length: usize, distance: usize, out: &mut Vec<u8>) {
+        for _ in 0..length {
+            let byte = self[-(distance as isize)];
+            self.append(byte);
+            out.push(byte);
+        }
+    }
 }
 
 impl Index<isize> for History {
@@ -63,13 +84,13 @@ impl Index<isize> for History {
             if index >= self.length {
                 panic!("Index out of bounds: {} vs {}", index, self.length);
             }
-            return &self.buffer[self.buffer_index(index as usize)];
+            return &self.buffer[self.buffer_index(index)];
         }
         // Negative index
         if index < -(self.length as isize) {
             panic!("Index out of bounds: {} vs {}", index, self.length);
         }
-        return &self.buffer[self.buffer_index((self.length as isize + index) as usize)];
+        &self.buffer[self.buffer_index((self.length as isize + index) as usize)]
     }
 }
 
@@ -77,6 +98,15 @@ impl Index<isize> for History {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_to_vec() {
+        let mut history = History::new(4);
+        history.extend(b"ab");
+        assert_eq!(history.to_vec(), b"ab");
+        history.extend(b"cdef");
+        assert_eq!(history.to_vec(), b"cdef");
+    }
+
     #[test]
     fn test_eviction() {
         let mut history = History::new(3);
@@ -113,6 +143,19 @@ mod tests {
         assert_eq!(history[-3], 0);
     }
 
+    #[test]
+    fn test_copy_back_reference() {
+        let mut history = History::new(4);
+        history.extend(&[0, 1, 2]);
+
+        let mut out = Vec::new();
+        // Overlapping copy repeats the last two bytes.
+        history.copy_back_reference(5, 2, &mut out);
+        assert_eq!(out, vec![1, 2, 1, 2, 1]);
+        assert_eq!(history[-1], 1);
+        assert_eq!(history[-4], 2);
+    }
+
     #[test]
     fn test_negative_index_underfull() {
         // One slot is not yet filled.
@@ -0,0 +1,874 @@
+use crate::lz77::Instruction;
+
+pub const MIN_MATCH: usize = 3;
+pub const MAX_MATCH: usize = 258;
+pub const WINDOW_SIZE: usize = 32 * 1024;
+
+// Lookahead needed to find a match of maximal length and then evaluate a lazy
+// match at the following position.
+const MIN_LOOKAHEAD: usize = MAX_MATCH + MIN_MATCH + 1;
+
+const HASH_BITS: u32 = 15;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+const HASH_SHIFT: u32 = HASH_BITS.div_ceil(MIN_MATCH as u32);
+
+// Marks an empty hash chain entry.
+const NIL: usize = usize::MAX;
+
+// Matches of the minimum length at a distance larger than this are usually
+// cheaper to encode as literals.
+const TOO_FAR: usize = 4096;
+
+// How to choose between the matches found at consecutive positions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parsing {
+    // Take the longest match at the current position.
+    Greedy,
+    // Check whether the next position has a longer match before committing
+    // to the match at the current position.
+    Lazy,
+    // Only emit literals, leaving all compression to the entropy coder.
+    Literals,
+    // Only look for runs of the previous byte, i.e. matches at distance 1.
+    Rle,
+}
+
+// Tuning parameters for the match search, with the same meaning as in zlib's
+// configuration table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchParams {
+    pub parsing: Parsing,
+    // Maximum number of hash chain entries to examine for each position.
+    pub max_chain: usize,
+    // If the previous match is at least this long, only a quarter of the chain
+    // is searched for a better lazy match.
+    pub good_length: usize,
+    // Stop searching once a match at least this long is found.
+    pub nice_length: usize,
+    // Don't look for a lazy match if the current match is at least this long.
+    // With greedy parsing, this is instead the maximum match length for which
+    // every position in the match is added to the hash chains.
+    pub max_lazy: usize,
+    // With lazy parsing, matches shorter than this are emitted as literals
+    // instead.
+    pub min_length: usize,
+}
+
+impl Default for MatchParams {
+    // Equivalent to zlib's default compression level (6).
+    fn default() -> Self {
+        Self {
+            parsing: Parsing::Lazy,
+            max_chain: 128,
+            good_length: 8,
+            nice_length: 128,
+            max_lazy: 16,
+            min_length: MIN_MATCH,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Match {
+    length: usize,
+    distance: usize,
+}
+
+impl Match {
+    const NONE: Match = Match {
+        length: 0,
+        distance: 0,
+    };
+
+    fn instruction(&self) -> Instruction {
+        Instruction::BackReference {
+            length: self.length as u16,
+            distance: self.distance as u16,
+        }
+    }
+}
+
+// Number of leading bytes that are equal in both slices.
+pub(crate) fn common_prefix_length(a: &[u8], b: &[u8]) -> usize {
+    let length = a.len().min(b.len());
+    let mut i = 0;
+    while i + 8 <= length {
+        let x = u64::from_le_bytes(a[i..i + 8].try_into().unwrap());
+        let y = u64::from_le_bytes(b[i..i + 8].try_into().unwrap());
+        let difference = x ^ y;
+        if difference != 0 {
+            return i + (difference.trailing_zeros() / 8) as usize;
+        }
+        i += 8;
+    }
+    while i < length && a[i] == b[i] {
+        i += 1;
+    }
+    i
+}
+
+// Turns a stream of bytes into LZ77 instructions, using hash chains of
+// previous positions to find matches within the window, in the style of zlib.
+//
+// Positions are tracked as absolute offsets into the input stream, so the hash
+// chains never need to be rebased as the window slides forward.
+#[derive(Debug)]
+pub struct MatchFinder {
+    params: MatchParams,
+    // Buffered input. buffer[0] is at stream position `buffer_start`.
+    buffer: Vec<u8>,
+    buffer_start: usize,
+    // Position of the next byte to search for a match at.
+    search_position: usize,
+    // Position up to which instructions have been emitted. This trails
+    // `search_position` when a lazy match decision is pending.
+    position: usize,
+    // Back-references may not point before this position.
+    window_start: usize,
+    // Bytes from this position onwards are kept in the buffer even if they
+    // slide out of the window.
+    retain_from: Option<usize>,
+    // Most recent position for each hash value.
+    head: Vec<usize>,
+    // Previous position with the same hash, indexed by position modulo the
+    // window size.
+    prev: Vec<usize>,
+    // Match found at `position - 1` that is waiting to be compared against the
+    // match at `position`. Only used for lazy matching.
+    pending: Option<Match>,
+    // Length of the preset dictionary at the start of the buffer.
+    dictionary_length: usize,
+}
+
+impl MatchFinder {
+    pub fn new(params: MatchParams) -> Self {
+        Self {
+            params,
+            buffer: Vec::new(),
+            buffer_start: 0,
+            search_position: 0,
+            position: 0,
+            window_start: 0,
+            retain_from: None,
+            head: vec![NIL; HASH_SIZE],
+            prev: vec![NIL; WINDOW_SIZE],
+            pending: None,
+            dictionary_length: 0,
+        }
+    }
+
+    pub fn params(&self) -> MatchParams {
+        self.params
+    }
+
+    // Change the search parameters. The hash chains and window carry over.
+    // Should only be called after draining the input, as a pending lazy match
+    // decision would be lost.
+    pub fn set_params(&mut self, params: MatchParams) {
+        debug_assert!(self.pending.is_none());
+        self.params = params;
+    }
+
+    // Total number of bytes covered by emitted instructions.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    // Total number of bytes pushed so far.
+    pub fn end(&self) -> usize {
+        self.buffer_start + self.buffer.len()
+    }
+
+    fn lookahead(&self) -> usize {
+        self.end() - self.search_position
+    }
+
+    fn byte(&self, position: usize) -> u8 {
+        self.buffer[position - self.buffer_start]
+    }
+
+    // Buffer more input.
+    pub fn push(&mut self, data: &[u8]) {
+        // Discard data that can no longer be referenced. This is only done once
+        // a full window's worth can be dropped, to amortize the cost of moving
+        // the remaining data.
+        let keep_from = self.position.saturating_sub(WINDOW_SIZE).min(
+            self.retain_from
+                .map_or(usize::MAX, |start| start.saturating_sub(WINDOW_SIZE)),
+        );
+        if keep_from >= self.buffer_start + WINDOW_SIZE {
+            self.buffer.drain(..keep_from - self.buffer_start);
+            self.buffer_start = keep_from;
+        }
+        self.buffer.extend_from_slice(data);
+    }
+
+    // Make `dictionary` available for back-references, as if it had been
+    // pushed and emitted, without producing any instructions for it. Only the
+    // last window's worth is used. Replaces any earlier dictionary. Must be
+    // called before any input is pushed.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) {
+        assert_eq!(
+            self.end(),
+            self.dictionary_length,
+            "Dictionary set after input was pushed"
+        );
+        if self.dictionary_length > 0 {
+            *self = Self::new(self.params);
+        }
+        let dictionary = &dictionary[dictionary.len().saturating_sub(WINDOW_SIZE)..];
+        self.dictionary_length = dictionary.len();
+        self.buffer.extend_from_slice(dictionary);
+        // Strings starting in the last MIN_MATCH - 1 bytes can't be hashed,
+        // so matches are never found there.
+        for position in 0..dictionary.len() {
+            self.insert(position);
+        }
+        self.search_position = dictionary.len();
+        self.position = dictionary.len();
+    }
+
+    // Forget all input before the current position, so that later matches
+    // can't reference it. Any pending lazy match decision is dropped, so this
+    // should only be called after draining the input.
+    pub fn reset(&mut self) {
+        self.window_start = self.search_position;
+        self.pending = None;
+    }
+
+    // Keep all bytes from the current position onwards until the next call,
+    // regardless of the window. They can be retrieved with `retained()`.
+    pub fn retain_from_position(&mut self) {
+        self.retain_from = Some(self.position);
+    }
+
+    // Bytes between the last call to `retain_from_position()` and the current
+    // position.
+    pub fn retained(&self) -> &[u8] {
+        match self.retain_from {
+            Some(start) => {
+                &self.buffer[start - self.buffer_start..self.position - self.buffer_start]
+            }
+            None => &[],
+        }
+    }
+
+    // The retained bytes, preceded by the window that back-references from
+    // them may point into. Returns the data and the offset of the retained
+    // bytes within it.
+    pub fn retained_with_window(&self) -> (&[u8], usize) {
+        let Some(start) = self.retain_from else {
+            return (&[], 0);
+        };
+        let window_start = start
+            .saturating_sub(WINDOW_SIZE)
+            .max(self.window_start.min(start));
+        let data =
+            &self.buffer[window_start - self.buffer_start..self.position - self.buffer_start];
+        (data, start - window_start)
+    }
+
+    fn hash(&self, position: usize) -> usize {
+        let i = position - self.buffer_start;
+        let mut hash = 0;
+        for &byte in &self.buffer[i..i + MIN_MATCH] {
+            hash = ((hash << HASH_SHIFT) ^ byte as usize) & (HASH_SIZE - 1);
+        }
+        hash
+    }
+
+    // Add the string starting at `position` to its hash chain.
+    fn insert(&mut self, position: usize) {
+        if position + MIN_MATCH > self.end() {
+            return;
+        }
+        let hash = self.hash(position);
+        self.prev[position % WINDOW_SIZE] = self.head[hash];
+        self.head[hash] = position;
+    }
+
+    // Find the longest match for the string at `position` that is longer than
+    // `previous_length`. The string must already have been inserted.
+    fn longest_match(&self, position: usize, previous_length: usize) -> Match {
+        let max_length = MAX_MATCH.min(self.lookahead());
+        if max_length < MIN_MATCH {
+            return Match::NONE;
+        }
+        let nice_length = self.params.nice_length.min(max_length);
+        let mut chain_length = self.params.max_chain;
+        if previous_length >= self.params.good_length {
+            chain_length >>= 2;
+        }
+        let limit = position.saturating_sub(WINDOW_SIZE).max(self.window_start);
+
+        let current = &self.buffer[position - self.buffer_start..][..max_length];
+        let mut best = Match {
+            length: previous_length.max(MIN_MATCH - 1),
+            distance: 0,
+        };
+        let mut candidate = self.prev[position % WINDOW_SIZE];
+        while candidate != NIL && candidate >= limit && chain_length > 0 {
+            let start = candidate - self.buffer_start;
+            let previous = &self.buffer[start..start + max_length];
+            // Quick rejection before comparing the whole string.
+            if previous[best.length.min(max_length - 1)] == current[best.length.min(max_length - 1)]
+            {
+                let length = common_prefix_length(previous, current);
+                if length > best.length {
+                    best = Match {
+                        length,
+                        distance: position - candidate,
+                    };
+                    if length >= nice_length {
+                        break;
+                    }
+                }
+            }
+            let next = self.prev[candidate % WINDOW_SIZE];
+            // The chain entry may have been overwritten by a newer position.
+            if next != NIL && next >= candidate {
+                break;
+            }
+            candidate = next;
+            chain_length -= 1;
+        }
+        if best.distance == 0 {
+            return Match::NONE;
+        }
+        best
+    }
+
+    // Emit instructions for the buffered input, appending them to `out`. Stops
+    // once `out` holds at least `limit` instructions. Unless `drain` is set,
+    // enough input is held back to keep finding maximal matches once more
+    // input is pushed. With `drain` set, all buffered input is consumed.
+    pub fn find(&mut self, out: &mut Vec<Instruction>, limit: usize, drain: bool) {
+        match self.params.parsing {
+            Parsing::Greedy => self.find_greedy(out, limit, drain),
+            Parsing::Lazy => self.find_lazy(out, limit, drain),
+            Parsing::Literals => self.find_literals(out, limit),
+            Parsing::Rle => self.find_rle(out, limit, drain),
+        }
+    }
+
+    fn has_lookahead(&self, drain: bool) -> bool {
+        let lookahead = self.lookahead();
+        lookahead != 0 && (drain || lookahead >= MIN_LOOKAHEAD)
+    }
+
+    // Equivalent to zlib's deflate_fast().
+    fn find_greedy(&mut self, out: &mut Vec<Instruction>, limit: usize, drain: bool) {
+        while out.len() < limit && self.has_lookahead(drain) {
+            let position = self.search_position;
+            self.insert(position);
+            let current = self.longest_match(position, 0);
+            if current.length >= MIN_MATCH {
+                out.push(current.instruction());
+                let end = position + current.length;
+                // Skip updating the hash chains for long matches to save time.
+                if current.length <= self.params.max_lazy {
+                    for p in position + 1..end {
+                        self.insert(p);
+                    }
+                }
+                self.search_position = end;
+            } else {
+                out.push(Instruction::Literal(self.byte(position)));
+                self.search_position += 1;
+            }
+            self.position = self.search_position;
+        }
+    }
+
+    // Equivalent to zlib's deflate_slow().
+    fn find_lazy(&mut self, out: &mut Vec<Instruction>, limit: usize, drain: bool) {
+        while out.len() < limit && self.has_lookahead(drain) {
+            let position = self.search_position;
+            self.insert(position);
+            let previous = self.pending.take();
+            let previous_length = previous.map_or(0, |m| m.length);
+            let mut current = if previous_length < self.params.max_lazy {
+                self.longest_match(position, previous_length)
+            } else {
+                Match::NONE
+            };
+            if current.length < self.params.min_length
+                || (current.length == MIN_MATCH && current.distance > TOO_FAR)
+            {
+                current = Match::NONE;
+            }
+
+            if previous_length >= MIN_MATCH && current.length <= previous_length {
+                // The match at the previous position is at least as good.
+                let previous = previous.unwrap();
+                out.push(previous.instruction());
+                let end = position - 1 + previous.length;
+                for p in position + 1..end {
+                    self.insert(p);
+                }
+                self.search_position = end;
+                self.position = end;
+            } else {
+                if previous.is_some() {
+                    out.push(Instruction::Literal(self.byte(position - 1)));
+                    self.position = position;
+                }
+                // Defer the decision on this position until the next one has
+                // been searched.
+                self.pending = Some(current);
+                self.search_position += 1;
+            }
+        }
+        if drain && self.lookahead() == 0 && self.pending.take().is_some() {
+            out.push(Instruction::Literal(self.byte(self.search_position - 1)));
+            self.position = self.search_position;
+        }
+    }
+
+    // Equivalent to zlib's deflate_huff().
+    fn find_literals(&mut self, out: &mut Vec<Instruction>, limit: usize) {
+        while out.len() < limit && self.lookahead() != 0 {
+            out.push(Instruction::Literal(self.byte(self.search_position)));
+            self.search_position += 1;
+            self.position = self.search_position;
+        }
+    }
+
+    // Equivalent to zlib's deflate_rle().
+    fn find_rle(&mut self, out: &mut Vec<Instruction>, limit: usize, drain: bool) {
+        while out.len() < limit && self.has_lookahead(drain) {
+            let position = self.search_position;
+            let mut length = 0;
+            if position > self.window_start {
+                let previous = self.byte(position - 1);
+                let max_length = MAX_MATCH.min(self.lookahead());
+                length = self.buffer[position - self.buffer_start..][..max_length]
+                    .iter()
+                    .take_while(|&&byte| byte == previous)
+                    .count();
+            }
+            if length >= MIN_MATCH {
+                out.push(Instruction::BackReference {
+                    length: length as u16,
+                    distance: 1,
+                });
+                self.search_position += length;
+            } else {
+                out.push(Instruction::Literal(self.byte(position)));
+                self.search_position += 1;
+            }
+            self.position = self.search_position;
+        }
+    }
+}
+
+// Convenience function for finding matches over a complete input.
+pub fn find_matches(data: &[u8], params: MatchParams) -> Vec<Instruction> {
+    let mut finder = MatchFinder::new(params);
+    finder.push(data);
+    let mut instructions = Vec::new();
+    finder.find(&mut instructions, usize::MAX, true);
+    instructions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_reader::{BitRead, BitReader};
+    use crate::bit_writer::BitWriter;
+    use crate::block_decoder::BlockDecoder;
+    use crate::block_encoder::write_fixed_block;
+    use crate::error::InflateResult;
+    use crate::lz77::History;
+
+    // Deterministic pseudo-random test data with a mix of repeated phrases
+    // and noise.
+    fn test_data(length: usize) -> Vec<u8> {
+        let words: [&[u8]; 6] = [
+            b"alpha ",
+            b"beta ",
+            b"gamma ",
+            b"delta\n",
+            b"\x00\x01",
+            b"zz",
+        ];
+        let mut state: u32 = 12345;
+        let mut data = Vec::new();
+        while data.len() < length {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            let r = (state >> 16) as usize;
+            if r.is_multiple_of(7) {
+                data.push(r as u8);
+            } else {
+                data.extend_from_slice(words[r % words.len()]);
+            }
+        }
+        data.truncate(length);
+        data
+    }
+
+    // Expand instructions back into bytes.
+    fn replay(instructions: &[Instruction]) -> Vec<u8> {
+        let mut history = History::new(WINDOW_SIZE);
+        let mut out = Vec::new();
+        for instruction in instructions {
+            match *instruction {
+                Instruction::Literal(byte) => {
+                    history.append(byte);
+                    out.push(byte);
+                }
+                Instruction::BackReference { length, distance } => {
+                    history.copy_back_reference(length as usize, distance as usize, &mut out);
+                }
+                Instruction::EndOfBlock => {}
+            }
+        }
+        out
+    }
+
+    fn assert_valid(instructions: &[Instruction]) {
+        for instruction in instructions {
+            if let Instruction::BackReference { length, distance } = *instruction {
+                assert!((MIN_MATCH..=MAX_MATCH).contains(&(length as usize)));
+                assert!((1..=WINDOW_SIZE).contains(&(distance as usize)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_common_prefix_length() {
+        assert_eq!(common_prefix_length(b"", b"abc"), 0);
+        assert_eq!(common_prefix_length(b"abc", b"abd"), 2);
+        assert_eq!(common_prefix_length(b"0123456789ab", b"0123456789ab"), 12);
+        assert_eq!(common_prefix_length(b"0123456789ab", b"0123456789xb"), 10);
+    }
+
+    #[test]
+    fn test_no_matches() {
+        let instructions = find_matches(b"abcdef", MatchParams::default());
+        assert_eq!(
+            instructions,
+            b"abcdef"
+                .iter()
+                .map(|&b| Instruction::Literal(b))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_repeated_byte() {
+        let instructions = find_matches(&[7; 300], MatchParams::default());
+        assert_eq!(
+            instructions,
+            vec![
+                Instruction::Literal(7),
+                Instruction::BackReference {
+                    length: 258,
+                    distance: 1
+                },
+                Instruction::BackReference {
+                    length: 41,
+                    distance: 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lazy_match() {
+        // At "bcdef", the greedy choice would be "bcd" from the first string,
+        // but deferring by one byte gives the longer match "cdef".
+        let instructions = find_matches(b"bcdxxcdefyyabcdef", MatchParams::default());
+        assert_eq!(
+            &instructions[11..],
+            &[
+                Instruction::Literal(b'a'),
+                Instruction::Literal(b'b'),
+                Instruction::BackReference {
+                    length: 4,
+                    distance: 8
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_greedy_match() {
+        // Greedy parsing takes the first match "bcd", so "ef" become literals.
+        let params = MatchParams {
+            parsing: Parsing::Greedy,
+            ..MatchParams::default()
+        };
+        let instructions = find_matches(b"bcdxxcdefyyabcdef", params);
+        assert_eq!(
+            &instructions[11..],
+            &[
+                Instruction::Literal(b'a'),
+                Instruction::BackReference {
+                    length: 3,
+                    distance: 12
+                },
+                Instruction::Literal(b'e'),
+                Instruction::Literal(b'f'),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_greedy_replay() {
+        let data = test_data(100_000);
+        let params = MatchParams {
+            parsing: Parsing::Greedy,
+            max_chain: 8,
+            good_length: 4,
+            nice_length: 16,
+            max_lazy: 5,
+            min_length: MIN_MATCH,
+        };
+        let instructions = find_matches(&data, params);
+        assert_valid(&instructions);
+        assert_eq!(replay(&instructions), data);
+    }
+
+    #[test]
+    fn test_replay() {
+        let data = test_data(100_000);
+        let instructions = find_matches(&data, MatchParams::default());
+        assert_valid(&instructions);
+        assert!(instructions.len() < data.len() / 3);
+        assert_eq!(replay(&instructions), data);
+    }
+
+    #[test]
+    fn test_incremental_push() {
+        let data = test_data(100_000);
+        let mut finder = MatchFinder::new(MatchParams::default());
+        let mut instructions = Vec::new();
+        for chunk in data.chunks(1000) {
+            finder.push(chunk);
+            finder.find(&mut instructions, usize::MAX, false);
+        }
+        assert!(finder.position() < data.len());
+        finder.find(&mut instructions, usize::MAX, true);
+        assert_eq!(finder.position(), data.len());
+        assert_eq!(instructions, find_matches(&data, MatchParams::default()));
+    }
+
+    #[test]
+    fn test_retained() {
+        let data = test_data(100_000);
+        let mut finder = MatchFinder::new(MatchParams::default());
+        finder.push(&data[..50_000]);
+        let mut instructions = Vec::new();
+        finder.find(&mut instructions, 100, false);
+        let start = finder.position();
+        finder.retain_from_position();
+        finder.find(&mut instructions, usize::MAX, false);
+        finder.push(&data[50_000..]);
+        finder.find(&mut instructions, usize::MAX, true);
+        assert_eq!(finder.retained(), &data[start..]);
+    }
+
+    #[test]
+    fn test_retained_with_window() {
+        let data = test_data(200_000);
+        let mut finder = MatchFinder::new(MatchParams::default());
+        let mut instructions = Vec::new();
+        for chunk in data[..100_000].chunks(10_000) {
+            finder.push(chunk);
+            finder.find(&mut instructions, usize::MAX, false);
+        }
+        let start = finder.position();
+        finder.retain_from_position();
+        for chunk in data[100_000..].chunks(10_000) {
+            finder.push(chunk);
+            finder.find(&mut instructions, usize::MAX, false);
+        }
+        let (window, offset) = finder.retained_with_window();
+        assert_eq!(offset, WINDOW_SIZE);
+        assert_eq!(window, &data[start - WINDOW_SIZE..finder.position()]);
+
+        // Nothing before a reset can be referenced.
+        finder.find(&mut instructions, usize::MAX, true);
+        finder.reset();
+        finder.retain_from_position();
+        finder.push(b"after reset");
+        finder.find(&mut instructions, usize::MAX, true);
+        assert_eq!(
+            finder.retained_with_window(),
+            (b"after reset".as_slice(), 0)
+        );
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut finder = MatchFinder::new(MatchParams::default());
+        let mut instructions = Vec::new();
+        finder.push(b"abcdef");
+        finder.find(&mut instructions, usize::MAX, true);
+        finder.reset();
+        finder.push(b"abcdef");
+        finder.find(&mut instructions, usize::MAX, true);
+        // The second copy can't refer to the first.
+        assert_eq!(instructions.len(), 12);
+    }
+
+    #[test]
+    fn test_max_distance() {
+        // Two copies of a random block that are exactly a window apart.
+        let mut data = test_data(WINDOW_SIZE);
+        let mut state: u32 = 1;
+        for byte in data.iter_mut() {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            *byte = (state >> 16) as u8;
+        }
+        data.extend_from_within(..1000);
+        let instructions = find_matches(&data, MatchParams::default());
+        assert_valid(&instructions);
+        assert!(instructions.contains(&Instruction::BackReference {
+            length: 258,
+            distance: WINDOW_SIZE as u16
+        }));
+        assert_eq!(replay(&instructions), data);
+    }
+
+    #[test]
+    fn test_round_trip_through_block_decoder() -> InflateResult<()> {
+        let data = test_data(20_000);
+        let instructions = find_matches(&data, MatchParams::default());
+        let mut writer = BitWriter::new(Vec::new());
+        write_fixed_block(&mut writer, &instructions, true)?;
+        writer.align()?;
+        let raw = std::mem::take(writer.get_mut());
+        assert!(raw.len() < data.len() / 2);
+
+        let mut reader = BitReader::new(raw.as_slice());
+        assert!(reader.read_bit()?);
+        assert_eq!(reader.read_bits::<u8>(2)?, 1);
+        let mut decoder = BlockDecoder::new_fixed(&mut reader);
+        let mut decoded = Vec::new();
+        loop {
+            let instruction = decoder.next()?;
+            if instruction == Instruction::EndOfBlock {
+                break;
+            }
+            decoded.push(instruction);
+        }
+        assert_eq!(decoded, instructions);
+        assert_eq!(replay(&decoded), data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_filtered_min_length() {
+        let data = b"abcdefabcdef-abcdeabcde";
+        let params = MatchParams {
+            min_length: 6,
+            ..MatchParams::default()
+        };
+        let instructions = find_matches(data, params);
+        assert_eq!(replay(&instructions), data);
+        // Only the six byte match is kept.
+        assert_eq!(
+            instructions
+                .iter()
+                .filter(|i| matches!(i, Instruction::BackReference { .. }))
+                .collect::<Vec<_>>(),
+            vec![&Instruction::BackReference {
+                length: 6,
+                distance: 6
+            }]
+        );
+    }
+
+    #[test]
+    fn test_literals() {
+        let data = test_data(10_000);
+        let params = MatchParams {
+            parsing: Parsing::Literals,
+            ..MatchParams::default()
+        };
+        let instructions = find_matches(&data, params);
+        assert_eq!(instructions.len(), data.len());
+        assert_eq!(replay(&instructions), data);
+    }
+
+    #[test]
+    fn test_rle() {
+        let data = [b"ab".as_slice(), &[b'x'; 300], b"abab", &[0; 3]].concat();
+        let params = MatchParams {
+            parsing: Parsing::Rle,
+            ..MatchParams::default()
+        };
+        let instructions = find_matches(&data, params);
+        let backreference = |length| Instruction::BackReference {
+            length,
+            distance: 1,
+        };
+        assert_eq!(
+            instructions,
+            [
+                vec![Instruction::Literal(b'a'), Instruction::Literal(b'b')],
+                vec![
+                    Instruction::Literal(b'x'),
+                    backreference(258),
+                    backreference(41)
+                ],
+                b"abab".iter().map(|&b| Instruction::Literal(b)).collect(),
+                vec![
+                    Instruction::Literal(0),
+                    Instruction::Literal(0),
+                    Instruction::Literal(0)
+                ],
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_rle_round_trip() {
+        let data = test_data(50_000);
+        let params = MatchParams {
+            parsing: Parsing::Rle,
+            ..MatchParams::default()
+        };
+        let mut finder = MatchFinder::new(params);
+        let mut instructions = Vec::new();
+        for chunk in data.chunks(1000) {
+            finder.push(chunk);
+            finder.find(&mut instructions, usize::MAX, false);
+        }
+        finder.find(&mut instructions, usize::MAX, true);
+        assert_valid(&instructions);
+        assert_eq!(replay(&instructions), data);
+    }
+
+    #[test]
+    fn test_dictionary() {
+        let mut finder = MatchFinder::new(MatchParams::default());
+        finder.set_dictionary(b"xxhello world");
+        finder.push(b"hello there");
+        let mut instructions = Vec::new();
+        finder.find(&mut instructions, usize::MAX, true);
+        assert_eq!(
+            instructions[0],
+            Instruction::BackReference {
+                length: 6,
+                distance: 11
+            }
+        );
+        assert_eq!(instructions.len(), 6);
+        assert_eq!(finder.position(), 24);
+    }
+
+    #[test]
+    fn test_dictionary_uses_last_window() {
+        let dictionary = [b"abcdefgh".as_slice(), &vec![0; WINDOW_SIZE]].concat();
+        let mut finder = MatchFinder::new(MatchParams::default());
+        finder.set_dictionary(&dictionary);
+        finder.push(b"abcdefgh");
+        let mut instructions = Vec::new();
+        finder.find(&mut instructions, usize::MAX, true);
+        assert_eq!(instructions, b"abcdefgh".map(Instruction::Literal));
+    }
+}
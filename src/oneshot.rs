@@ -0,0 +1,260 @@
+// Compression and decompression of whole buffers in a single call, for each
+// container format.
+
+use crate::Inflator;
+use crate::deflate::{BLOCK_INSTRUCTIONS, Compression};
+use crate::encoder::encode;
+use crate::error::InflateResult;
+use crate::format::Format;
+use crate::gzip::GzDecoder;
+use crate::inflate::RawInflator;
+use std::io;
+
+// Sizes of the zlib header and trailer, and of the gzip ones without
+// optional header fields.
+const ZLIB_OVERHEAD: usize = 2 + 4;
+const GZIP_OVERHEAD: usize = 10 + 8;
+
+// Largest DEFLATE expansion: a 258 byte match coded in a single bit. Output
+// size hints are capped by it.
+const MAX_RATIO: usize = 1032;
+
+// Without a size hint, the output is pre-sized for this ratio.
+const DEFAULT_RATIO: usize = 4;
+
+// Worst-case size of the raw DEFLATE stream for `length` bytes of input. The
+// encoder falls back to stored blocks when compression doesn't pay off, and
+// each block covers at most BLOCK_INSTRUCTIONS literals, so this allows for a
+// stored block header, with its padding, per block. There is an extra block
+// for a trailing empty final block.
+pub fn raw_compress_bound(length: usize) -> usize {
+    length + 6 * (length / BLOCK_INSTRUCTIONS + 2)
+}
+
+// Worst-case size of the zlib stream for `length` bytes of input, like
+// zlib's compressBound().
+pub fn compress_bound(length: usize) -> usize {
+    raw_compress_bound(length) + ZLIB_OVERHEAD
+}
+
+// Worst-case size of a gzip member for `length` bytes of input, as written
+// by gzip_compress().
+pub fn gzip_compress_bound(length: usize) -> usize {
+    raw_compress_bound(length) + GZIP_OVERHEAD
+}
+
+fn compress_format(input: &[u8], format: Format, compression: Compression) -> Vec<u8> {
+    // Writes to a Vec can't fail.
+    encode(input, Vec::new(), format, compression).unwrap()
+}
+
+// Compress into `output`, returning the compressed size. Fails with
+// ErrorKind::WriteZero if it doesn't fit, which can't happen if `output`
+// has the length given by the matching bound function.
+fn compress_format_to_slice(
+    input: &[u8],
+    output: &mut [u8],
+    format: Format,
+    compression: Compression,
+) -> io::Result<usize> {
+    let capacity = output.len();
+    let remaining = encode(input, output, format, compression)?;
+    Ok(capacity - remaining.len())
+}
+
+// Compress `input` into a zlib stream.
+pub fn compress(input: &[u8], compression: Compression) -> Vec<u8> {
+    compress_format(input, Format::Zlib, compression)
+}
+
+// Compress `input` into a zlib stream in `output`, returning its size. An
+// output of compress_bound() bytes is always large enough.
+pub fn compress_to_slice(
+    input: &[u8],
+    output: &mut [u8],
+    compression: Compression,
+) -> io::Result<usize> {
+    compress_format_to_slice(input, output, Format::Zlib, compression)
+}
+
+// Decompress a zlib stream, verifying its checksum. Streams that need a
+// preset dictionary fail with DictionaryRequired.
+pub fn decompress(input: &[u8]) -> InflateResult<Vec<u8>> {
+    let mut out = Vec::with_capacity(input.len() * DEFAULT_RATIO);
+    Inflator::try_new(input)?.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+// Compress `input` into a gzip member without optional header fields. Use
+// GzEncoder to set them.
+pub fn gzip_compress(input: &[u8], compression: Compression) -> Vec<u8> {
+    compress_format(input, Format::Gzip, compression)
+}
+
+// Compress `input` into a gzip member in `output`, returning its size. An
+// output of gzip_compress_bound() bytes is always large enough.
+pub fn gzip_compress_to_slice(
+    input: &[u8],
+    output: &mut [u8],
+    compression: Compression,
+) -> io::Result<usize> {
+    compress_format_to_slice(input, output, Format::Gzip, compression)
+}
+
+// Decompress one or more concatenated gzip members, verifying each trailer,
+// as `gzip -d` does. The output is pre-sized from the last member's ISIZE,
+// which is exact for single-member files under 4 GiB.
+pub fn gzip_decompress(input: &[u8]) -> InflateResult<Vec<u8>> {
+    let size_hint = match input.len().checked_sub(4) {
+        Some(start) => u32::from_le_bytes(input[start..].try_into().unwrap()) as usize,
+        None => 0,
+    };
+    let mut out = Vec::with_capacity(size_hint.min(input.len() * MAX_RATIO));
+    let mut input = input;
+    loop {
+        GzDecoder::try_new(&mut input)?.read_to_end(&mut out)?;
+        if input.is_empty() {
+            return Ok(out);
+        }
+    }
+}
+
+// Compress `input` into a raw DEFLATE stream.
+pub fn raw_compress(input: &[u8], compression: Compression) -> Vec<u8> {
+    compress_format(input, Format::Raw, compression)
+}
+
+// Compress `input` into a raw DEFLATE stream in `output`, returning its size.
+// An output of raw_compress_bound() bytes is always large enough.
+pub fn raw_compress_to_slice(
+    input: &[u8],
+    output: &mut [u8],
+    compression: Compression,
+) -> io::Result<usize> {
+    compress_format_to_slice(input, output, Format::Raw, compression)
+}
+
+// Decompress a raw DEFLATE stream.
+pub fn raw_decompress(input: &[u8]) -> InflateResult<Vec<u8>> {
+    let mut out = Vec::with_capacity(input.len() * DEFAULT_RATIO);
+    RawInflator::new(input).read_to_end(&mut out)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::InflateError;
+    use std::assert_matches;
+
+    fn text(length: usize) -> Vec<u8> {
+        b"one-shot helpers for the common case. "
+            .iter()
+            .copied()
+            .cycle()
+            .take(length)
+            .collect()
+    }
+
+    fn noise(length: usize) -> Vec<u8> {
+        let mut state: u32 = 5;
+        (0..length)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip() -> InflateResult<()> {
+        for data in [vec![], text(100_000), noise(50_000)] {
+            let compression = Compression::default();
+            assert_eq!(decompress(&compress(&data, compression))?, data);
+            assert_eq!(gzip_decompress(&gzip_compress(&data, compression))?, data);
+            assert_eq!(raw_decompress(&raw_compress(&data, compression))?, data);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_compress_bound() {
+        // Incompressible data is where the bound matters.
+        for length in [
+            0,
+            1,
+            100,
+            BLOCK_INSTRUCTIONS,
+            3 * BLOCK_INSTRUCTIONS + 1,
+            200_000,
+        ] {
+            let data = noise(length);
+            for level in [0, 1, 6, 9] {
+                let compression = Compression::new(level);
+                assert!(compress(&data, compression).len() <= compress_bound(length));
+                assert!(gzip_compress(&data, compression).len() <= gzip_compress_bound(length));
+                assert!(raw_compress(&data, compression).len() <= raw_compress_bound(length));
+            }
+            let extreme = compress(&data, Compression::extreme());
+            assert!(extreme.len() <= compress_bound(length));
+        }
+    }
+
+    #[test]
+    fn test_compress_to_slice() -> io::Result<()> {
+        let data = noise(70_000);
+        let mut output = vec![0; compress_bound(data.len())];
+        let size = compress_to_slice(&data, &mut output, Compression::default())?;
+        assert_eq!(output[..size], compress(&data, Compression::default()));
+
+        let mut output = vec![0; gzip_compress_bound(data.len())];
+        let size = gzip_compress_to_slice(&data, &mut output, Compression::fast())?;
+        assert_eq!(output[..size], gzip_compress(&data, Compression::fast()));
+
+        let mut output = vec![0; raw_compress_bound(data.len())];
+        let size = raw_compress_to_slice(&data, &mut output, Compression::best())?;
+        assert_eq!(output[..size], raw_compress(&data, Compression::best()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_too_small() {
+        let data = text(10_000);
+        let mut output = vec![0; 10];
+        let error = compress_to_slice(&data, &mut output, Compression::default()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::WriteZero);
+    }
+
+    #[test]
+    fn test_gzip_multiple_members() -> InflateResult<()> {
+        let first = text(5000);
+        let second = noise(3000);
+        let joined = [
+            gzip_compress(&first, Compression::default()),
+            gzip_compress(&second, Compression::best()),
+        ]
+        .concat();
+        assert_eq!(gzip_decompress(&joined)?, [first, second].concat());
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzip_errors() {
+        let mut compressed = gzip_compress(&text(1000), Compression::default());
+        let length = compressed.len();
+        compressed[length - 1] ^= 1;
+        assert_matches!(
+            gzip_decompress(&compressed),
+            Err(InflateError::SizeMismatch(..))
+        );
+        compressed[length - 8] ^= 1;
+        assert_matches!(
+            gzip_decompress(&compressed),
+            Err(InflateError::ChecksumMismatch(..))
+        );
+        assert_matches!(
+            gzip_decompress(&compress(b"zlib", Compression::default())),
+            Err(InflateError::InvalidGzipMagic(0x78, 0x9c))
+        );
+    }
+}
@@ -0,0 +1,370 @@
+use crate::block_encoder::{
+    BlockCosts, DISTANCE_COUNT, DynamicCodes, Frequencies, LL_COUNT, distance_symbol,
+    fixed_ll_lengths, length_symbol,
+};
+use crate::code_table::{CodeLength, code_lengths_from_frequencies};
+use crate::lz77::Instruction;
+use crate::match_finder::{MAX_MATCH, MIN_MATCH, WINDOW_SIZE, common_prefix_length};
+
+// Maximum number of times the parse is recomputed against the codes of the
+// previous parse.
+const ITERATIONS: usize = 15;
+
+// Maximum number of hash chain entries to examine for each position.
+const MAX_CHAIN: usize = 8192;
+
+const HASH_BITS: u32 = 15;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+
+// Marks an empty hash chain entry.
+const NIL: usize = usize::MAX;
+
+// Same hash as MatchFinder, over the MIN_MATCH bytes starting at `position`.
+fn hash(data: &[u8], position: usize) -> usize {
+    let shift = HASH_BITS.div_ceil(MIN_MATCH as u32);
+    data[position..position + MIN_MATCH]
+        .iter()
+        .fold(0, |hash, &byte| {
+            ((hash << shift) ^ byte as usize) & (HASH_SIZE - 1)
+        })
+}
+
+// The matches available at each position of a block. For each position, the
+// entries are (length, distance) pairs in order of increasing length and
+// distance: every length up to an entry's length, and above the previous
+// entry's length, is reachable at that entry's distance.
+#[derive(Debug)]
+struct MatchTable {
+    entries: Vec<(u16, u16)>,
+    // Entries for block position i are entries[offsets[i]..offsets[i + 1]].
+    offsets: Vec<usize>,
+}
+
+impl MatchTable {
+    // Search `data[start..]` for matches, which may reach back into the
+    // preceding window `data[..start]`.
+    fn new(data: &[u8], start: usize) -> Self {
+        let mut head = vec![NIL; HASH_SIZE];
+        let mut prev = vec![NIL; data.len()];
+        let insert = |head: &mut [usize], prev: &mut [usize], position: usize| {
+            if position + MIN_MATCH <= data.len() {
+                let hash = hash(data, position);
+                prev[position] = head[hash];
+                head[hash] = position;
+            }
+        };
+        for position in start.saturating_sub(WINDOW_SIZE)..start {
+            insert(&mut head, &mut prev, position);
+        }
+
+        let mut table = Self {
+            entries: Vec::new(),
+            offsets: vec![0],
+        };
+        for position in start..data.len() {
+            insert(&mut head, &mut prev, position);
+            table.add_matches(data, position, &prev);
+            table.offsets.push(table.entries.len());
+        }
+        table
+    }
+
+    fn add_matches(&mut self, data: &[u8], position: usize, prev: &[usize]) {
+        let max_length = MAX_MATCH.min(data.len() - position);
+        if max_length < MIN_MATCH {
+            return;
+        }
+        let limit = position.saturating_sub(WINDOW_SIZE);
+        let current = &data[position..position + max_length];
+        let mut best_length = MIN_MATCH - 1;
+        let mut candidate = prev[position];
+        let mut chain_length = MAX_CHAIN;
+        while candidate != NIL && candidate >= limit && chain_length > 0 {
+            // Quick rejection before comparing the whole string.
+            if data[candidate + best_length] == current[best_length] {
+                let length = common_prefix_length(&data[candidate..], current);
+                if length > best_length {
+                    best_length = length;
+                    self.entries
+                        .push((length as u16, (position - candidate) as u16));
+                    if length == max_length {
+                        break;
+                    }
+                }
+            }
+            candidate = prev[candidate];
+            chain_length -= 1;
+        }
+    }
+
+    fn at(&self, position: usize) -> &[(u16, u16)] {
+        &self.entries[self.offsets[position]..self.offsets[position + 1]]
+    }
+}
+
+// Cost in bits of each choice the parser can make, including extra bits.
+#[derive(Debug)]
+struct CostModel {
+    literal: [u32; 256],
+    length: [u32; MAX_MATCH + 1],
+    distance: [u32; DISTANCE_COUNT],
+}
+
+impl CostModel {
+    fn new(ll_lengths: &[CodeLength], distance_lengths: &[CodeLength]) -> Self {
+        let mut model = Self {
+            literal: [0; 256],
+            length: [0; MAX_MATCH + 1],
+            distance: [0; DISTANCE_COUNT],
+        };
+        for (cost, &length) in model.literal.iter_mut().zip(ll_lengths) {
+            *cost = length as u32;
+        }
+        for length in MIN_MATCH..=MAX_MATCH {
+            let symbol = length_symbol(length as u16);
+            model.length[length] = (ll_lengths[symbol.symbol] + symbol.extra_bit_count) as u32;
+        }
+        for distance in 1..=WINDOW_SIZE {
+            let symbol = distance_symbol(distance as u16);
+            model.distance[symbol.symbol] =
+                (distance_lengths[symbol.symbol] + symbol.extra_bit_count) as u32;
+        }
+        model
+    }
+
+    fn fixed() -> Self {
+        Self::new(&fixed_ll_lengths(), &[5; DISTANCE_COUNT])
+    }
+
+    // Costs under the Huffman codes built for `frequencies`. Symbols that
+    // don't occur are priced one bit longer than the longest code, so that the
+    // parser can still pick them when they save enough elsewhere.
+    fn from_frequencies(frequencies: &Frequencies) -> Self {
+        let fill_unused = |mut lengths: Vec<CodeLength>| {
+            let unused = lengths.iter().max().unwrap() + 1;
+            for length in lengths.iter_mut().filter(|l| **l == 0) {
+                *length = unused;
+            }
+            lengths
+        };
+        let ll_lengths = code_lengths_from_frequencies(&frequencies.ll, 15);
+        let distance_lengths = code_lengths_from_frequencies(&frequencies.distance, 15);
+        debug_assert_eq!(ll_lengths.len(), LL_COUNT);
+        Self::new(&fill_unused(ll_lengths), &fill_unused(distance_lengths))
+    }
+
+    fn match_cost(&self, length: u16, distance: u16) -> u32 {
+        self.length[length as usize] + self.distance[distance_symbol(distance).symbol]
+    }
+}
+
+// The cheapest sequence of instructions for `block` under `model`, found as
+// the shortest path through the graph of positions.
+fn shortest_path(block: &[u8], matches: &MatchTable, model: &CostModel) -> Vec<Instruction> {
+    let n = block.len();
+    let mut costs = vec![u32::MAX; n + 1];
+    // The (length, distance) of the step that reaches each position, with a
+    // distance of 0 for a literal.
+    let mut steps = vec![(0u16, 0u16); n + 1];
+    costs[0] = 0;
+    for i in 0..n {
+        let cost = costs[i];
+        let literal_cost = cost + model.literal[block[i] as usize];
+        if literal_cost < costs[i + 1] {
+            costs[i + 1] = literal_cost;
+            steps[i + 1] = (1, 0);
+        }
+        let mut length = MIN_MATCH as u16;
+        for &(max_length, distance) in matches.at(i) {
+            let distance_cost = model.distance[distance_symbol(distance).symbol];
+            while length <= max_length {
+                let end = i + length as usize;
+                let match_cost = cost + model.length[length as usize] + distance_cost;
+                if match_cost < costs[end] {
+                    costs[end] = match_cost;
+                    steps[end] = (length, distance);
+                }
+                length += 1;
+            }
+        }
+    }
+
+    let mut instructions = Vec::new();
+    let mut position = n;
+    while position > 0 {
+        let (length, distance) = steps[position];
+        position -= length as usize;
+        instructions.push(if distance == 0 {
+            Instruction::Literal(block[position])
+        } else {
+            Instruction::BackReference { length, distance }
+        });
+    }
+    instructions.reverse();
+    debug_assert_eq!(
+        costs[n],
+        instructions
+            .iter()
+            .map(|instruction| match *instruction {
+                Instruction::Literal(byte) => model.literal[byte as usize],
+                Instruction::BackReference { length, distance } =>
+                    model.match_cost(length, distance),
+                Instruction::EndOfBlock => 0,
+            })
+            .sum()
+    );
+    instructions
+}
+
+// Size in bits of a block with these instructions, using fixed or dynamic
+// codes, whichever is smaller.
+fn block_cost(instructions: &[Instruction]) -> usize {
+    let frequencies = Frequencies::new(instructions);
+    let codes = DynamicCodes::new(&frequencies);
+    let costs = BlockCosts::new(&frequencies, &codes, 0, 0);
+    costs.fixed.min(costs.dynamic)
+}
+
+// Find the instructions that encode `data[start..]` most compactly as a single
+// block, in the style of Zopfli: starting from the cheapest parse under the
+// fixed codes, repeatedly find the cheapest parse under the Huffman codes of
+// the previous parse, until the block stops getting smaller. Back-references
+// may reach into `data[..start]`.
+//
+// This is far slower than MatchFinder, and meant for data that is compressed
+// once and decompressed many times.
+pub fn optimal_parse(data: &[u8], start: usize) -> Vec<Instruction> {
+    let block = &data[start..];
+    let matches = MatchTable::new(data, start);
+    let mut best = shortest_path(block, &matches, &CostModel::fixed());
+    let mut best_cost = block_cost(&best);
+    let mut current = best.clone();
+    let mut previous_cost = usize::MAX;
+    for _ in 0..ITERATIONS {
+        let model = CostModel::from_frequencies(&Frequencies::new(&current));
+        current = shortest_path(block, &matches, &model);
+        let cost = block_cost(&current);
+        if cost < best_cost {
+            best = current.clone();
+            best_cost = cost;
+        }
+        if cost >= previous_cost {
+            break;
+        }
+        previous_cost = cost;
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_writer::BitWriter;
+    use crate::block_encoder::write_block;
+    use crate::error::InflateResult;
+    use crate::inflate::inflate;
+    use crate::match_finder::{MatchParams, Parsing, find_matches};
+
+    fn test_data(length: usize) -> Vec<u8> {
+        let words: [&[u8]; 7] = [
+            b"optimal ",
+            b"parsing ",
+            b"of ",
+            b"lz77 ",
+            b"matches ",
+            b"zopfli\n",
+            b"\x00\xff",
+        ];
+        let mut state: u32 = 99;
+        let mut data = Vec::new();
+        while data.len() < length {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            let r = (state >> 16) as usize;
+            if r.is_multiple_of(3) {
+                data.push(r as u8);
+            } else {
+                data.extend_from_slice(words[r % words.len()]);
+            }
+        }
+        data.truncate(length);
+        data
+    }
+
+    fn encode(instructions: &[Instruction], bytes: &[u8]) -> Vec<u8> {
+        let mut writer = BitWriter::new(Vec::new());
+        write_block(&mut writer, instructions, bytes, true, true).unwrap();
+        writer.align().unwrap();
+        std::mem::take(writer.get_mut())
+    }
+
+    #[test]
+    fn test_round_trip() -> InflateResult<()> {
+        let data = test_data(20_000);
+        let instructions = optimal_parse(&data, 0);
+        assert_eq!(inflate(&encode(&instructions, &data))?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_smaller_than_lazy_matching() {
+        let data = test_data(20_000);
+        let lazy = find_matches(
+            &data,
+            MatchParams {
+                parsing: Parsing::Lazy,
+                max_chain: 4096,
+                good_length: 32,
+                nice_length: 258,
+                max_lazy: 258,
+                min_length: MIN_MATCH,
+            },
+        );
+        let optimal = optimal_parse(&data, 0);
+        assert!(block_cost(&optimal) < block_cost(&lazy));
+        assert!(encode(&optimal, &data).len() < encode(&lazy, &data).len());
+    }
+
+    #[test]
+    fn test_matches_into_window() {
+        let window = test_data(1000);
+        let data = [window.as_slice(), &window].concat();
+        let instructions = optimal_parse(&data, window.len());
+        // The block is a copy of the window.
+        assert!(instructions.len() <= window.len().div_ceil(MAX_MATCH));
+        assert!(
+            instructions
+                .iter()
+                .all(|instruction| matches!(instruction, Instruction::BackReference { .. }))
+        );
+    }
+
+    #[test]
+    fn test_window_limit() -> InflateResult<()> {
+        // The last 50 bytes repeat the first 50, but further back than the
+        // window.
+        let noise = test_data(WINDOW_SIZE + 100);
+        let data = [&noise[..50], &noise[60..], &noise[..50]].concat();
+        let instructions = optimal_parse(&data, 0);
+        for instruction in &instructions {
+            if let Instruction::BackReference { distance, .. } = *instruction {
+                assert!(distance as usize <= WINDOW_SIZE);
+            }
+        }
+        assert_eq!(inflate(&encode(&instructions, &data))?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_repeated_byte() {
+        let data = vec![7u8; 10_000];
+        let instructions = optimal_parse(&data, 0);
+        assert_eq!(instructions[0], Instruction::Literal(7));
+        assert!(instructions.len() <= 1 + 10_000usize.div_ceil(MAX_MATCH));
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(optimal_parse(b"", 0), vec![]);
+        assert_eq!(optimal_parse(b"abc", 3), vec![]);
+    }
+}
@@ -0,0 +1,123 @@
+use crate::error::{InflateError, InflateResult};
+
+// How the decoder responds when input deviates from the format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    // Fail with the deviation.
+    Error,
+    // Record the deviation in Inflator::warnings() and carry on.
+    Warning,
+    // Carry on without recording anything.
+    Ignore,
+}
+
+impl Policy {
+    // Fail with, record or drop `deviation` according to the policy.
+    pub(crate) fn apply(
+        self,
+        deviation: InflateError,
+        warnings: &mut Vec<InflateError>,
+    ) -> InflateResult<()> {
+        match self {
+            Policy::Error => return Err(deviation),
+            Policy::Warning => warnings.push(deviation),
+            Policy::Ignore => {}
+        }
+        Ok(())
+    }
+}
+
+// Tolerance of Inflator for deviations that don't stop the data from being
+// decoded. The default fails on a bad header or trailer and accepts the rest,
+// like zlib.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InflateOptions {
+    // FCHECK doesn't make the header a multiple of 31: FlagCheckMismatch.
+    pub flag_check: Policy,
+    // Input ends before the Adler-32 trailer is complete: TruncatedTrailer.
+    // The checksum is only verified if the whole trailer is present.
+    pub truncated_trailer: Policy,
+    // Input continues after the trailer: TrailingData. Checking reads one
+    // byte past the end of the stream.
+    pub trailing_data: Policy,
+    // A dynamic block declares length codes 286 and 287, which can't occur
+    // in valid data: TooManyLengthCodes.
+    pub excess_length_codes: Policy,
+    // A dynamic block has no distance codes, which RFC 1951 allows for
+    // blocks of literals but some decoders reject: NoDistanceCodes.
+    pub empty_distance_code: Policy,
+    // A back-reference reaches beyond the window declared in the header:
+    // DistanceBeyondWindow, as zlib built with INFLATE_STRICT reports. Only
+    // references within the history kept can still be decoded, see
+    // full_window.
+    pub distance_beyond_window: Policy,
+    // Keep a full 32 KiB history, like zlib, so back-references beyond the
+    // declared window can still be decoded. Otherwise only the declared
+    // window is kept, saving memory for small windows.
+    pub full_window: bool,
+}
+
+impl InflateOptions {
+    // Fail on every deviation.
+    pub fn strict() -> Self {
+        Self::with_policy(Policy::Error)
+    }
+
+    // Record every deviation as a warning.
+    pub fn validating() -> Self {
+        Self::with_policy(Policy::Warning)
+    }
+
+    // Accept every deviation that still allows the data to be decoded.
+    pub fn lenient() -> Self {
+        Self::with_policy(Policy::Ignore)
+    }
+
+    fn with_policy(policy: Policy) -> Self {
+        Self {
+            flag_check: policy,
+            truncated_trailer: policy,
+            trailing_data: policy,
+            excess_length_codes: policy,
+            empty_distance_code: policy,
+            distance_beyond_window: policy,
+            full_window: false,
+        }
+    }
+}
+
+impl Default for InflateOptions {
+    fn default() -> Self {
+        Self {
+            flag_check: Policy::Error,
+            truncated_trailer: Policy::Error,
+            ..Self::lenient()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::assert_matches;
+
+    #[test]
+    fn test_apply() {
+        let mut warnings = Vec::new();
+        assert_matches!(
+            Policy::Error.apply(InflateError::NoDistanceCodes, &mut warnings),
+            Err(InflateError::NoDistanceCodes)
+        );
+        assert!(warnings.is_empty());
+        assert_matches!(
+            Policy::Ignore.apply(InflateError::NoDistanceCodes, &mut warnings),
+            Ok(())
+        );
+        assert!(warnings.is_empty());
+        assert_matches!(
+            Policy::Warning.apply(InflateError::NoDistanceCodes, &mut warnings),
+            Ok(())
+        );
+        assert_matches!(warnings[..], [InflateError::NoDistanceCodes]);
+    }
+}
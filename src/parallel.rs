@@ -0,0 +1,375 @@
+use crate::deflate::{Compression, Deflater, Flush};
+use crate::format::{Format, Framing};
+use crate::match_finder::WINDOW_SIZE;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+// Default amount of input compressed by each job.
+pub const CHUNK_SIZE: usize = 128 * 1024;
+
+// A piece of input to compress, along with the input that precedes it.
+#[derive(Debug)]
+struct Job {
+    index: usize,
+    // Up to a window of the input before `data`, for back-references across
+    // the chunk boundary.
+    dictionary: Vec<u8>,
+    data: Vec<u8>,
+    is_last: bool,
+}
+
+// A compressed chunk and the checksum of its uncompressed data.
+#[derive(Debug)]
+struct Chunk {
+    index: usize,
+    output: io::Result<Vec<u8>>,
+    framing: Framing,
+    length: usize,
+}
+
+// Compress a chunk to a sequence of blocks that ends on a byte boundary, so
+// that chunks can be concatenated. Only the last chunk has a final block.
+fn compress_chunk(job: &Job, compression: Compression) -> io::Result<Vec<u8>> {
+    let mut deflater = Deflater::new(compression);
+    deflater.set_dictionary(&job.dictionary);
+    deflater.write(&job.data)?;
+    deflater.flush(if job.is_last {
+        Flush::Finish
+    } else {
+        Flush::Sync
+    })?;
+    Ok(deflater.take_output())
+}
+
+fn run_worker(
+    jobs: &Mutex<Receiver<Job>>,
+    results: &Sender<Chunk>,
+    format: Format,
+    compression: Compression,
+) {
+    loop {
+        // The lock is released before compressing, so that other workers can
+        // take the next job meanwhile.
+        let job = match jobs.lock().unwrap().recv() {
+            Ok(job) => job,
+            // The encoder has been dropped.
+            Err(_) => return,
+        };
+        let mut framing = Framing::new(format);
+        framing.update(&job.data);
+        let chunk = Chunk {
+            index: job.index,
+            output: compress_chunk(&job, compression),
+            framing,
+            length: job.data.len(),
+        };
+        if results.send(chunk).is_err() {
+            return;
+        }
+    }
+}
+
+// Compresses data written to it on several threads, in the manner of pigz.
+// The input is cut into chunks that are compressed independently, each
+// primed with the 32 KiB of input before it, so the ratio stays close to
+// that of a single thread. The chunks end in sync flush markers and are
+// joined into a single zlib, gzip or raw DEFLATE stream, with the chunks'
+// checksums combined for the trailer.
+//
+// Up to two chunks per thread are buffered in memory. As with ZlibEncoder,
+// the stream is finished when the encoder is dropped, ignoring errors.
+#[derive(Debug)]
+pub struct ParallelEncoder<W: Write> {
+    // None once `finish()` has returned the writer.
+    output: Option<W>,
+    compression: Compression,
+    framing: Framing,
+    header_written: bool,
+    chunk_size: usize,
+    // Input not yet handed to a worker.
+    input: Vec<u8>,
+    // The last window of the input handed to workers.
+    window: Vec<u8>,
+    // None once the workers have been told to stop.
+    jobs: Option<Sender<Job>>,
+    results: Receiver<Chunk>,
+    workers: Vec<JoinHandle<()>>,
+    // Index of the next job to hand out, and of the next chunk to write.
+    next_job: usize,
+    next_chunk: usize,
+    // Chunks that finished ahead of an earlier one.
+    completed: BTreeMap<usize, Chunk>,
+    // Whether the last chunk has been handed out, and the trailer written.
+    input_finished: bool,
+    finished: bool,
+}
+
+impl<W: Write> ParallelEncoder<W> {
+    // Compress into `format` on `threads` worker threads, or on as many as
+    // the system has cores if `threads` is 0.
+    pub fn new(output: W, format: Format, compression: Compression, threads: usize) -> Self {
+        let threads = if threads == 0 {
+            thread::available_parallelism().map_or(1, |n| n.get())
+        } else {
+            threads
+        };
+        let (jobs, job_receiver) = mpsc::channel::<Job>();
+        let (result_sender, results) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let workers = (0..threads)
+            .map(|_| {
+                let jobs = Arc::clone(&job_receiver);
+                let results = result_sender.clone();
+                thread::spawn(move || run_worker(&jobs, &results, format, compression))
+            })
+            .collect();
+        Self {
+            output: Some(output),
+            compression,
+            framing: Framing::new(format),
+            header_written: false,
+            chunk_size: CHUNK_SIZE,
+            input: Vec::new(),
+            window: Vec::new(),
+            jobs: Some(jobs),
+            results,
+            workers,
+            next_job: 0,
+            next_chunk: 0,
+            completed: BTreeMap::new(),
+            input_finished: false,
+            finished: false,
+        }
+    }
+
+    // Amount of input per job, CHUNK_SIZE by default. Smaller chunks spread
+    // the work more evenly, larger ones lose less compression at the
+    // boundaries. Applies to chunks started after the call.
+    pub fn set_chunk_size(&mut self, size: usize) {
+        assert!(size > 0, "Chunk size must be positive");
+        self.chunk_size = size;
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.output.as_ref().unwrap()
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        self.output.as_mut().unwrap()
+    }
+
+    // Compress the remaining input, wait for all chunks, and write the
+    // trailer, keeping the underlying writer. Calling this again once it has
+    // succeeded does nothing.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        if !self.input_finished {
+            let data = std::mem::take(&mut self.input);
+            self.submit(data, true)?;
+            self.input_finished = true;
+        }
+        while self.next_chunk < self.next_job {
+            self.write_next_chunk()?;
+        }
+        let trailer = self.framing.trailer();
+        self.output.as_mut().unwrap().write_all(&trailer)?;
+        self.finished = true;
+        Ok(())
+    }
+
+    // Write the final block and the trailer, and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.output.take().unwrap())
+    }
+
+    // Hand a chunk to the workers, first waiting for earlier chunks if too
+    // many are in flight.
+    fn submit(&mut self, data: Vec<u8>, is_last: bool) -> io::Result<()> {
+        while self.next_job - self.next_chunk >= 2 * self.workers.len() {
+            self.write_next_chunk()?;
+        }
+        let job = Job {
+            index: self.next_job,
+            dictionary: self.window.clone(),
+            data,
+            is_last,
+        };
+        self.window.extend_from_slice(&job.data);
+        let excess = self.window.len().saturating_sub(WINDOW_SIZE);
+        self.window.drain(..excess);
+        self.jobs
+            .as_ref()
+            .unwrap()
+            .send(job)
+            .map_err(|_| io::Error::other("Compression worker stopped"))?;
+        self.next_job += 1;
+        Ok(())
+    }
+
+    // Wait for the next chunk in order and pass it to the underlying writer.
+    fn write_next_chunk(&mut self) -> io::Result<()> {
+        let chunk = loop {
+            if let Some(chunk) = self.completed.remove(&self.next_chunk) {
+                break chunk;
+            }
+            let chunk = self
+                .results
+                .recv()
+                .map_err(|_| io::Error::other("Compression worker stopped"))?;
+            self.completed.insert(chunk.index, chunk);
+        };
+        let output = chunk.output?;
+        if !self.header_written {
+            let header = self.framing.header(self.compression);
+            self.output.as_mut().unwrap().write_all(&header)?;
+            self.header_written = true;
+        }
+        self.output.as_mut().unwrap().write_all(&output)?;
+        self.framing.combine(&chunk.framing, chunk.length);
+        self.next_chunk += 1;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ParallelEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.input_finished {
+            return Err(io::Error::other("Write after the stream was finished"));
+        }
+        self.input.extend_from_slice(buf);
+        // A full chunk is only handed out once more input follows it, since
+        // the last chunk has to end the stream.
+        while self.input.len() > self.chunk_size {
+            let rest = self.input.split_off(self.chunk_size);
+            let data = std::mem::replace(&mut self.input, rest);
+            self.submit(data, false)?;
+        }
+        Ok(buf.len())
+    }
+
+    // Passes on the chunks completed so far. Unlike ZlibEncoder, buffered
+    // input isn't flushed, as that would end a chunk early.
+    fn flush(&mut self) -> io::Result<()> {
+        while let Ok(chunk) = self.results.try_recv() {
+            self.completed.insert(chunk.index, chunk);
+        }
+        while self.completed.contains_key(&self.next_chunk) {
+            self.write_next_chunk()?;
+        }
+        self.output.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write> Drop for ParallelEncoder<W> {
+    fn drop(&mut self) {
+        if self.output.is_some() {
+            let _ = self.try_finish();
+        }
+        // Closing the job channel stops the workers.
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::InflateResult;
+    use crate::inflate::RawInflator;
+    use crate::{Inflator, ZlibEncoder};
+
+    // Text-like data with repetitions both within and across chunks.
+    fn sample_data(length: usize) -> Vec<u8> {
+        let words = ["alpha ", "beta ", "gamma ", "delta ", "epsilon\n"];
+        let mut state = 7u32;
+        let mut data = Vec::new();
+        while data.len() < length {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            data.extend_from_slice(words[(state >> 16) as usize % words.len()].as_bytes());
+        }
+        data.truncate(length);
+        data
+    }
+
+    fn compress(data: &[u8], format: Format, chunk_size: usize) -> io::Result<Vec<u8>> {
+        let mut encoder = ParallelEncoder::new(Vec::new(), format, Compression::default(), 4);
+        encoder.set_chunk_size(chunk_size);
+        // Uneven writes, so that chunks span several of them.
+        for piece in data.chunks(10_000) {
+            encoder.write_all(piece)?;
+        }
+        encoder.finish()
+    }
+
+    #[test]
+    fn test_zlib_round_trip() -> InflateResult<()> {
+        let data = sample_data(300_000);
+        for chunk_size in [1000, 40_000, CHUNK_SIZE] {
+            let compressed = compress(&data, Format::Zlib, chunk_size)?;
+            let mut inflator = Inflator::try_new(compressed.as_slice())?;
+            let mut out = Vec::new();
+            while !inflator.is_finished() {
+                out.extend(inflator.next_block()?);
+            }
+            assert_eq!(out, data);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzip() -> InflateResult<()> {
+        let data = sample_data(100_000);
+        let compressed = compress(&data, Format::Gzip, 16 * 1024)?;
+        assert_eq!(compressed[..4], [0x1f, 0x8b, 8, 0]);
+        let (body, trailer) = compressed[10..].split_at(compressed.len() - 18);
+        let mut out = Vec::new();
+        RawInflator::new(body).read_to_end(&mut out)?;
+        assert_eq!(out, data);
+        let mut expected = Framing::new(Format::Gzip);
+        expected.update(&data);
+        assert_eq!(trailer, expected.trailer());
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw() -> InflateResult<()> {
+        let data = sample_data(50_000);
+        let compressed = compress(&data, Format::Raw, 8192)?;
+        let mut out = Vec::new();
+        RawInflator::new(compressed.as_slice()).read_to_end(&mut out)?;
+        assert_eq!(out, data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_input() -> io::Result<()> {
+        let serial = ZlibEncoder::new(Vec::new(), Compression::default()).finish()?;
+        assert_eq!(compress(b"", Format::Zlib, CHUNK_SIZE)?, serial);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ratio_close_to_serial() -> io::Result<()> {
+        let data = sample_data(500_000);
+        let mut serial = ZlibEncoder::new(Vec::new(), Compression::default());
+        serial.write_all(&data)?;
+        let serial = serial.finish()?;
+        let parallel = compress(&data, Format::Zlib, 64 * 1024)?;
+        // Each boundary costs a sync marker and fresh Huffman codes.
+        assert!(
+            parallel.len() < serial.len() * 102 / 100,
+            "{} vs {}",
+            parallel.len(),
+            serial.len()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,757 @@
+// Recovering what can be recovered from a corrupted compressed stream, like
+// gzip's --recover tools. Decoding stops at the first error as usual, but
+// then the input is scanned bit by bit for a place where decoding can resume:
+//
+// - A stored block, whose LEN and NLEN fields are complements of each other.
+//   Empty stored blocks are the markers left by sync and full flushes.
+// - A block header that decodes to a complete block, followed by enough
+//   further complete blocks, or by the end of the stream.
+//
+// Data decoded after a resync can't see what came before, so back-references
+// reaching into the lost window produce a placeholder byte instead. Blocks
+// that fail to decode are dropped, except at the end of truncated input, where
+// what could be decoded is kept.
+
+use crate::adler32::adler32;
+use crate::bit_reader::{BitRead, BitReader};
+use crate::block_decoder::BlockDecoder;
+use crate::crc32::crc32;
+use crate::error::{InflateError, InflateResult};
+use crate::format::Format;
+use crate::gzip::GzHeader;
+use crate::header::StreamHeader;
+use crate::join::skip_block;
+use crate::lz77::{History, Instruction};
+use crate::match_finder::WINDOW_SIZE;
+use std::io::{self, Cursor, Read};
+use std::ops::Range;
+
+// Number of consecutive complete blocks, starting with a candidate block
+// header, that confirm it. Random bits decode to a complete fixed block
+// surprisingly often, so a single block proves little.
+const CONFIRM_BLOCKS: usize = 4;
+
+// Number of complete blocks that are enough to trust a candidate when the
+// input ends before CONFIRM_BLOCKS.
+const TRUNCATED_CONFIRM_BLOCKS: usize = 2;
+
+// How decoding of a segment started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anchor {
+    // The start of a stream, with its window known.
+    StreamStart,
+    // A stored block with matching LEN and NLEN.
+    StoredBlock,
+    // An empty stored block, as written by a sync or full flush.
+    SyncMarker,
+    // A Huffman-coded block header, confirmed by decoding ahead.
+    BlockHeader,
+}
+
+// How likely a segment is to be real data rather than a misreading of
+// corrupted input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    // Confirmed by a couple of blocks before the input ran out.
+    Low,
+    // Confirmed by decoding several blocks ahead, or up to the end of the
+    // stream.
+    Medium,
+    // The start of a stream, or a stored block's 32-bit length check.
+    High,
+}
+
+// A run of input that decoded without errors.
+#[derive(Debug)]
+pub struct Segment {
+    // Bit offsets in the input. For segments resuming at a stored block, this
+    // starts at its LEN field, as the exact position of its header is unknown.
+    pub input: Range<u64>,
+    // Byte offsets in the recovered data.
+    pub output: Range<usize>,
+    pub anchor: Anchor,
+    pub confidence: Confidence,
+    // Number of bytes whose value was lost with the window, and replaced with
+    // the placeholder.
+    pub placeholders: usize,
+    // What ended the segment, if it didn't end with its stream. A checksum
+    // mismatch is reported on the segment that ends the stream.
+    pub error: Option<InflateError>,
+}
+
+// The outcome of salvaging a stream.
+#[derive(Debug)]
+pub struct Salvage {
+    pub data: Vec<u8>,
+    pub segments: Vec<Segment>,
+    // Bit ranges of the input that couldn't be decoded, including any data
+    // after the stream.
+    pub corrupted: Vec<Range<u64>>,
+}
+
+impl Salvage {
+    // Whether everything decoded normally, as if the stream wasn't corrupted.
+    pub fn is_intact(&self) -> bool {
+        self.corrupted.is_empty()
+            && self.segments.iter().all(|segment| {
+                segment.anchor == Anchor::StreamStart
+                    && segment.placeholders == 0
+                    && segment.error.is_none()
+            })
+    }
+}
+
+// Where to resume decoding.
+#[derive(Debug, Clone, Copy)]
+enum Resume {
+    // At a block header.
+    Header(u64),
+    // At the LEN field of a stored block whose header was lost.
+    StoredLength { position: u64, is_final: bool },
+}
+
+impl Resume {
+    fn position(&self) -> u64 {
+        match *self {
+            Resume::Header(position) => position,
+            Resume::StoredLength { position, .. } => position,
+        }
+    }
+}
+
+// How a segment ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SegmentEnd {
+    // With the final block of its stream.
+    Stream,
+    // With the end of the input.
+    Truncated,
+    // With a block that failed to decode.
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Resync {
+    resume: Resume,
+    anchor: Anchor,
+    confidence: Confidence,
+}
+
+// Recover as much as possible of the compressed stream in `data`, writing
+// `placeholder` for bytes that can't be recovered. Gzip input may contain
+// several members. Never fails: errors are reported in the segments.
+//
+// Scanning corrupted input tries to decode at every bit, so it is slow on
+// large corrupted regions.
+pub fn salvage(data: &[u8], format: Format, placeholder: u8) -> Salvage {
+    let mut salvager = Salvager {
+        data,
+        format,
+        placeholder,
+        history: History::new(WINDOW_SIZE),
+        unknown: History::new(WINDOW_SIZE),
+        flags: Vec::new(),
+        salvage: Salvage {
+            data: Vec::new(),
+            segments: Vec::new(),
+            corrupted: Vec::new(),
+        },
+    };
+    salvager.run();
+    salvager.salvage
+}
+
+struct Salvager<'a> {
+    data: &'a [u8],
+    format: Format,
+    placeholder: u8,
+    history: History,
+    // 1 for each byte of the history that is a placeholder.
+    unknown: History,
+    // Scratch space for the flags of copied bytes.
+    flags: Vec<u8>,
+    salvage: Salvage,
+}
+
+impl Salvager<'_> {
+    fn input_bits(&self) -> u64 {
+        self.data.len() as u64 * 8
+    }
+
+    fn bit_at(&self, position: u64) -> bool {
+        (self.data[(position / 8) as usize] >> (position % 8)) & 1 != 0
+    }
+
+    fn run(&mut self) {
+        let mut position = 0;
+        while position < self.input_bits() {
+            let member_start = (position / 8) as usize;
+            let resync = match self.read_header(member_start) {
+                Ok((deflate_start, has_dictionary)) => {
+                    self.reset_window(!has_dictionary);
+                    Resync {
+                        resume: Resume::Header(deflate_start as u64 * 8),
+                        anchor: Anchor::StreamStart,
+                        confidence: Confidence::High,
+                    }
+                }
+                Err(_) => match self.find_resync(position) {
+                    Some(resync) => {
+                        self.mark_corrupted(position..resync.resume.position());
+                        self.reset_window(false);
+                        resync
+                    }
+                    None => {
+                        self.mark_corrupted(position..self.input_bits());
+                        return;
+                    }
+                },
+            };
+            let Some(end) = self.decode_stream(resync) else {
+                return;
+            };
+            position = end;
+            if self.format != Format::Gzip && position < self.input_bits() {
+                self.mark_corrupted(position..self.input_bits());
+                return;
+            }
+        }
+    }
+
+    // Parse the container header at `start`, returning where the DEFLATE
+    // stream starts and whether it needs a preset dictionary.
+    fn read_header(&self, start: usize) -> InflateResult<(usize, bool)> {
+        let mut input = Cursor::new(&self.data[start..]);
+        let mut has_dictionary = false;
+        match self.format {
+            Format::Raw => {}
+            Format::Zlib => {
+                let mut header = [0u8; 2];
+                input.read_exact(&mut header)?;
+                if StreamHeader::try_from(&header)?.flags.preset_dictionary {
+                    input.read_exact(&mut [0u8; 4])?;
+                    has_dictionary = true;
+                }
+            }
+            Format::Gzip => {
+                GzHeader::read_from(&mut input)?;
+            }
+        }
+        Ok((start + input.position() as usize, has_dictionary))
+    }
+
+    // Start over with an empty window, or with one full of placeholders if
+    // the data before is unknown.
+    fn reset_window(&mut self, known: bool) {
+        self.history = History::new(WINDOW_SIZE);
+        self.unknown = History::new(WINDOW_SIZE);
+        if !known {
+            self.history.extend(&[self.placeholder; WINDOW_SIZE]);
+            self.unknown.extend(&[1; WINDOW_SIZE]);
+        }
+    }
+
+    fn mark_corrupted(&mut self, range: Range<u64>) {
+        if range.is_empty() {
+            return;
+        }
+        match self.salvage.corrupted.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => self.salvage.corrupted.push(range),
+        }
+    }
+
+    // Decode a stream from `resync`, resyncing after errors, up to the end of
+    // its trailer. Returns the trailer's end, or None if the input ran out.
+    fn decode_stream(&mut self, mut resync: Resync) -> Option<u64> {
+        let stream_start = self.salvage.data.len();
+        let first_segment = self.salvage.segments.len();
+        loop {
+            let (end, segment_end) = self.decode_segment(resync);
+            match segment_end {
+                SegmentEnd::Stream => return self.check_trailer(end, stream_start, first_segment),
+                SegmentEnd::Truncated => return None,
+                SegmentEnd::Failed => {}
+            }
+            resync = match self.find_resync(end + 1) {
+                Some(resync) => resync,
+                None => {
+                    self.mark_corrupted(end..self.input_bits());
+                    return None;
+                }
+            };
+            self.mark_corrupted(end..resync.resume.position());
+            self.reset_window(false);
+        }
+    }
+
+    // Decode blocks from `resync` until the final block or an error, and
+    // record the segment. Returns where the last complete block ended, or for
+    // truncated input, the end of the input, and how the segment ended.
+    fn decode_segment(&mut self, resync: Resync) -> (u64, SegmentEnd) {
+        let output_start = self.salvage.data.len();
+        let start = resync.resume.position();
+        let base = start / 8 * 8;
+        let mut input = BitReader::new(&self.data[(start / 8) as usize..]);
+        input.read_bits::<u8>((start % 8) as u8).unwrap();
+        let mut placeholders = 0;
+        let mut block_end = start;
+        let mut result = match resync.resume {
+            Resume::Header(_) => Ok(false),
+            Resume::StoredLength { is_final, .. } => {
+                self.decode_stored(&mut input).map(|()| is_final)
+            }
+        };
+        loop {
+            match result {
+                Ok(true) => break,
+                Ok(false) => {
+                    block_end = base + input.bit_position();
+                    let output_length = self.salvage.data.len();
+                    result = self.decode_block(&mut input, &mut placeholders);
+                    if matches!(&result, Err(error) if !is_eof(error)) {
+                        self.salvage.data.truncate(output_length);
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+        let error = result.err();
+        let (end, segment_end) = match &error {
+            None => (base + input.bit_position(), SegmentEnd::Stream),
+            Some(error) if is_eof(error) => (self.input_bits(), SegmentEnd::Truncated),
+            Some(_) => (block_end, SegmentEnd::Failed),
+        };
+        if end > start || self.salvage.data.len() > output_start {
+            self.salvage.segments.push(Segment {
+                input: start..end,
+                output: output_start..self.salvage.data.len(),
+                anchor: resync.anchor,
+                confidence: resync.confidence,
+                placeholders,
+                error,
+            });
+        }
+        (end, segment_end)
+    }
+
+    // Decode the next block, returning whether it is the final block.
+    // Placeholder bytes are only counted for complete blocks.
+    fn decode_block(
+        &mut self,
+        input: &mut BitReader<&[u8]>,
+        placeholders: &mut usize,
+    ) -> InflateResult<bool> {
+        let is_final = input.read_bit()?;
+        let block_type = input.read_bits::<u8>(2)?;
+        let mut block_placeholders = 0;
+        match block_type {
+            0 => self.decode_stored(input)?,
+            1 => {
+                let decoder = BlockDecoder::new_fixed(input);
+                self.decode_huffman(decoder, &mut block_placeholders)?
+            }
+            2 => {
+                let decoder = BlockDecoder::new_dynamic(input)?;
+                self.decode_huffman(decoder, &mut block_placeholders)?
+            }
+            _ => return Err(InflateError::InvalidBlockType(block_type)),
+        }
+        *placeholders += block_placeholders;
+        Ok(is_final)
+    }
+
+    // Decode a stored block from its LEN field on. Truncated data is kept.
+    fn decode_stored(&mut self, input: &mut BitReader<&[u8]>) -> InflateResult<()> {
+        let length = input.read_u16()?;
+        let inverse_length = input.read_u16()?;
+        if inverse_length != !length {
+            return Err(InflateError::LengthComplementMismatch(
+                length,
+                inverse_length,
+            ));
+        }
+        let mut data = Vec::new();
+        input.take(length as u64).read_to_end(&mut data)?;
+        self.history.extend(&data);
+        self.unknown.extend(&vec![0; data.len()]);
+        self.salvage.data.extend(&data);
+        if data.len() < length as usize {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        Ok(())
+    }
+
+    fn decode_huffman(
+        &mut self,
+        mut decoder: BlockDecoder<BitReader<&[u8]>>,
+        placeholders: &mut usize,
+    ) -> InflateResult<()> {
+        loop {
+            match decoder.next()? {
+                Instruction::Literal(byte) => {
+                    self.history.append(byte);
+                    self.unknown.append(0);
+                    self.salvage.data.push(byte);
+                }
+                Instruction::BackReference { length, distance } => {
+                    if distance as usize > self.history.len() {
+                        return Err(InflateError::DistanceTooFarBack(
+                            distance,
+                            self.history.len(),
+                        ));
+                    }
+                    let (length, distance) = (length as usize, distance as usize);
+                    self.history
+                        .copy_back_reference(length, distance, &mut self.salvage.data);
+                    self.flags.clear();
+                    self.unknown
+                        .copy_back_reference(length, distance, &mut self.flags);
+                    *placeholders += self.flags.iter().filter(|&&flag| flag != 0).count();
+                }
+                Instruction::EndOfBlock => return Ok(()),
+            }
+        }
+    }
+
+    // Check the trailer after a stream ending at `end`, if the whole stream
+    // was recovered. Returns where the trailer ends, or None if it is
+    // missing.
+    fn check_trailer(
+        &mut self,
+        end: u64,
+        stream_start: usize,
+        first_segment: usize,
+    ) -> Option<u64> {
+        let trailer_start = end.div_ceil(8) as usize;
+        let trailer_end = trailer_start + self.format.trailer_size();
+        let segments = &mut self.salvage.segments[first_segment..];
+        let Some(trailer) = self.data.get(trailer_start..trailer_end) else {
+            if let Some(last) = segments.last_mut() {
+                last.error = Some(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            self.mark_corrupted(trailer_start as u64 * 8..self.input_bits());
+            return None;
+        };
+        if let [segment] = segments
+            && segment.anchor == Anchor::StreamStart
+            && segment.placeholders == 0
+        {
+            let data = &self.salvage.data[stream_start..];
+            segment.error = match self.format {
+                Format::Raw => None,
+                Format::Zlib => {
+                    let expected = u32::from_be_bytes(trailer.try_into().unwrap());
+                    let actual = adler32(data);
+                    (expected != actual).then_some(InflateError::ChecksumMismatch(expected, actual))
+                }
+                Format::Gzip => {
+                    let expected = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+                    let actual = crc32(data);
+                    let expected_size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+                    if expected != actual {
+                        Some(InflateError::ChecksumMismatch(expected, actual))
+                    } else if expected_size != data.len() as u32 {
+                        Some(InflateError::SizeMismatch(expected_size, data.len() as u32))
+                    } else {
+                        None
+                    }
+                }
+            };
+        }
+        Some(trailer_end as u64 * 8)
+    }
+
+    // Whether a stream whose DEFLATE data ends at byte `end` would be
+    // followed by exactly its trailer, or by its trailer and another gzip
+    // member.
+    fn ends_stream(&self, end: usize) -> bool {
+        let trailer_end = end + self.format.trailer_size();
+        trailer_end == self.data.len()
+            || (self.format == Format::Gzip
+                && trailer_end < self.data.len()
+                && GzHeader::read_from(&mut &self.data[trailer_end..]).is_ok())
+    }
+
+    // Find the first place at or after bit `from` where decoding can resume.
+    fn find_resync(&self, from: u64) -> Option<Resync> {
+        (from..self.input_bits()).find_map(|position| {
+            // A stored block's LEN field follows its header and padding, so
+            // check for one where this would be its latest header position.
+            if (position + 3) % 8 == 0
+                && let Some(resync) = self.stored_block_at((position + 3) as usize / 8)
+            {
+                return Some(resync);
+            }
+            self.block_header_at(position)
+        })
+    }
+
+    // A stored block whose LEN field is at byte `start`. Its BFINAL bit can't
+    // be told apart from the bits before it, so the block is taken to be
+    // final if the stream would end after it.
+    fn stored_block_at(&self, start: usize) -> Option<Resync> {
+        // The byte before holds the block type and padding, both zero, in
+        // its top two bits at least.
+        let previous = *self.data.get(start.checked_sub(1)?)?;
+        let fields = self.data.get(start..start + 4)?;
+        let length = u16::from_le_bytes([fields[0], fields[1]]);
+        let inverse_length = u16::from_le_bytes([fields[2], fields[3]]);
+        let end = start + 4 + length as usize;
+        if previous & 0xc0 != 0 || inverse_length != !length || end > self.data.len() {
+            return None;
+        }
+        Some(Resync {
+            resume: Resume::StoredLength {
+                position: start as u64 * 8,
+                is_final: self.ends_stream(end),
+            },
+            anchor: if length == 0 {
+                Anchor::SyncMarker
+            } else {
+                Anchor::StoredBlock
+            },
+            confidence: Confidence::High,
+        })
+    }
+
+    // A Huffman-coded block header at bit `position`, if decoding ahead from
+    // it confirms it.
+    fn block_header_at(&self, position: u64) -> Option<Resync> {
+        if position + 3 > self.input_bits()
+            || self.bit_at(position + 1) == self.bit_at(position + 2)
+        {
+            // Not a fixed or dynamic block.
+            return None;
+        }
+        let mut input = BitReader::new(&self.data[(position / 8) as usize..]);
+        input.read_bits::<u8>((position % 8) as u8).ok()?;
+        // Back-references may reach into the lost window.
+        let mut length = WINDOW_SIZE as u64;
+        let mut confidence = Confidence::Medium;
+        for blocks in 0..CONFIRM_BLOCKS {
+            match skip_block(&mut input, &mut length) {
+                Ok(true) => {
+                    let end = (position / 8 * 8 + input.bit_position()).div_ceil(8);
+                    if !self.ends_stream(end as usize) {
+                        return None;
+                    }
+                    break;
+                }
+                Ok(false) => {}
+                Err(error) if is_eof(&error) && blocks >= TRUNCATED_CONFIRM_BLOCKS => {
+                    confidence = Confidence::Low;
+                    break;
+                }
+                Err(_) => return None,
+            }
+        }
+        Some(Resync {
+            resume: Resume::Header(position),
+            anchor: Anchor::BlockHeader,
+            confidence,
+        })
+    }
+}
+
+fn is_eof(error: &InflateError) -> bool {
+    matches!(error, InflateError::IoError(e) if e.kind() == io::ErrorKind::UnexpectedEof)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Compression, Flush, GzEncoder, ZlibEncoder, compress, gzip_compress, raw_compress,
+    };
+    use std::assert_matches;
+    use std::io::Write;
+
+    // Text without zero bytes, so that zero placeholders stand out.
+    fn text(length: usize) -> Vec<u8> {
+        let words: [&[u8]; 8] = [
+            b"salvage ",
+            b"what ",
+            b"you ",
+            b"can ",
+            b"from ",
+            b"the ",
+            b"broken ",
+            b"backup\n",
+        ];
+        let mut state: u32 = 7;
+        let mut data = Vec::new();
+        while data.len() < length {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            let r = (state >> 16) as usize;
+            if r.is_multiple_of(5) {
+                data.extend(format!("{} ", r % 1000).bytes());
+            } else {
+                data.extend_from_slice(words[r % words.len()]);
+            }
+        }
+        data.truncate(length);
+        data
+    }
+
+    // Check that `recovered` matches the end of `original`, except for zero
+    // placeholders, and return the number of placeholders.
+    fn check_suffix(recovered: &[u8], original: &[u8]) -> usize {
+        let expected = &original[original.len() - recovered.len()..];
+        let mut placeholders = 0;
+        for (&actual, &expected) in recovered.iter().zip(expected) {
+            if actual == 0 {
+                placeholders += 1;
+            } else {
+                assert_eq!(actual, expected);
+            }
+        }
+        placeholders
+    }
+
+    #[test]
+    fn test_intact() {
+        let data = text(100_000);
+        let compressed = gzip_compress(&data, Compression::default()).repeat(2);
+        let salvage = salvage(&compressed, Format::Gzip, 0);
+        assert!(salvage.is_intact());
+        assert_eq!(salvage.data, data.repeat(2));
+        assert_eq!(salvage.segments.len(), 2);
+        assert_eq!(salvage.segments[1].output, data.len()..2 * data.len());
+        assert_eq!(salvage.segments[1].confidence, Confidence::High);
+    }
+
+    #[test]
+    fn test_resync_at_sync_marker() -> io::Result<()> {
+        let records: Vec<Vec<u8>> = (0..4).map(|i| text(10_000 + i)).collect();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        let mut flush_points = Vec::new();
+        for record in &records {
+            encoder.write_all(record)?;
+            encoder.flush_with(Flush::Sync)?;
+            flush_points.push(encoder.get_ref().len());
+        }
+        let mut compressed = encoder.finish()?;
+        // Wipe out the header of the second record's block. Damage inside a
+        // block tends to go unnoticed until the checksum is checked.
+        let damaged = flush_points[0] + 1;
+        compressed[damaged..damaged + 8].fill(0x55);
+
+        let salvage = salvage(&compressed, Format::Gzip, 0);
+        assert!(!salvage.is_intact());
+        assert_eq!(&salvage.data[..records[0].len()], records[0]);
+        let [corrupted] = salvage.corrupted.as_slice() else {
+            panic!("{:?}", salvage.corrupted);
+        };
+        assert!(corrupted.contains(&(damaged as u64 * 8)));
+        assert_eq!(corrupted.end, flush_points[1] as u64 * 8 - 32);
+        let [first, second] = salvage.segments.as_slice() else {
+            panic!("{:?}", salvage.segments);
+        };
+        assert_matches!(first.error, Some(_));
+        assert_eq!(second.anchor, Anchor::SyncMarker);
+        assert_eq!(second.confidence, Confidence::High);
+        assert_eq!(second.output.len(), records[2].len() + records[3].len());
+        let recovered = &salvage.data[second.output.clone()];
+        let placeholders = check_suffix(recovered, &records.concat());
+        assert_eq!(placeholders, second.placeholders);
+        // The checksum covers lost data, so it isn't checked.
+        assert_matches!(second.error, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resync_at_block_header() -> io::Result<()> {
+        // Partial flushes end each record's block with an empty fixed block,
+        // so there are no stored blocks to resync at.
+        let data = text(60_000);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        for record in data.chunks(3000) {
+            encoder.write_all(record)?;
+            encoder.flush_with(Flush::Partial)?;
+        }
+        let mut compressed = encoder.finish()?;
+        // Wipe out the header of the sixth record's block.
+        let mut input = BitReader::new(&compressed[2..]);
+        for _ in 0..10 {
+            skip_block(&mut input, &mut (WINDOW_SIZE as u64)).unwrap();
+        }
+        let damaged = 2 + (input.bit_position() / 8) as usize + 1;
+        compressed[damaged..damaged + 8].fill(0xaa);
+
+        let salvage = salvage(&compressed, Format::Zlib, 0);
+        let [corrupted] = salvage.corrupted.as_slice() else {
+            panic!("{:?}", salvage.corrupted);
+        };
+        assert!(corrupted.contains(&(damaged as u64 * 8)));
+        let [first, second] = salvage.segments.as_slice() else {
+            panic!("{:?}", salvage.segments);
+        };
+        assert_eq!(&salvage.data[first.output.clone()], &data[..5 * 3000]);
+        assert_eq!(second.anchor, Anchor::BlockHeader);
+        assert_eq!(second.confidence, Confidence::Medium);
+        assert_eq!(second.input.start, corrupted.end);
+        assert_eq!(second.output.len(), data.len() - 6 * 3000);
+        let placeholders = check_suffix(&salvage.data[second.output.clone()], &data);
+        assert_eq!(placeholders, second.placeholders);
+        assert!(placeholders > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupted_header() {
+        let data = text(1000);
+        let mut compressed = gzip_compress(&data, Compression::default());
+        compressed[0] = 0;
+        let salvage = salvage(&compressed, Format::Gzip, 0);
+        // The window starts empty anyway, so nothing is lost.
+        assert_eq!(salvage.data, data);
+        assert_eq!(salvage.corrupted, vec![0..10 * 8]);
+        assert_eq!(salvage.segments[0].anchor, Anchor::BlockHeader);
+        assert_eq!(salvage.segments[0].placeholders, 0);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let data = text(100_000);
+        let compressed = raw_compress(&data, Compression::default());
+        let truncated = &compressed[..compressed.len() * 2 / 3];
+        let salvage = salvage(truncated, Format::Raw, 0);
+        assert!(salvage.data.len() > data.len() / 2);
+        assert_eq!(salvage.data, data[..salvage.data.len()]);
+        assert!(salvage.corrupted.is_empty());
+        let [segment] = salvage.segments.as_slice() else {
+            panic!("{:?}", salvage.segments);
+        };
+        assert_eq!(segment.input.end, truncated.len() as u64 * 8);
+        assert_matches!(&segment.error, Some(e) if is_eof(e));
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let data = text(1000);
+        let mut compressed = gzip_compress(&data, Compression::none());
+        // A byte of a stored block's data.
+        compressed[100] ^= 1;
+        let salvage = salvage(&compressed, Format::Gzip, 0);
+        assert_eq!(salvage.data.len(), data.len());
+        assert!(!salvage.is_intact());
+        assert!(salvage.corrupted.is_empty());
+        assert_matches!(
+            salvage.segments[0].error,
+            Some(InflateError::ChecksumMismatch(..))
+        );
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        let data = text(1000);
+        let compressed = compress(&data, Compression::default());
+        let padded = [compressed.as_slice(), b"junk"].concat();
+        let salvage = salvage(&padded, Format::Zlib, 0);
+        assert_eq!(salvage.data, data);
+        assert_matches!(salvage.segments[0].error, None);
+        let end = compressed.len() as u64 * 8;
+        assert_eq!(salvage.corrupted, vec![end..end + 32]);
+    }
+}